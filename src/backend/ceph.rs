@@ -4,16 +4,17 @@ use std::fs::{
     create_dir, read_dir, read_link, read_to_string, remove_dir_all, symlink_metadata, File,
     OpenOptions,
 };
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::os::unix::{fs::symlink, io::AsRawFd};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
-use std::thread::*;
-use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::backend::selector::{device_matches, DeviceSelector};
+use crate::backend::util::{is_same_device, load_state, save_state};
 use crate::backend::Backend;
-use api::service::OpOutcome;
+use api::service::{BlockingPg, Capabilities, OpOutcome, Progress, SuspectDisk};
 
 use blkid::BlkId;
 use ceph::ceph::{connect_to_ceph, Rados};
@@ -23,6 +24,7 @@ use ceph::cmd::{pg_stat, PgStat};
 use ceph::CephVersion;
 use dirs::home_dir;
 use fstab::FsTab;
+use hashicorp_vault::client::VaultClient;
 use helpers::{error::*, host_information::Host};
 use hostname::get_hostname;
 use init_daemon::{detect_daemon, Daemon};
@@ -49,6 +51,20 @@ pub struct CephBackend {
     cluster_handle: Rados,
     config: CephConfig,
     version: CephVersion,
+    /// cephadm runs the osds in containers.  Adds and removals go through
+    /// ceph orch instead of ceph-volume and systemd
+    orchestrated: bool,
+    /// Names the cluster in logs.  ceph for the top level cluster
+    name: String,
+    fsid: String,
+    /// The new disks that go in this cluster
+    devices: Vec<DeviceSelector>,
+}
+
+/// Every Ceph cluster this host serves osds to.  A disk with an osd on it
+/// goes to the cluster with the osd's cluster fsid
+pub struct CephClusters {
+    clusters: Vec<CephBackend>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -117,6 +133,8 @@ struct OsdConfig {
     journal_path: Option<String>,
     /// optional RocksDB path, for blustore non-LVM deployment, should NOT be the same as journal_path   
     rdb_path: Option<String>,
+    /// Overrides the cluster wide encrypted setting for this osd
+    encrypted: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -125,11 +143,156 @@ struct SystemDisk {
     device: PathBuf,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+/// Where new osds go in the crush map.  Each bucket is a template that can use
+/// {hostname}, {region}, {row} and {rack}.  Buckets that aren't set are left out
+struct CrushLocation {
+    root: Option<String>,
+    datacenter: Option<String>,
+    room: Option<String>,
+    row: Option<String>,
+    rack: Option<String>,
+    /// Defaults to {hostname}
+    host: Option<String>,
+}
+
+impl CrushLocation {
+    /// Fill in the templates.  ie ["root=default", "rack=r12", "host=ceph01"]
+    fn render(&self, values: &BTreeMap<&str, &str>) -> BynarResult<Vec<String>> {
+        let buckets = [
+            ("root", &self.root),
+            ("datacenter", &self.datacenter),
+            ("room", &self.room),
+            ("row", &self.row),
+            ("rack", &self.rack),
+            ("host", &self.host),
+        ];
+        let mut location = Vec::new();
+        for (bucket, template) in buckets.iter() {
+            let template = match template {
+                Some(t) => t.as_str(),
+                None if *bucket == "host" => "{hostname}",
+                None => continue,
+            };
+            location.push(format!("{}={}", bucket, fill_template(template, values)?));
+        }
+        Ok(location)
+    }
+}
+
+// Replace each {name} in template with its value
+fn fill_template(template: &str, values: &BTreeMap<&str, &str>) -> BynarResult<String> {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| {
+            BynarError::new(format!("Unclosed {{ in crush location {}", template))
+        })? + start;
+        let name = &rest[start + 1..end];
+        let value = values.get(name).ok_or_else(|| {
+            BynarError::new(format!(
+                "crush location {} uses {{{}}} which isn't set",
+                template, name
+            ))
+        })?;
+        filled.push_str(&rest[..start]);
+        filled.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+#[test]
+fn test_crush_location() {
+    let location = CrushLocation {
+        root: Some("default".to_string()),
+        datacenter: Some("{region}-dc1".to_string()),
+        rack: Some("{row}{rack}".to_string()),
+        ..Default::default()
+    };
+    let mut values = BTreeMap::new();
+    values.insert("hostname", "ceph01");
+    values.insert("region", "us-east");
+    values.insert("row", "b");
+    values.insert("rack", "12");
+    assert_eq!(
+        location.render(&values).unwrap(),
+        vec![
+            "root=default".to_string(),
+            "datacenter=us-east-dc1".to_string(),
+            "rack=b12".to_string(),
+            "host=ceph01".to_string(),
+        ]
+    );
+    // rack isn't configured on this host
+    values.remove("rack");
+    assert!(location.render(&values).is_err());
+    assert!(fill_template("{region", &values).is_err());
+}
+
 // default latency allowed for pool
 fn default_latency() -> f64 {
     15.0
 }
 
+// default percentile of osd latencies compared to latency_cap
+fn default_latency_percentile() -> f64 {
+    95.0
+}
+
+fn default_latency_source() -> LatencySource {
+    LatencySource::OsdPerf
+}
+
+// default place in Vault for the dm-crypt keys of encrypted osds
+fn default_vault_key_path() -> String {
+    "bynar/dmcrypt".to_string()
+}
+
+/// Cluster wide checks a removal has to pass before Bynar drains an osd.
+/// These catch what osd safe-to-destroy can't see from a single osd
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+struct RemovalPolicy {
+    /// Refuse while the cluster is HEALTH_ERR
+    refuse_health_err: bool,
+    /// The crush bucket type that fails together.  ie host or rack
+    failure_domain: String,
+    /// Refuse when more than this many other osds in the failure domain of
+    /// the osd are already down or out
+    max_down_in_failure_domain: u64,
+    /// Refuse when more than this fraction of the objects is degraded
+    max_degraded_ratio: f64,
+    /// Refuse when the cluster would be fuller than its nearfull_ratio
+    /// without the osd's capacity
+    check_nearfull: bool,
+}
+
+impl Default for RemovalPolicy {
+    fn default() -> Self {
+        RemovalPolicy {
+            refuse_health_err: true,
+            failure_domain: "host".to_string(),
+            max_down_in_failure_domain: 1,
+            max_degraded_ratio: 0.05,
+            check_nearfull: true,
+        }
+    }
+}
+
+/// Where the latency compared to latency_cap comes from
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum LatencySource {
+    /// The commit and apply latency osds report through osd perf.  Measuring
+    /// it puts no load on the cluster
+    OsdPerf,
+    /// Write to pool_name with rados bench for 5 seconds.  This adds write
+    /// load to the pool while it's being protected, so it's opt in
+    RadosBench,
+}
+
 // default number of pgs allowed to backfill
 fn default_backfill() -> u64 {
     50
@@ -140,19 +303,274 @@ fn default_increment() -> f64 {
     0.01
 }
 
+// default file to keep track of osds being drained
+fn default_drain_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_drains.json")
+}
+
+// default seconds a drain can run before it needs a human
+fn default_drain_timeout_secs() -> u64 {
+    172800
+}
+
+// default file to keep track of new osds being weighted up
+fn default_reweight_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_reweights.json")
+}
+
+// default file to keep track of the disks ceph orch is creating osds on
+fn default_orch_add_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_orch_adds.json")
+}
+
+// Seconds cephadm gets to start an osd on a new disk
+const ORCH_OSD_BOOT_SECS: u64 = 600;
+
+// default file to keep track of what adding an osd left on the host
+fn default_artifact_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_artifacts.json")
+}
+
+// default file to keep track of how often the osds on this host come back up
+fn default_osd_history_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_osd_history.json")
+}
+
+// default times an osd can come back up within flap_window_secs
+fn default_flap_count() -> usize {
+    3
+}
+
+// default seconds flaps are counted over
+fn default_flap_window_secs() -> u64 {
+    86400
+}
+
+// default multiple of the median osd latency that makes an osd slow
+fn default_slow_osd_factor() -> f64 {
+    5.0
+}
+
+// default ms an osd has to be over to be slow
+fn default_slow_osd_latency() -> f64 {
+    100.0
+}
+
+// default checks in a row an osd has to be slow in
+fn default_slow_osd_runs() -> u32 {
+    3
+}
+
+/// The times an osd on this host went down and came back up
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OsdHistory {
+    osd_id: u64,
+    /// The osdmap epoch the osd last came up in
+    up_from: u64,
+    /// Seconds since the epoch of each time it came back up
+    flaps: Vec<u64>,
+    /// Checks in a row the osd was slow in
+    #[serde(default)]
+    slow_runs: u32,
+}
+
+// Enabled systemd units are symlinked in here
+const SYSTEMD_WANTS_DIR: &str = "/etc/systemd/system/multi-user.target.wants";
+
+/// Something adding an osd left on the host that removing it has to undo
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum HostArtifact {
+    /// A line in udev_rule_path making the partitions of a disk ceph's.  ie sdc
+    UdevRule { device: String },
+    /// The fstab entry that mounts an osd's data partition
+    FstabEntry {
+        fs_spec: String,
+        mountpoint: PathBuf,
+    },
+    /// The ceph-volume unit that activates an lvm osd at boot
+    SystemdUnit { unit: String },
+}
+
+impl fmt::Display for HostArtifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostArtifact::UdevRule { device } => write!(f, "udev rule for {}", device),
+            HostArtifact::FstabEntry {
+                fs_spec,
+                mountpoint,
+            } => write!(f, "fstab entry {} {}", fs_spec, mountpoint.display()),
+            HostArtifact::SystemdUnit { unit } => write!(f, "systemd unit {}", unit),
+        }
+    }
+}
+
+/// An artifact and the osd whose add created it
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct TrackedArtifact {
+    osd_id: u64,
+    artifact: HostArtifact,
+}
+
+// default seconds a cluster flag lease lasts without being renewed
+fn default_flag_lease_secs() -> u64 {
+    3600
+}
+
+// The mon config-keys holding the cluster flag leases.  Each lease is its own
+// key, bynar/flag_leases/{host}/osd.{id}/{flag}, so hosts never rewrite each
+// other's leases
+const FLAG_LEASE_PREFIX: &str = "bynar/flag_leases";
+
+// default flags set while an osd is drained, removed or weighted up
+fn default_held_flags() -> Vec<ClusterFlag> {
+    vec![ClusterFlag::NoScrub, ClusterFlag::NoDeepScrub]
+}
+
+/// A cluster wide osd flag
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+enum ClusterFlag {
+    #[serde(rename = "noscrub")]
+    NoScrub,
+    #[serde(rename = "nodeep-scrub")]
+    NoDeepScrub,
+    #[serde(rename = "noout")]
+    NoOut,
+    #[serde(rename = "norebalance")]
+    NoRebalance,
+    #[serde(rename = "noin")]
+    NoIn,
+}
+
+impl ClusterFlag {
+    fn name(self) -> &'static str {
+        match self {
+            ClusterFlag::NoScrub => "noscrub",
+            ClusterFlag::NoDeepScrub => "nodeep-scrub",
+            ClusterFlag::NoOut => "noout",
+            ClusterFlag::NoRebalance => "norebalance",
+            ClusterFlag::NoIn => "noin",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ClusterFlag> {
+        [
+            ClusterFlag::NoScrub,
+            ClusterFlag::NoDeepScrub,
+            ClusterFlag::NoOut,
+            ClusterFlag::NoRebalance,
+            ClusterFlag::NoIn,
+        ]
+        .iter()
+        .cloned()
+        .find(|f| f.name() == name)
+    }
+
+    fn option(self) -> OsdOption {
+        match self {
+            ClusterFlag::NoScrub => OsdOption::NoScrub,
+            ClusterFlag::NoDeepScrub => OsdOption::NoDeepScrub,
+            ClusterFlag::NoOut => OsdOption::NoOut,
+            ClusterFlag::NoRebalance => OsdOption::NoRebalance,
+            ClusterFlag::NoIn => OsdOption::NoIn,
+        }
+    }
+}
+
+/// A cluster flag held for an operation on one osd.  The flag is only unset
+/// once nobody holds it.  A lease that isn't renewed before it expires, ie
+/// because its host crashed, is dropped by the next host to touch the flags
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct FlagLease {
+    flag: ClusterFlag,
+    /// hostname/osd.N, or hostname/sdb for a disk that has no osd yet
+    holder: String,
+    /// Seconds since the epoch when the lease runs out
+    expires: u64,
+}
+
+/// A new osd that is being weighted up to its target weight one increment
+/// at a time.  The weight it got to lives in the crush map
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OsdReweight {
+    device: PathBuf,
+    osd_id: u64,
+    /// Seconds since the epoch when the reweight started
+    started: u64,
+    target_weight: f64,
+}
+
+// default file to keep track of osds lost with their journal device
+fn default_journal_group_state_file() -> PathBuf {
+    PathBuf::from("/var/lib/bynar/ceph_journal_groups.json")
+}
+
+/// An osd that can't run without its journal device
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct JournalDependent {
+    /// The disk the osd keeps its data on
+    device: PathBuf,
+    osd_id: u64,
+}
+
+/// The osds that went down with a failed journal device.  They're removed
+/// together and rebuilt with their journals on the replacement device
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct JournalGroup {
+    journal: PathBuf,
+    osds: Vec<JournalDependent>,
+}
+
+/// A disk ceph orch was asked to create an osd on.  add_disk calls look for
+/// the osd until it boots
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OrchAdd {
+    device: PathBuf,
+    /// The destroyed osd id the new osd has to get
+    osd_id: Option<u64>,
+    /// Seconds since the epoch when ceph orch was asked for the osd
+    started: u64,
+}
+
+/// An osd that is having its data moved off before it's destroyed
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OsdDrain {
+    device: PathBuf,
+    osd_id: u64,
+    /// Seconds since the epoch when the drain started
+    started: u64,
+    /// Number of pgs on the osd when the drain started
+    initial_pgs: u64,
+}
+
 #[derive(Deserialize, Debug)]
 struct CephConfig {
     /// The location of the ceph.conf file
     config_file: String,
     /// The cephx user to connect to the Ceph service with
     user_id: String,
-    /// The name of the pool to test latency on when gently reweighting an osd for an add operation
+    /// The pool whose client I/O is checked against client_ops_cap when gently
+    /// reweighting an osd.  rados_bench tests latency on it
     pool_name: String,
     /// The target weight of the osds
     target_weight: f64,
+    /// More clusters this host serves osds to.  config_file, user_id,
+    /// pool_name and target_weight above are the default cluster
+    #[serde(default)]
+    clusters: Vec<CephCluster>,
     /// the maximum amount of latency allowed in the pool while performing operations in ms
     #[serde(default = "default_latency")]
     latency_cap: f64,
+    /// How latency is measured.  osd_perf or rados_bench
+    #[serde(default = "default_latency_source")]
+    latency_source: LatencySource,
+    /// With osd_perf, the percentile of osd latencies that has to stay under
+    /// latency_cap.  95 lets the slowest 5% of osds go over it
+    #[serde(default = "default_latency_percentile")]
+    latency_percentile: f64,
+    /// The client operations per second on pool_name above which reweighting
+    /// waits.  Unlimited if not set
+    client_ops_cap: Option<u64>,
     /// the maximum amount of pgs allowed to backfill while performing operations
     #[serde(default = "default_backfill")]
     backfill_cap: u64,
@@ -168,12 +586,169 @@ struct CephConfig {
     /// Bynar will create new partitions on these devices as needed
     /// if no journal_partition_id is given
     journal_devices: Option<Vec<JournalDevice>>,
-    /// The configuration for the osds, specifically, whether the osd
+    /// Overrides for the discovered osd layout, specifically, whether the osd
     /// uses LVMs, and what its journal path and RocksDB path are
+    #[serde(default)]
     osd_config: Vec<OsdConfig>,
     /// The location of the udev rules, which will be updated on adding an osd device
     /// so the osd is owned properly by ceph:ceph
     udev_rule_path: String,
+    /// Where osds being drained ahead of removal are recorded so a drain
+    /// survives disk-manager restarts
+    #[serde(default = "default_drain_state_file")]
+    drain_state_file: PathBuf,
+    /// Seconds a drain can run before it's reported as stuck.  The osd stays
+    /// at weight 0 until someone looks at it or cancels the job
+    #[serde(default = "default_drain_timeout_secs")]
+    drain_timeout_secs: u64,
+    /// Where new osds being weighted up are recorded so the reweight
+    /// survives disk-manager restarts
+    #[serde(default = "default_reweight_state_file")]
+    reweight_state_file: PathBuf,
+    /// Where the disks ceph orch is creating osds on are recorded
+    #[serde(default = "default_orch_add_state_file")]
+    orch_add_state_file: PathBuf,
+    /// Where the osds of a failed journal device are recorded until
+    /// they're rebuilt on the replacement
+    #[serde(default = "default_journal_group_state_file")]
+    journal_group_state_file: PathBuf,
+    /// Where the udev rules, fstab entries and systemd units created for
+    /// each osd are recorded so its removal can undo them
+    #[serde(default = "default_artifact_state_file")]
+    artifact_state_file: PathBuf,
+    /// Where the times each osd on this host came back up are recorded
+    #[serde(default = "default_osd_history_state_file")]
+    osd_history_state_file: PathBuf,
+    /// An osd that comes back up this many times within flap_window_secs is
+    /// flapping and its disk is evaluated again
+    #[serde(default = "default_flap_count")]
+    flap_count: usize,
+    #[serde(default = "default_flap_window_secs")]
+    flap_window_secs: u64,
+    /// An osd whose osd perf latency is over this multiple of the median
+    /// osd and over slow_osd_latency ms is slow and its disk is evaluated again
+    #[serde(default = "default_slow_osd_factor")]
+    slow_osd_factor: f64,
+    #[serde(default = "default_slow_osd_latency")]
+    slow_osd_latency: f64,
+    /// An osd only counts as slow after this many checks in a row.  One osd
+    /// perf sample is too noisy
+    #[serde(default = "default_slow_osd_runs")]
+    slow_osd_runs: u32,
+    /// Seconds a host holds a cluster flag for an osd without renewing it.
+    /// Drains and reweights renew their leases every time they're checked on
+    #[serde(default = "default_flag_lease_secs")]
+    flag_lease_secs: u64,
+    /// The cluster flags set while an osd is drained, removed or weighted up.
+    /// Any of noscrub, nodeep-scrub and noout
+    #[serde(default = "default_held_flags")]
+    held_flags: Vec<ClusterFlag>,
+    /// Where new osds are placed in the crush map.  Without it new osds
+    /// go under their host wherever ceph puts it
+    crush_location: Option<CrushLocation>,
+    /// The row this host is in.  Fills {row} in crush_location
+    row: Option<String>,
+    /// The rack this host is in.  Fills {rack} in crush_location
+    rack: Option<String>,
+    /// Checks every removal has to pass
+    #[serde(default)]
+    removal_policy: RemovalPolicy,
+    /// Create new osds on dm-crypt devices.  Only lvm bluestore osds
+    /// can be encrypted
+    #[serde(default)]
+    encrypted: bool,
+    /// The Vault server the dm-crypt keys are kept in
+    vault_endpoint: Option<String>,
+    vault_token: Option<String>,
+    /// Where in Vault the dm-crypt keys go.  Each key is stored under the
+    /// fsid of its osd
+    #[serde(default = "default_vault_key_path")]
+    vault_key_path: String,
+}
+
+/// Another cluster this host serves osds to.  Everything but the connection,
+/// pool and weight comes from the top level config
+#[derive(Clone, Debug, Deserialize)]
+struct CephCluster {
+    /// Names the cluster in logs and its state files.  ie backup
+    name: String,
+    config_file: String,
+    user_id: String,
+    pool_name: String,
+    target_weight: f64,
+    /// The new disks that go in this cluster.  Disks that match no cluster
+    /// go in the default cluster
+    #[serde(default)]
+    devices: Vec<DeviceSelector>,
+}
+
+// The config of an extra cluster.  Its state files get the cluster name
+// since osd ids can be the same in both clusters
+fn cluster_config(config: &mut CephConfig, cluster: &CephCluster) {
+    config.config_file = cluster.config_file.clone();
+    config.user_id = cluster.user_id.clone();
+    config.pool_name = cluster.pool_name.clone();
+    config.target_weight = cluster.target_weight;
+    config.clusters.clear();
+    config.drain_state_file = cluster_state_file(&config.drain_state_file, &cluster.name);
+    config.reweight_state_file = cluster_state_file(&config.reweight_state_file, &cluster.name);
+    config.orch_add_state_file = cluster_state_file(&config.orch_add_state_file, &cluster.name);
+    config.journal_group_state_file =
+        cluster_state_file(&config.journal_group_state_file, &cluster.name);
+    config.artifact_state_file = cluster_state_file(&config.artifact_state_file, &cluster.name);
+    config.osd_history_state_file =
+        cluster_state_file(&config.osd_history_state_file, &cluster.name);
+}
+
+// ie /var/lib/bynar/ceph_drains.json becomes ceph_drains-backup.json
+fn cluster_state_file(state_file: &Path, name: &str) -> PathBuf {
+    let stem = state_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match state_file.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, name, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, name),
+    };
+    state_file.with_file_name(file_name)
+}
+
+#[test]
+fn test_cluster_config() {
+    let mut config: CephConfig = serde_json::from_str(
+        r#"{
+            "config_file": "/etc/ceph/ceph.conf",
+            "user_id": "admin",
+            "pool_name": "rbd",
+            "target_weight": 1.0,
+            "system_disks": [],
+            "udev_rule_path": "/etc/udev/rules.d/99-ceph-osd.rules",
+            "clusters": [{
+                "name": "backup",
+                "config_file": "/etc/ceph/backup.conf",
+                "user_id": "bynar",
+                "pool_name": "archive",
+                "target_weight": 0.5,
+                "devices": [{"path": "/dev/sd[k-z]"}]
+            }]
+        }"#,
+    )
+    .unwrap();
+    let cluster = config.clusters[0].clone();
+    cluster_config(&mut config, &cluster);
+    assert_eq!(config.config_file, "/etc/ceph/backup.conf");
+    assert_eq!(config.user_id, "bynar");
+    assert_eq!(config.pool_name, "archive");
+    assert_eq!(config.target_weight, 0.5);
+    assert!(config.clusters.is_empty());
+    assert_eq!(
+        config.drain_state_file,
+        Path::new("/var/lib/bynar/ceph_drains-backup.json")
+    );
+    assert_eq!(
+        config.artifact_state_file,
+        Path::new("/var/lib/bynar/ceph_artifacts-backup.json")
+    );
 }
 
 fn choose_ceph_config(config_dir: Option<&Path>) -> BynarResult<PathBuf> {
@@ -223,6 +798,18 @@ fn validate_config(config: &mut CephConfig, cluster_handle: &Rados) -> BynarResu
     if config.latency_cap == 0.0 || config.latency_cap < 0.0 {
         return Err(BynarError::from("latency cap is less than or equal to 0.0"));
     }
+    if config.latency_percentile <= 0.0 || config.latency_percentile > 100.0 {
+        return Err(BynarError::new(format!(
+            "latency percentile {} is not between 0 and 100",
+            config.latency_percentile
+        )));
+    }
+    if config.held_flags.contains(&ClusterFlag::NoRebalance) {
+        // The drains and reweights holding the flags need the data to move
+        return Err(BynarError::from(
+            "norebalance can't be in held_flags.  It stops drains and reweights",
+        ));
+    }
     let names = osd_pool_ls(cluster_handle)?;
 
     if !names.iter().any(|e| e == &config.pool_name) {
@@ -231,6 +818,17 @@ fn validate_config(config: &mut CephConfig, cluster_handle: &Rados) -> BynarResu
             config.pool_name
         )));
     }
+    if let Some(ref crush_location) = config.crush_location {
+        // Catch template mistakes before an osd is half added
+        let host_info = Host::new()?;
+        crush_location.render(&crush_values(config, &host_info))?;
+    }
+    let encrypts = config.encrypted || config.osd_config.iter().any(|o| o.encrypted == Some(true));
+    if encrypts && (config.vault_endpoint.is_none() || config.vault_token.is_none()) {
+        return Err(BynarError::from(
+            "vault_endpoint and vault_token must be set to encrypt osds",
+        ));
+    }
     for osd_config in &config.osd_config {
         if let Some(journal_path) = &osd_config.journal_path {
             if let Some(rdb_path) = &osd_config.rdb_path {
@@ -246,7 +844,22 @@ fn validate_config(config: &mut CephConfig, cluster_handle: &Rados) -> BynarResu
     Ok(())
 }
 
-// get the OSDConfig for a given input osd path if one exists
+// The values crush_location templates can use
+fn crush_values<'a>(config: &'a CephConfig, host_info: &'a Host) -> BTreeMap<&'a str, &'a str> {
+    let mut values = BTreeMap::new();
+    values.insert("hostname", host_info.hostname.as_str());
+    values.insert("region", host_info.region.as_str());
+    if let Some(ref row) = config.row {
+        values.insert("row", row.as_str());
+    }
+    if let Some(ref rack) = config.rack {
+        values.insert("rack", rack.as_str());
+    }
+    values
+}
+
+// get the OSDConfig for a given input osd path.  An entry in the config file
+// wins, otherwise the layout is discovered from the host
 fn get_osd_config_by_path(config: &CephConfig, dev_path: &Path) -> BynarResult<OsdConfig> {
     let path = dev_path.to_string_lossy().to_string();
     let parent = match block_utils::get_parent_devpath_from_path(dev_path) {
@@ -259,33 +872,360 @@ fn get_osd_config_by_path(config: &CephConfig, dev_path: &Path) -> BynarResult<O
             return Ok(osdconfig.clone());
         }
     }
-    Err(BynarError::from(format!(
-        "Neither {} or its parent is in the config file",
-        dev_path.display()
-    )))
+    discover_osd_config(dev_path)
 }
 
-impl CephBackend {
-    pub fn new(config_dir: Option<&Path>) -> BynarResult<CephBackend> {
+// Work out how the osd on dev_path was deployed.  ceph-volume tags its logical
+// volumes with ceph.type=block.  A manually provisioned bluestore osd has a
+// block symlink to a partition on the disk.  Anything else, including a new
+// disk or a filestore osd, takes the LVM path which falls back to filestore
+// removal when there's no volume group
+fn discover_osd_config(dev_path: &Path) -> BynarResult<OsdConfig> {
+    let lvm_config = OsdConfig {
+        is_lvm: true,
+        dev_path: dev_path.to_string_lossy().into_owned(),
+        journal_path: None,
+        rdb_path: None,
+        encrypted: None,
+    };
+    let disk = match block_utils::get_parent_devpath_from_path(dev_path) {
+        Ok(Some(p)) => p,
+        _ => dev_path.to_path_buf(),
+    };
+
+    debug!("initializing LVM");
+    let lvm = Lvm::new(None)?;
+    lvm.scan()?;
+    if let Some(vg_name) = lvm.vg_name_from_device(&disk.to_string_lossy())? {
+        let vg = lvm.vg_open(&vg_name, &OpenMode::Read)?;
+        for lv in vg.list_lvs()? {
+            if tag_value(&lv.get_tags()?, "ceph.type") == Some("block") {
+                debug!("{} is an lvm osd in {}", dev_path.display(), vg_name);
+                return Ok(lvm_config);
+            }
+        }
+    }
+
+    let osd_dir = Path::new("/var/lib/ceph/osd");
+    if osd_dir.exists() {
+        for entry in read_dir(osd_dir)? {
+            let mount_point = entry?.path();
+            match read_to_string(mount_point.join("type")) {
+                Ok(ref osd_type) if osd_type.trim() == "bluestore" => {}
+                _ => continue,
+            };
+            let block = match osd_link(&mount_point, "block") {
+                Some(block) => block,
+                None => continue,
+            };
+            if let Some(osd_config) = manual_osd_config(
+                &disk,
+                &block,
+                osd_link(&mount_point, "block.wal"),
+                osd_link(&mount_point, "block.db"),
+            ) {
+                debug!(
+                    "{} is a manual bluestore osd at {}",
+                    dev_path.display(),
+                    mount_point.display()
+                );
+                return Ok(osd_config);
+            }
+        }
+    }
+    Ok(lvm_config)
+}
+
+// The fsid of the cluster the osds on device are in.  ceph-volume tags its
+// logical volumes with it and every osd directory has a ceph_fsid file.
+// None for a disk without osds.  An osd without a fsid is an error since
+// guessing its cluster could remove it from the wrong one
+fn device_cluster_fsid(device: &Path) -> BynarResult<Option<String>> {
+    let disk = match block_utils::get_parent_devpath_from_path(device) {
+        Ok(Some(p)) => p,
+        _ => device.to_path_buf(),
+    };
+
+    debug!("initializing LVM");
+    let lvm = Lvm::new(None)?;
+    lvm.scan()?;
+    if let Some(vg_name) = lvm.vg_name_from_device(&disk.to_string_lossy())? {
+        let vg = lvm.vg_open(&vg_name, &OpenMode::Read)?;
+        for lv in vg.list_lvs()? {
+            let tags = lv.get_tags()?;
+            if let Some(fsid) = tag_value(&tags, "ceph.cluster_fsid") {
+                return Ok(Some(fsid.to_string()));
+            }
+            if let Some(osd_id) = tag_value(&tags, "ceph.osd_id") {
+                return Err(BynarError::new(format!(
+                    "osd {} on {} has no ceph.cluster_fsid tag.  Unable to tell its cluster",
+                    osd_id,
+                    device.display()
+                )));
+            }
+        }
+    }
+
+    let osd_dir = Path::new("/var/lib/ceph/osd");
+    if !osd_dir.exists() {
+        return Ok(None);
+    }
+    // Filestore osds mount the disk on their directory
+    let mount_point = match block_utils::get_mountpoint(&disk) {
+        Ok(Some(mount_point)) => Some(mount_point),
+        _ => None,
+    };
+    for entry in read_dir(osd_dir)? {
+        let osd_path = entry?.path();
+        let on_disk = mount_point.as_ref() == Some(&osd_path)
+            || ["block", "block.wal", "block.db", "journal"]
+                .iter()
+                .filter_map(|name| osd_link(&osd_path, name))
+                .any(|link| on_device(&link, &disk));
+        if !on_disk {
+            continue;
+        }
+        return match read_to_string(osd_path.join("ceph_fsid")) {
+            Ok(fsid) => Ok(Some(fsid.trim().to_string())),
+            Err(e) => Err(BynarError::new(format!(
+                "Unable to read the cluster fsid of {} on {}: {}",
+                osd_path.display(),
+                device.display(),
+                e
+            ))),
+        };
+    }
+    Ok(None)
+}
+
+// Where a symlink in an osd directory points.  ie block.db
+fn osd_link(mount_point: &Path, name: &str) -> Option<PathBuf> {
+    let link = mount_point.join(name);
+    match symlink_metadata(&link) {
+        Ok(ref meta) if meta.file_type().is_symlink() => {}
+        _ => return None,
+    };
+    link.canonicalize().or_else(|_| link.read_link()).ok()
+}
+
+// The config of a manually provisioned bluestore osd if its block symlink
+// points to a partition on disk
+fn manual_osd_config(
+    disk: &Path,
+    block: &Path,
+    wal: Option<PathBuf>,
+    db: Option<PathBuf>,
+) -> Option<OsdConfig> {
+    if block == disk || !on_device(block, disk) {
+        return None;
+    }
+    Some(OsdConfig {
+        is_lvm: false,
+        dev_path: disk.to_string_lossy().into_owned(),
+        journal_path: wal.map(|p| p.to_string_lossy().into_owned()),
+        rdb_path: db.map(|p| p.to_string_lossy().into_owned()),
+        encrypted: None,
+    })
+}
+
+impl CephClusters {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<CephClusters> {
         let ceph_config = choose_ceph_config(config_dir)?;
         if !ceph_config.exists() {
             error!("ceph config {} does not exist", ceph_config.display());
         }
         let s = read_to_string(ceph_config)?;
         let mut deserialized: CephConfig = serde_json::from_str(&s)?;
+        let extra = deserialized.clusters.split_off(0);
+
+        let mut clusters = vec![CephBackend::connect(deserialized, "ceph", Vec::new())?];
+        for cluster in extra {
+            let mut config: CephConfig = serde_json::from_str(&s)?;
+            cluster_config(&mut config, &cluster);
+            let backend = CephBackend::connect(config, &cluster.name, cluster.devices)?;
+            if let Some(other) = clusters.iter().find(|c| c.fsid == backend.fsid) {
+                return Err(BynarError::new(format!(
+                    "Clusters {} and {} are both fsid {}",
+                    other.name, backend.name, backend.fsid
+                )));
+            }
+            clusters.push(backend);
+        }
+        let clusters = CephClusters { clusters };
+        if clusters.clusters.len() > 1 {
+            clusters.check_osd_ids()?;
+        }
+        Ok(clusters)
+    }
+
+    // The osd directories and units on the host are named by osd id alone,
+    // ie /var/lib/ceph/osd/ceph-3, so two clusters can't both have an osd 3 here
+    fn check_osd_ids(&self) -> BynarResult<()> {
+        let mut seen: Vec<(u64, &str)> = Vec::new();
+        for cluster in &self.clusters {
+            for osd_id in cluster.host_osd_ids()? {
+                if let Some((_, other)) = seen.iter().find(|(id, _)| *id == osd_id) {
+                    return Err(BynarError::new(format!(
+                        "Clusters {} and {} both have osd {} on this host",
+                        other, cluster.name, osd_id
+                    )));
+                }
+                seen.push((osd_id, &cluster.name));
+            }
+        }
+        Ok(())
+    }
+
+    // The cluster a device belongs to.  Disks with an osd go by the osd's
+    // cluster fsid, then by the operations in progress on them.  New disks
+    // go to the first cluster whose devices match, or the default cluster
+    fn cluster_for(&self, device: &Path) -> BynarResult<&CephBackend> {
+        if self.clusters.len() == 1 {
+            return Ok(&self.clusters[0]);
+        }
+        if let Some(fsid) = device_cluster_fsid(device)? {
+            return match self.clusters.iter().find(|c| c.fsid == fsid) {
+                Some(cluster) => {
+                    debug!("{} is in cluster {}", device.display(), cluster.name);
+                    Ok(cluster)
+                }
+                None => Err(BynarError::new(format!(
+                    "{} belongs to cluster {} which isn't configured",
+                    device.display(),
+                    fsid
+                ))),
+            };
+        }
+        for cluster in &self.clusters {
+            if cluster.tracks_device(device)? {
+                debug!(
+                    "{} has an operation in progress in cluster {}",
+                    device.display(),
+                    cluster.name
+                );
+                return Ok(cluster);
+            }
+        }
+        for cluster in &self.clusters {
+            if device_matches(&cluster.devices, device)? {
+                debug!("{} goes in cluster {}", device.display(), cluster.name);
+                return Ok(cluster);
+            }
+        }
+        Ok(&self.clusters[0])
+    }
+}
 
-        info!("Connecting to Ceph");
-        let cluster_handle = connect_to_ceph(&deserialized.user_id, &deserialized.config_file)?;
-        info!("Connected to Ceph");
+impl CephBackend {
+    fn connect(
+        mut config: CephConfig,
+        name: &str,
+        devices: Vec<DeviceSelector>,
+    ) -> BynarResult<CephBackend> {
+        info!("Connecting to Ceph cluster {}", name);
+        let cluster_handle = connect_to_ceph(&config.user_id, &config.config_file)?;
+        info!("Connected to Ceph cluster {}", name);
         let version_str = version(&cluster_handle)?;
         let version: CephVersion = version_str.parse()?;
-        validate_config(&mut deserialized, &cluster_handle)?;
+        validate_config(&mut config, &cluster_handle)?;
+        let fsid = cluster_fsid(&cluster_handle)?;
+        // orch status answers from Octopus on.  Older releases run the osds
+        // with systemd
+        let orchestrated = version >= CephVersion::Nautilus && orch_available(&cluster_handle);
+        if orchestrated {
+            info!("cephadm manages the osds.  Using ceph orch");
+        }
 
-        Ok(CephBackend {
+        let backend = CephBackend {
             cluster_handle,
-            config: deserialized,
+            config,
             version,
-        })
+            orchestrated,
+            name: name.to_string(),
+            fsid,
+            devices,
+        };
+        if let Err(e) = backend.open_encrypted_osds() {
+            error!("Opening encrypted osds failed: {:?}", e);
+        }
+        if let Err(e) = backend.expire_flag_leases() {
+            error!("Expiring cluster flag leases failed: {:?}", e);
+        }
+        Ok(backend)
+    }
+
+    // ceph-volume can't activate the encrypted osds at boot because their
+    // keys are in Vault.  Open the dm-crypt mapping of any osd that doesn't
+    // have one yet and start it.  An osd that is being removed or was
+    // destroyed stays closed
+    fn open_encrypted_osds(&self) -> BynarResult<()> {
+        let osd_dir = Path::new("/var/lib/ceph/osd");
+        if !osd_dir.exists() {
+            return Ok(());
+        }
+        let drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        let groups: Vec<JournalGroup> = load_state(&self.config.journal_group_state_file)?;
+        let osd_dump = osd_dump_json(&self.cluster_handle)?;
+        for entry in read_dir(osd_dir)? {
+            let mount_point = entry?.path();
+            let dmcrypt_file = mount_point.join("dmcrypt");
+            if !dmcrypt_file.exists() {
+                continue;
+            }
+            let osd_id = match mount_point
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.trim_start_matches("ceph-").parse::<u64>().ok())
+            {
+                Some(id) => id,
+                None => continue,
+            };
+            let removing = drains.iter().any(|d| d.osd_id == osd_id)
+                || groups
+                    .iter()
+                    .any(|g| g.osds.iter().any(|osd| osd.osd_id == osd_id));
+            if removing || osd_has_state(&osd_dump, osd_id, "destroyed") {
+                debug!(
+                    "osd {} is being removed or was destroyed.  Not opening it",
+                    osd_id
+                );
+                continue;
+            }
+            let osd_fsid = uuid::Uuid::parse_str(read_to_string(mount_point.join("fsid"))?.trim())?;
+            if dmcrypt_path(&osd_fsid).exists() {
+                continue;
+            }
+            // The dmcrypt file holds the logical volume under the mapping
+            let lv_dev_name = PathBuf::from(read_to_string(&dmcrypt_file)?.trim());
+            info!("Opening dm-crypt mapping of osd {}", osd_id);
+            let key = get_dmcrypt_key(&self.config, &osd_fsid)?;
+            luks_open(&lv_dev_name, &osd_fsid, &key, false)?;
+            let ceph_user = Passwd::from_name("ceph")?
+                .ok_or_else(|| BynarError::from("ceph user id not found"))?;
+            let backer_device = self.resolve_lvm_device(&dmcrypt_path(&osd_fsid))?;
+            self.change_permissions(&[&backer_device], &ceph_user)?;
+            setup_osd_init(osd_id, false)?;
+        }
+        Ok(())
+    }
+
+    // Format the logical volume of a new osd with LUKS and open it.  The key
+    // is saved to Vault first so there's never data nobody can decrypt.
+    // Returns the dm-crypt device ceph should use
+    fn encrypt_lv(
+        &self,
+        lv_dev_name: &Path,
+        osd_fsid: &uuid::Uuid,
+        simulate: bool,
+    ) -> BynarResult<PathBuf> {
+        let key = generate_dmcrypt_key()?;
+        debug!("Saving the dm-crypt key of {} to Vault", osd_fsid);
+        if !simulate {
+            save_dmcrypt_key(&self.config, osd_fsid, &key)?;
+        }
+        luks_format(lv_dev_name, &key, simulate)?;
+        luks_open(lv_dev_name, osd_fsid, &key, simulate)?;
+        Ok(dmcrypt_path(osd_fsid))
     }
 
     // add a bluestore without using LVM, dev_path should be the disk path (ensure it is the disk path with get_parent_dev)
@@ -295,7 +1235,7 @@ impl CephBackend {
         id: Option<u64>,
         osd_config: &OsdConfig,
         simulate: bool,
-    ) -> BynarResult<()> {
+    ) -> BynarResult<u64> {
         // disk /dev/sdX should have two partitions, 1st one 100MB, second one rest of disk
         // first one for bluestore mount, second for block device. make them if so.  Note: gpt cfg.open()
         // errors out if the path is NOT a gpt formatted disk. if partition or not gpt, will fail
@@ -310,7 +1250,8 @@ impl CephBackend {
         mkfs_osd_dir(&dir_partition)?;
 
         // create osd id
-        let osd_id = osd_create(&self.cluster_handle, id, simulate)?;
+        let osd_fsid = uuid::Uuid::new_v4();
+        let osd_id = self.create_osd_id(id, &osd_fsid, simulate)?;
         //mkdir /var/lib/ceph/osd/{clustername-osd}
         let mount_point = Path::new("/var/lib/ceph/osd").join(&format!("ceph-{}", osd_id));
         if !mount_point.exists() {
@@ -336,12 +1277,14 @@ impl CephBackend {
         // add block device to udev/rules.d if not already in (journal + rdb should already be in but check anyways)
         // check if dev_path in udev/rules.d
         debug!("Try to add the block device to the udev rules");
-        add_block_to_udev(dev_path, &self.config.udev_rule_path)?;
+        if let Some(device) = add_block_to_udev(dev_path, &self.config.udev_rule_path, simulate)? {
+            self.record_artifact(osd_id, HostArtifact::UdevRule { device }, simulate)?;
+        }
         //chown /var/lib/ceph/osd/{cluster-id}
         ceph_chown(&mount_point, simulate)?;
 
         // ceph-osd --setuser ceph -i id --mkkey --mkfs
-        ceph_mkkey_mkfs(osd_id, simulate)?;
+        ceph_mkkey_mkfs(osd_id, &osd_fsid, simulate)?;
         // ceph auth add osd.0 osd 'allow *' mon 'allow rwx' mgr 'allow profile osd' -i /var/lib/ceph/osd/ceph-id/keyring
         debug!("Creating ceph authorization entry");
         let keyring = mount_point.join("keyring");
@@ -356,33 +1299,33 @@ impl CephBackend {
             "Adding OSD {} to crushmap under host {} with weight: {}",
             osd_id, host_info.hostname, osd_weight
         );
-        osd_crush_add(
-            &self.cluster_handle,
-            osd_id,
-            osd_weight,
-            &host_info.hostname,
-            simulate,
-        )?;
-        // gradual weight
+        self.place_osd(osd_id, osd_weight, &host_info, simulate)?;
         //systemctl start
         enable_bluestore_manual(osd_id, simulate)?;
         setup_osd_init(osd_id, simulate)?;
-        self.gradual_weight(osd_id, true, simulate)?;
-        Ok(())
+        Ok(osd_id)
     }
 
-    // add a bluestore osd, either with LVM or manually
+    // add a bluestore osd, either with LVM or manually.  Returns the new osd id
     fn add_bluestore_osd(
         &self,
         dev_path: &Path,
         id: Option<u64>,
+        journal_device: Option<&Path>,
         simulate: bool,
-    ) -> BynarResult<()> {
+    ) -> BynarResult<u64> {
         //get osd_config
         debug!("Get osd config");
         let osd_config = get_osd_config_by_path(&self.config, dev_path)?;
+        let encrypted = osd_config.encrypted.unwrap_or(self.config.encrypted);
         if !osd_config.is_lvm {
             debug!("osd is not lvm");
+            if encrypted {
+                return Err(BynarError::new(format!(
+                    "{} can't be encrypted.  Only lvm osds can be",
+                    dev_path.display()
+                )));
+            }
             //check if dev_path is disk or not
             if let Ok(Some(parent)) = block_utils::get_parent_devpath_from_path(dev_path) {
                 // disk in question has partitions
@@ -403,18 +1346,29 @@ impl CephBackend {
             */
         debug!("Select a Journal");
         // Create the journal device if requested
-        let journal = self.select_journal()?;
+        let journal = self.select_journal(journal_device)?;
         // Create a new osd id
-        let new_osd_id = osd_create(&self.cluster_handle, id, simulate)?;
-        debug!("New osd id created: {:?}", new_osd_id);
         let osd_fsid = uuid::Uuid::new_v4();
-        let (lv_dev_name, vg_size) =
-            self.create_lvm(&osd_fsid, new_osd_id, &dev_path, journal.as_ref())?;
-
-        // Mount the drive
-        let mount_point = Path::new("/var/lib/ceph/osd").join(&format!("ceph-{}", new_osd_id));
-        if !mount_point.exists() {
-            debug!(
+        let new_osd_id = self.create_osd_id(id, &osd_fsid, simulate)?;
+        debug!("New osd id created: {:?}", new_osd_id);
+        let (lv_dev_name, vg_size) = self.create_lvm(
+            &osd_fsid,
+            new_osd_id,
+            &dev_path,
+            journal.as_ref(),
+            encrypted,
+        )?;
+        // An encrypted osd lives on the dm-crypt mapping of the logical volume
+        let block_device = if encrypted {
+            self.encrypt_lv(&lv_dev_name, &osd_fsid, simulate)?
+        } else {
+            lv_dev_name.clone()
+        };
+
+        // Mount the drive
+        let mount_point = Path::new("/var/lib/ceph/osd").join(&format!("ceph-{}", new_osd_id));
+        if !mount_point.exists() {
+            debug!(
                 "Mount point {} doesn't exist.  Creating.",
                 mount_point.display()
             );
@@ -427,16 +1381,22 @@ impl CephBackend {
         activate_file
             .write_all(&format!("{}\n", osd_fsid.to_hyphenated().to_string()).as_bytes())?;
 
+        if encrypted {
+            // Remember which logical volume to open the mapping on at boot
+            let mut dmcrypt_file = File::create(mount_point.join("dmcrypt"))?;
+            dmcrypt_file.write_all(format!("{}\n", lv_dev_name.display()).as_bytes())?;
+        }
+
         // LVM's logical volume name is a symlink to the true device
         // This finds that device and then we chown it so ceph can use it
-        let backer_device = self.resolve_lvm_device(&lv_dev_name)?;
+        let backer_device = self.resolve_lvm_device(&block_device)?;
         debug!("Resolved lvm device to {}", backer_device.display());
         debug!(
             "Symlinking {} to {}",
-            lv_dev_name.display(),
+            block_device.display(),
             mount_point.join("block").display()
         );
-        symlink(&lv_dev_name, mount_point.join("block"))?;
+        symlink(&block_device, mount_point.join("block"))?;
         // Optionally symlink the journal if using one
         if let Some(journal) = &journal {
             symlink(
@@ -481,7 +1441,7 @@ impl CephBackend {
             Some("ceph"),
             simulate,
         )?;
-        ceph_bluestore_tool(&lv_dev_name, &mount_point, simulate)?;
+        ceph_bluestore_tool(&block_device, &mount_point, simulate)?;
 
         let host_info = Host::new()?;
         let gb_capacity = vg_size / 1_073_741_824;
@@ -490,28 +1450,38 @@ impl CephBackend {
             "Adding OSD {} to crushmap under host {} with weight: {}",
             new_osd_id, host_info.hostname, osd_weight
         );
-        osd_crush_add(
-            &self.cluster_handle,
-            new_osd_id,
-            osd_weight,
-            &host_info.hostname,
-            simulate,
-        )?;
-        systemctl_enable(new_osd_id, &osd_fsid, simulate)?;
+        self.place_osd(new_osd_id, osd_weight, &host_info, simulate)?;
+        // ceph-volume would look for its own lockbox key at boot.  Bynar
+        // opens encrypted osds itself
+        if !encrypted {
+            let unit = ceph_volume_unit(new_osd_id, &osd_fsid);
+            systemctl_enable(&unit, simulate)?;
+            self.record_artifact(new_osd_id, HostArtifact::SystemdUnit { unit }, simulate)?;
+        }
         setup_osd_init(new_osd_id, simulate)?;
-        self.gradual_weight(new_osd_id, true, simulate)?;
-        Ok(())
+        Ok(new_osd_id)
     }
 
-    /// Add a new /dev/ path as an osd.
+    /// Add a new /dev/ path as an osd.  Returns the new osd id
     // Add osds with xfs
     // Jewel or earlier
     fn add_filestore_osd(
         &self,
         dev_path: &Path,
         id: Option<u64>,
+        journal_device: Option<&Path>,
         simulate: bool,
-    ) -> BynarResult<()> {
+    ) -> BynarResult<u64> {
+        let encrypted = match get_osd_config_by_path(&self.config, dev_path) {
+            Ok(osd_config) => osd_config.encrypted.unwrap_or(self.config.encrypted),
+            Err(_) => self.config.encrypted,
+        };
+        if encrypted {
+            return Err(BynarError::new(format!(
+                "{} can't be encrypted.  Filestore osds aren't supported",
+                dev_path.display()
+            )));
+        }
         //Format the drive
         let xfs_options = block_utils::Filesystem::Xfs {
             stripe_size: None,
@@ -558,7 +1528,7 @@ impl CephBackend {
             block_utils::mount_device(&info, &mount_point)?;
         }
 
-        let journal = self.select_journal()?;
+        let journal = self.select_journal(journal_device)?;
 
         // Format the osd with the osd filesystem
         ceph_mkfs(
@@ -584,18 +1554,46 @@ impl CephBackend {
             "Adding OSD {} to crushmap under host {} with weight: {}",
             new_osd_id, host_info.hostname, osd_weight
         );
-        osd_crush_add(
-            &self.cluster_handle,
-            new_osd_id,
-            osd_weight,
-            &host_info.hostname,
-            simulate,
-        )?;
-        add_osd_to_fstab(&info, new_osd_id, simulate)?;
+        self.place_osd(new_osd_id, osd_weight, &host_info, simulate)?;
+        let artifact = add_osd_to_fstab(&info, new_osd_id, simulate)?;
+        self.record_artifact(new_osd_id, artifact, simulate)?;
         // This step depends on whether it's systemctl, upstart, etc
         setup_osd_init(new_osd_id, simulate)?;
-        self.gradual_weight(new_osd_id, true, simulate)?;
-        Ok(())
+        Ok(new_osd_id)
+    }
+
+    // Put a new osd in the crush map.  With a crush_location it's created or
+    // moved into place before it gets any weight so rack aware rules hold
+    fn place_osd(
+        &self,
+        osd_id: u64,
+        osd_weight: f64,
+        host_info: &Host,
+        simulate: bool,
+    ) -> BynarResult<()> {
+        match self.config.crush_location {
+            Some(ref crush_location) => {
+                let location = crush_location.render(&crush_values(&self.config, host_info))?;
+                debug!("Moving osd {} to crush location {:?}", osd_id, location);
+                osd_crush_create_or_move(
+                    &self.cluster_handle,
+                    osd_id,
+                    osd_weight,
+                    &location,
+                    simulate,
+                )
+            }
+            None => {
+                osd_crush_add(
+                    &self.cluster_handle,
+                    osd_id,
+                    osd_weight,
+                    &host_info.hostname,
+                    simulate,
+                )?;
+                Ok(())
+            }
+        }
     }
 
     // Change permissions of many files at once
@@ -618,6 +1616,7 @@ impl CephBackend {
         new_osd_id: u64,
         dev_path: &Path,
         journal_device: Option<&JournalDevice>,
+        encrypted: bool,
     ) -> BynarResult<(PathBuf, u64)> {
         debug!("udev Probing device {:?}", dev_path);
         let info = block_utils::get_device_info(dev_path)?;
@@ -650,6 +1649,7 @@ impl CephBackend {
             new_osd_id,
             &info,
             journal_device,
+            encrypted,
         )?;
         Ok((lv_dev_name, vg.get_size()))
     }
@@ -663,6 +1663,7 @@ impl CephBackend {
         new_osd_id: u64,
         info: &block_utils::Device,
         journal_device: Option<&JournalDevice>,
+        encrypted: bool,
     ) -> BynarResult<()> {
         debug!("Creating lvm tags");
         let mut tags = vec![
@@ -670,11 +1671,9 @@ impl CephBackend {
             format!("ceph.block_device={}", lv_dev_name.display()),
             format!("ceph.osd_id={}", new_osd_id),
             format!("ceph.osd_fsid={}", osd_fsid),
-            // TODO: Find out where to find this. NOTE: can be found in ceph.conf file under cluster
-            // defaults to ceph.  EX: /etc/ceph/@clustername.keyring
-            format!("ceph.cluster_name={}", "ceph"),
+            format!("ceph.cluster_name={}", self.name),
             format!("ceph.cluster_fsid={}", self.cluster_handle.rados_fsid()?),
-            format!("ceph.encrypted={}", "0"),
+            format!("ceph.encrypted={}", if encrypted { "1" } else { "0" }),
             "ceph.cephx_lockbox_secret=".to_string(),
             format!("ceph.block_uuid={}", lv.get_uuid()),
         ];
@@ -736,25 +1735,439 @@ impl CephBackend {
         Ok(())
     }
 
-    // unset noscrub and nodeepscrub toggle
-    fn unset_noscrub(&self, simulate: bool) -> BynarResult<()> {
-        osd_unset(&self.cluster_handle, &OsdOption::NoScrub, simulate)?;
-        osd_unset(&self.cluster_handle, &OsdOption::NoDeepScrub, simulate)?;
+    // Remember an artifact adding osd_id left on the host
+    fn record_artifact(
+        &self,
+        osd_id: u64,
+        artifact: HostArtifact,
+        simulate: bool,
+    ) -> BynarResult<()> {
+        debug!("osd {} created {}", osd_id, artifact);
+        if simulate {
+            return Ok(());
+        }
+        let tracked = TrackedArtifact { osd_id, artifact };
+        let mut artifacts: Vec<TrackedArtifact> = load_state(&self.config.artifact_state_file)?;
+        if !artifacts.contains(&tracked) {
+            artifacts.push(tracked);
+        }
+        save_state(&self.config.artifact_state_file, &artifacts)
+    }
+
+    // Undo everything adding osd_id left on the host.  The osd is already
+    // gone so a failure is only logged.  host_hygiene finds what's left over
+    fn remove_artifacts(&self, osd_id: u64, simulate: bool) -> BynarResult<()> {
+        let (ours, mut others): (Vec<TrackedArtifact>, Vec<TrackedArtifact>) =
+            load_state::<TrackedArtifact>(&self.config.artifact_state_file)?
+                .into_iter()
+                .partition(|t| t.osd_id == osd_id);
+        let mut artifacts: Vec<HostArtifact> = ours.into_iter().map(|t| t.artifact).collect();
+        // osds added before their artifacts were recorded
+        for artifact in self.host_artifacts()? {
+            if artifact_osd_id(&artifact) == Some(osd_id) && !artifacts.contains(&artifact) {
+                artifacts.push(artifact);
+            }
+        }
+        for artifact in artifacts {
+            if let Err(e) = self.remove_artifact(&artifact, simulate) {
+                error!("Unable to remove {} of osd {}: {:?}", artifact, osd_id, e);
+                others.push(TrackedArtifact { osd_id, artifact });
+            }
+        }
+        if simulate {
+            return Ok(());
+        }
+        save_state(&self.config.artifact_state_file, &others)
+    }
+
+    fn remove_artifact(&self, artifact: &HostArtifact, simulate: bool) -> BynarResult<()> {
+        info!("Removing {}", artifact);
+        if simulate {
+            return Ok(());
+        }
+        match artifact {
+            HostArtifact::UdevRule { device } => {
+                remove_udev_rule(device, &self.config.udev_rule_path)
+            }
+            HostArtifact::FstabEntry { fs_spec, .. } => {
+                let fstab = FsTab::default();
+                fstab.remove_entry(fs_spec)?;
+                Ok(())
+            }
+            HostArtifact::SystemdUnit { unit } => systemctl_disable(unit, simulate),
+        }
+    }
+
+    // The udev rules, fstab entries and ceph-volume units on the host,
+    // whether Bynar recorded them or not
+    fn host_artifacts(&self) -> BynarResult<Vec<HostArtifact>> {
+        let mut artifacts = Vec::new();
+        let udev_path = Path::new(&self.config.udev_rule_path);
+        if udev_path.exists() {
+            for line in read_to_string(udev_path)?.lines() {
+                for device in bynar_udev_rules(line).unwrap_or_default() {
+                    artifacts.push(HostArtifact::UdevRule { device });
+                }
+            }
+        }
+        let fstab = FsTab::default();
+        for entry in fstab.get_entries()? {
+            if entry.mountpoint.starts_with("/var/lib/ceph/osd") {
+                artifacts.push(HostArtifact::FstabEntry {
+                    fs_spec: entry.fs_spec,
+                    mountpoint: entry.mountpoint,
+                });
+            }
+        }
+        let wants = Path::new(SYSTEMD_WANTS_DIR);
+        if wants.exists() {
+            for entry in read_dir(wants)? {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if name.starts_with("ceph-volume@lvm-") {
+                    artifacts.push(HostArtifact::SystemdUnit {
+                        unit: name.trim_end_matches(".service").to_string(),
+                    });
+                }
+            }
+        }
+        Ok(artifacts)
+    }
+
+    // The disks of the osds on this host that keep going down and coming
+    // back up, or are far slower than the rest of the cluster.  Osds being
+    // drained are already on their way out
+    fn find_suspects(&self) -> BynarResult<Vec<SuspectDisk>> {
+        let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+        let cmd = json!({
+            "prefix": "osd metadata",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let osd_metadata: Value = serde_json::from_slice(&result.0)?;
+        let drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        let osd_ids: Vec<u64> = host_osds(&osd_metadata, &host)
+            .into_iter()
+            .map(|(osd_id, _)| osd_id)
+            .filter(|osd_id| !drains.iter().any(|d| d.osd_id == *osd_id))
+            .collect();
+
+        let mut reasons: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+        let mut history: Vec<OsdHistory> = load_state(&self.config.osd_history_state_file)?;
+        let now = epoch_secs();
+        let osd_dump = osd_dump_json(&self.cluster_handle)?;
+        record_flaps(
+            &mut history,
+            &osd_dump,
+            &osd_ids,
+            now.saturating_sub(self.config.flap_window_secs),
+            now,
+        );
+        let latencies = osd_latencies_by_id(&osd_perf_json(&self.cluster_handle)?);
+        let median = percentile(
+            &latencies.iter().map(|(_, l)| *l).collect::<Vec<f64>>(),
+            50.0,
+        );
+        let slow = slow_osds(
+            &latencies,
+            median,
+            self.config.slow_osd_factor,
+            self.config.slow_osd_latency,
+        );
+        record_slow(&mut history, &slow);
+        save_state(&self.config.osd_history_state_file, &history)?;
+        for osd in history
+            .iter()
+            .filter(|h| h.flaps.len() >= self.config.flap_count)
+        {
+            reasons.entry(osd.osd_id).or_default().push(format!(
+                "osd.{} came back up {} times in the last {} seconds",
+                osd.osd_id,
+                osd.flaps.len(),
+                self.config.flap_window_secs
+            ));
+        }
+
+        for (osd_id, latency) in slow {
+            let runs = history
+                .iter()
+                .find(|h| h.osd_id == osd_id)
+                .map_or(0, |h| h.slow_runs);
+            if osd_ids.contains(&osd_id) && runs >= self.config.slow_osd_runs {
+                reasons.entry(osd_id).or_default().push(format!(
+                    "osd.{} latency {}ms is over {} times the median of {}ms for {} checks in a row",
+                    osd_id, latency, self.config.slow_osd_factor, median, runs
+                ));
+            }
+        }
+
+        let mut suspects: Vec<SuspectDisk> = Vec::new();
+        for (osd_id, osd_reasons) in reasons {
+            let disk = match osd_disk(&self.cluster_handle, &host, osd_id)? {
+                Some(disk) => disk.to_string_lossy().into_owned(),
+                None => {
+                    warn!("Unable to find the disk of suspect osd.{}", osd_id);
+                    continue;
+                }
+            };
+            let reason = osd_reasons.join(", ");
+            info!("{} is suspect: {}", disk, reason);
+            match suspects.iter_mut().find(|s| s.get_disk_path() == disk) {
+                Some(suspect) => {
+                    let reason = format!("{}, {}", suspect.get_reason(), reason);
+                    suspect.set_reason(reason);
+                }
+                None => {
+                    let mut suspect = SuspectDisk::new();
+                    suspect.set_disk_path(disk);
+                    suspect.set_reason(reason);
+                    suspects.push(suspect);
+                }
+            }
+        }
+        Ok(suspects)
+    }
+
+    // The artifacts on the host that none of the live osds or in use devices
+    // need.  Removes them if fix is set.  An artifact that couldn't be removed
+    // is reported with why
+    fn orphaned_artifacts(
+        &self,
+        live: &[u64],
+        in_use: &[PathBuf],
+        fix: bool,
+    ) -> BynarResult<Vec<String>> {
+        if fix && live.is_empty() {
+            // More likely the cluster couldn't be read than the host has no osds
+            return Err(BynarError::from(
+                "No live osds found on this host.  Refusing to remove anything",
+            ));
+        }
+        let mut orphans = Vec::new();
+        for artifact in self
+            .host_artifacts()?
+            .into_iter()
+            .filter(|a| is_orphaned(a, live, in_use))
+        {
+            // The osd list can be partial.  What's running on the host wins
+            match artifact_running(&artifact) {
+                Ok(false) => {}
+                Ok(true) => {
+                    warn!("{} is still in use.  Skipping", artifact);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Unable to tell if {} is in use: {:?}", artifact, e);
+                    continue;
+                }
+            }
+            if fix {
+                if let Err(e) = self.remove_artifact(&artifact, false) {
+                    error!("Unable to remove {}: {:?}", artifact, e);
+                    orphans.push(format!("{} (removing it failed: {})", artifact, e));
+                    continue;
+                }
+            }
+            orphans.push(artifact.to_string());
+        }
+        Ok(orphans)
+    }
+
+    // Drop the recorded artifacts of osds that aren't live anymore
+    fn forget_artifacts(&self, live: &[u64]) -> BynarResult<()> {
+        if live.is_empty() {
+            // Nothing to tell the live osds apart by
+            return Ok(());
+        }
+        let mut artifacts: Vec<TrackedArtifact> = load_state(&self.config.artifact_state_file)?;
+        artifacts.retain(|t| live.contains(&t.osd_id));
+        save_state(&self.config.artifact_state_file, &artifacts)
+    }
+
+    // The ids of the osds osd metadata puts on this host
+    fn host_osd_ids(&self) -> BynarResult<Vec<u64>> {
+        let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+        let cmd = json!({
+            "prefix": "osd metadata",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let osd_metadata: Value = serde_json::from_slice(&result.0)?;
+        Ok(host_osds(&osd_metadata, &host)
+            .into_iter()
+            .map(|(osd_id, _)| osd_id)
+            .collect())
+    }
+
+    // The osds on this host that aren't destroyed and the devices they use
+    fn live_osd_devices(&self) -> BynarResult<(Vec<u64>, Vec<PathBuf>)> {
+        let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+        let cmd = json!({
+            "prefix": "osd metadata",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let osd_metadata: Value = serde_json::from_slice(&result.0)?;
+        let osd_dump = osd_dump_json(&self.cluster_handle)?;
+        let mut live = Vec::new();
+        let mut in_use: Vec<PathBuf> = Vec::new();
+        for (osd_id, devices) in host_osds(&osd_metadata, &host) {
+            if osd_has_state(&osd_dump, osd_id, "destroyed") {
+                continue;
+            }
+            live.push(osd_id);
+            in_use.extend(devices.iter().map(|d| Path::new("/dev").join(d)));
+        }
+        // The journal devices keep their rules while they have free space
+        if let Some(journals) = &self.config.journal_devices {
+            in_use.extend(journals.iter().map(|j| j.device.clone()));
+        }
+        Ok((live, in_use))
+    }
+
+    // Every lease on the cluster.  config-key ls only lists the key names
+    fn load_flag_leases(&self) -> BynarResult<Vec<FlagLease>> {
+        let cmd = json!({
+            "prefix": "config-key ls",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let keys: Vec<String> = serde_json::from_slice(&result.0)?;
+        let mut leases = Vec::new();
+        for key in keys {
+            let (holder, flag) = match parse_lease_key(&key) {
+                Some(lease) => lease,
+                None => continue,
+            };
+            let cmd = json!({
+                "prefix": "config-key get",
+                "key": key,
+            });
+            // Released by its holder since the keys were listed
+            let result = match self.cluster_handle.ceph_mon_command_without_data(&cmd) {
+                Ok(result) => result,
+                Err(e) => {
+                    debug!("Unable to get {}: {:?}", key, e);
+                    continue;
+                }
+            };
+            let expires = match String::from_utf8_lossy(&result.0).trim().parse() {
+                Ok(expires) => expires,
+                Err(_) => {
+                    warn!("Lease {} has no expiry.  Treating it as expired", key);
+                    0
+                }
+            };
+            leases.push(FlagLease {
+                flag,
+                holder,
+                expires,
+            });
+        }
+        Ok(leases)
+    }
+
+    fn save_flag_lease(&self, lease: &FlagLease) -> BynarResult<()> {
+        let cmd = json!({
+            "prefix": "config-key set",
+            "key": lease_key(&lease.holder, lease.flag),
+            "val": lease.expires.to_string(),
+        });
+        self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        Ok(())
+    }
+
+    fn remove_flag_lease(&self, lease: &FlagLease) -> BynarResult<()> {
+        let cmd = json!({
+            "prefix": "config-key rm",
+            "key": lease_key(&lease.holder, lease.flag),
+        });
+        self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
         Ok(())
     }
 
-    // set cluster with noscrub and nodeepscrub
-    fn set_noscrub(&self, simulate: bool) -> BynarResult<()> {
-        osd_set(&self.cluster_handle, &OsdOption::NoScrub, false, simulate)?;
-        osd_set(
-            &self.cluster_handle,
-            &OsdOption::NoDeepScrub,
-            false,
-            simulate,
-        )?;
+    // Unset the flags nobody holds.  Another host can take a lease between
+    // listing the leases and unsetting, so look again and set back any flag
+    // that was taken meanwhile
+    fn unset_free_flags(&self, free: &[ClusterFlag]) -> BynarResult<()> {
+        if free.is_empty() {
+            return Ok(());
+        }
+        for flag in free {
+            debug!("Unsetting {:?}", flag);
+            osd_unset(&self.cluster_handle, &flag.option(), false)?;
+        }
+        let now = epoch_secs();
+        for lease in self.load_flag_leases()? {
+            if free.contains(&lease.flag) && lease.expires > now {
+                info!(
+                    "{} took {:?} meanwhile.  Setting it again",
+                    lease.holder, lease.flag
+                );
+                osd_set(&self.cluster_handle, &lease.flag.option(), false, false)?;
+            }
+        }
         Ok(())
     }
 
+    // Set flags, or renew the leases osd_id already holds on them
+    fn hold_flags(&self, osd_id: u64, flags: &[ClusterFlag], simulate: bool) -> BynarResult<()> {
+        if simulate {
+            debug!("Would hold {:?} for osd {}", flags, osd_id);
+            return Ok(());
+        }
+        self.hold_flags_as(&flag_holder(osd_id)?, flags)
+    }
+
+    fn hold_flags_as(&self, holder: &str, flags: &[ClusterFlag]) -> BynarResult<()> {
+        let holder = holder.to_string();
+        let now = epoch_secs();
+        // Record the leases first so a flag is never set without a holder
+        for flag in flags {
+            self.save_flag_lease(&FlagLease {
+                flag: *flag,
+                holder: holder.clone(),
+                expires: now + self.config.flag_lease_secs,
+            })?;
+        }
+        for flag in flags {
+            debug!("{} holds {:?}", holder, flag);
+            osd_set(&self.cluster_handle, &flag.option(), false, false)?;
+        }
+        self.expire_flag_leases()
+    }
+
+    // Give up every flag osd_id holds.  Flags someone else still holds stay set
+    fn release_flags(&self, osd_id: u64, simulate: bool) -> BynarResult<()> {
+        if simulate {
+            debug!("Would release the flags of osd {}", osd_id);
+            return Ok(());
+        }
+        self.release_flags_as(&flag_holder(osd_id)?)
+    }
+
+    fn release_flags_as(&self, holder: &str) -> BynarResult<()> {
+        let mut leases = self.load_flag_leases()?;
+        let (dropped, free) = drop_leases(&mut leases, Some(holder), epoch_secs());
+        for lease in &dropped {
+            self.remove_flag_lease(lease)?;
+        }
+        for lease in &leases {
+            debug!("{:?} is still held by {}", lease.flag, lease.holder);
+        }
+        self.unset_free_flags(&free)
+    }
+
+    // Unset the flags whose holders stopped renewing their leases
+    fn expire_flag_leases(&self) -> BynarResult<()> {
+        let mut leases = self.load_flag_leases()?;
+        let (expired, free) = drop_leases(&mut leases, None, epoch_secs());
+        for lease in &expired {
+            info!("{}'s lease on {:?} expired", lease.holder, lease.flag);
+            self.remove_flag_lease(lease)?;
+        }
+        self.unset_free_flags(&free)
+    }
+
     // get the journal path (if one exists)
     // if the config file journal_path is not None, return None
     fn get_journal_path(
@@ -822,54 +2235,23 @@ impl CephBackend {
 
     // remove a manually provisioned bluestore osd, dev_path should be the first partition path
     fn remove_bluestore_manual(&self, dev_path: &Path, simulate: bool) -> BynarResult<()> {
-        // toggle noscrub/deepscrub flags
-        debug!("Toggle noscrub, nodeep-scrub flags");
-        self.set_noscrub(simulate)?;
         // get the osd id -> it should be {dev_path}2
         let mut part2: String = dev_path.to_string_lossy().to_string();
         part2.push_str("2");
         let part2 = Path::new(&part2);
         let osd_id = get_osd_id_from_device(&self.cluster_handle, part2)?;
-        // gradually weight osd to 0
-        debug!("Check if osd is already out");
-        // check if the osd is out (if so, osd_crush_reweight to 0, else gradual reweight)
-        if self.is_osd_out(osd_id, simulate)? {
-            debug!("OSD already out, reweight osd to 0");
-            osd_crush_reweight(&self.cluster_handle, osd_id, 0.0, simulate)?;
-        } else {
-            debug!("gradually reweight to 0");
-            self.gradual_weight(osd_id, false, simulate)?;
-        }
-        debug!("Checking pgs on osd {:?} until empty", osd_id);
-        loop {
-            if simulate {
-                break;
-            }
-            let cmd = json!({
-                "prefix": "pg ls-by-osd",
-                "name":  format!("osd.{}", osd_id),
-            });
-            let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
-            debug!("PG List {:?}", result.1);
-            if result.1.is_none() {
-                break;
-            }
-        }
+        // toggle noscrub/deepscrub flags
+        debug!("Toggle noscrub, nodeep-scrub flags");
+        self.hold_flags(osd_id, &self.config.held_flags, simulate)?;
+        // remove_disk drained the osd before calling this
         // set the osd out
         debug!("Setting osd {} out", osd_id);
         osd_out(&self.cluster_handle, osd_id, simulate)?;
         // stop the osd
         debug!("Stop osd {}", osd_id);
         systemctl_stop(osd_id, simulate)?;
-        // remove from crush map
-        debug!("Removing osd {} from crush", osd_id);
-        osd_crush_remove(&self.cluster_handle, osd_id, simulate)?;
-        // remove auth key
-        debug!("Deleting osd {} auth key", osd_id);
-        auth_del(&self.cluster_handle, osd_id, simulate)?;
-        // rm osd
-        debug!("Removing osd {}", osd_id);
-        osd_rm(&self.cluster_handle, osd_id, simulate)?;
+        // destroy the osd, keeping its id for the replacement disk
+        self.retire_osd(osd_id, simulate)?;
 
         //unmount the device and clean up
         let mut part1: String = dev_path.to_string_lossy().to_string();
@@ -894,6 +2276,7 @@ impl CephBackend {
 
         //wipe disk
         zap_disk(dev_path, simulate)?;
+        self.remove_artifacts(osd_id, simulate)?;
 
         Ok(())
     }
@@ -950,9 +2333,13 @@ impl CephBackend {
         // List the tags to get the osd id
         let mut osd_id = None;
         let mut osd_fsid = None;
+        let mut encrypted = false;
         for lv in &lvs {
             let tags = lv.get_tags()?;
             debug!("Found tags for logical volume: {:?}", tags);
+            if tags.iter().any(|t| t == "ceph.encrypted=1") {
+                encrypted = true;
+            }
             let id_tag = tags.iter().find(|t| t.starts_with("ceph.osd_id"));
             if let Some(tag) = id_tag {
                 let parts: Vec<String> = tag.split('=').map(ToString::to_string).collect();
@@ -975,44 +2362,21 @@ impl CephBackend {
             )));
         }
         let osd_id = osd_id.unwrap();
+        let osd_fsid = osd_fsid.unwrap();
         debug!("Try to get the journal path");
         let journal_path = self.get_journal_path(osd_id, &osd_config)?;
         debug!("Toggle noscrub, nodeep-scrub flags");
-        self.set_noscrub(simulate)?;
-        debug!("Check if osd is already out");
-        // check if the osd is out (if so, osd_crush_reweight to 0, else gradual reweight)
-        if self.is_osd_out(osd_id, simulate)? {
-            debug!("OSD already out, reweight osd to 0");
-            osd_crush_reweight(&self.cluster_handle, osd_id, 0.0, simulate)?;
-        } else {
-            debug!("gradually reweight to 0");
-            self.gradual_weight(osd_id, false, simulate)?;
-        }
-        debug!("Checking pgs on osd {:?} until empty", osd_id);
-        loop {
-            if simulate {
-                break;
-            }
-            let cmd = json!({
-                "prefix": "pg ls-by-osd",
-                "name":  format!("osd.{}", osd_id),
-            });
-            let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
-            debug!("PG List {:?}", result.1);
-            if result.1.is_none() {
-                break;
-            }
-        }
+        self.hold_flags(osd_id, &self.config.held_flags, simulate)?;
+        // remove_disk drained the osd before calling this
         debug!("Setting osd {} out", osd_id);
         osd_out(&self.cluster_handle, osd_id, simulate)?;
         debug!("Stop osd {}", osd_id);
         systemctl_stop(osd_id, simulate)?;
-        debug!("Removing osd {} from crush", osd_id);
-        osd_crush_remove(&self.cluster_handle, osd_id, simulate)?;
-        debug!("Deleting osd {} auth key", osd_id);
-        auth_del(&self.cluster_handle, osd_id, simulate)?;
-        debug!("Removing osd {}", osd_id);
-        osd_rm(&self.cluster_handle, osd_id, simulate)?;
+        self.retire_osd(osd_id, simulate)?;
+        if encrypted && dmcrypt_path(&osd_fsid).exists() {
+            debug!("Closing the dm-crypt mapping of osd {}", osd_id);
+            luks_close(&osd_fsid, simulate)?;
+        }
 
         // Wipe the disk
         debug!("Erasing disk {}", dev_path.display());
@@ -1042,94 +2406,731 @@ impl CephBackend {
             remove_dir_all(Path::new("/var/lib/ceph/osd/").join(&format!("ceph-{}", osd_id)))?;
         }
 
-        systemctl_disable(osd_id, &osd_fsid.unwrap(), simulate)?;
-        // remove the journal if one exists
+        if encrypted {
+            // Without its key nothing left on the old disk can be read back
+            debug!("Revoking the dm-crypt key of osd {}", osd_id);
+            if !simulate {
+                revoke_dmcrypt_key(&self.config, &osd_fsid)?;
+            }
+        }
+        self.remove_artifacts(osd_id, simulate)?;
+        // remove the journal if one exists.  The journal device may be what failed
         if let Some(journal) = journal_path {
             debug!("Cleaning up journal {:?}", journal.display());
-            self.remove_journal(&journal)?;
-        }
-        Ok(())
-    }
-
-    // check if the osd is out of the cluster
-    fn is_osd_out(&self, osd_id: u64, simulate: bool) -> BynarResult<bool> {
-        let out_tree = osd_tree_status(&self.cluster_handle, ceph::cmd::CrushNodeStatus::Out)?;
-        for node in out_tree.nodes {
-            if node.id as u64 == osd_id {
-                return Ok(true);
+            if let Err(e) = self.remove_journal(&journal) {
+                error!("Unable to remove journal {}: {:?}", journal.display(), e);
             }
         }
-        Ok(false)
+        Ok(())
     }
 
-    fn remove_filestore_osd(&self, dev_path: &Path, simulate: bool) -> BynarResult<()> {
-        //If the OSD is still running we can query its version.  If not then we
-        //should ask either another OSD or a monitor.
-        let osd_id = get_osd_id_from_device(&self.cluster_handle, dev_path)?;
-        debug!("Get the osd config");
-        let osd_config = get_osd_config_by_path(&self.config, dev_path)?;
-        debug!("Try to get the journal path");
-        let journal_path = self.get_journal_path(osd_id, &osd_config)?;
-        debug!("Toggle noscrub, nodeep-scrub flags");
-        self.set_noscrub(simulate)?;
-        debug!("Check id osd is already out");
-        // check if the osd is out (if so, osd_crush_reweight to 0, else gradual reweight)
-        if self.is_osd_out(osd_id, simulate)? {
-            debug!("OSD already out, reweight osd to 0");
-            osd_crush_reweight(&self.cluster_handle, osd_id, 0.0, simulate)?;
-        } else {
-            debug!("gradually reweight to 0");
-            self.gradual_weight(osd_id, false, simulate)?;
-        }
-        debug!("Checking pgs on osd {:?} until empty", osd_id);
-        loop {
-            if simulate {
-                break;
-            }
-            let cmd = json!({
-                "prefix": "pg ls-by-osd",
-                "name":  format!("osd.{}", osd_id),
-            });
-            let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
-            debug!("PG List {:?}", result.1);
-            if result.1.is_none() {
-                break;
-            }
+    // Luminous and later destroy the osd so the replacement disk can take over
+    // its id and crush position.  Older releases don't have osd destroy
+    fn retire_osd(&self, osd_id: u64, simulate: bool) -> BynarResult<()> {
+        if self.version >= CephVersion::Luminous {
+            debug!("Destroying osd {}", osd_id);
+            return osd_destroy(&self.cluster_handle, osd_id, simulate);
         }
-        debug!("Setting osd {} out", osd_id);
-        osd_out(&self.cluster_handle, osd_id, simulate)?;
-        debug!("Stop osd {}", osd_id);
-        systemctl_stop(osd_id, simulate)?;
         debug!("Removing osd {} from crush", osd_id);
         osd_crush_remove(&self.cluster_handle, osd_id, simulate)?;
         debug!("Deleting osd {} auth key", osd_id);
         auth_del(&self.cluster_handle, osd_id, simulate)?;
         debug!("Removing osd {}", osd_id);
         osd_rm(&self.cluster_handle, osd_id, simulate)?;
+        Ok(())
+    }
 
-        // Wipe the disk
-        debug!("Erasing disk {}", dev_path.display());
-        if !simulate {
-            match block_utils::erase_block_device(&dev_path) {
-                Ok(_) => {
-                    debug!("{} erased", dev_path.display());
-                }
-                Err(e) => {
-                    // At this point the disk is about to be replaced anyways
-                    // so this doesn't really matter
-                    error!("{} failed to erase: {:?}", dev_path.display(), e);
-                }
-            };
+    // Create the osd id for a new osd.  A destroyed osd's id is handed
+    // to the new osd with osd new, which needs the osd fsid up front
+    fn create_osd_id(
+        &self,
+        id: Option<u64>,
+        osd_fsid: &uuid::Uuid,
+        simulate: bool,
+    ) -> BynarResult<u64> {
+        if let Some(osd_id) = id {
+            if is_osd_destroyed(&self.cluster_handle, osd_id)? {
+                debug!("Reusing destroyed osd id {}", osd_id);
+                osd_new(&self.cluster_handle, osd_id, osd_fsid, simulate)?;
+                return Ok(osd_id);
+            }
         }
+        Ok(osd_create(&self.cluster_handle, id, simulate)?)
+    }
 
-        // remove the journal device partition if one exists
-        if let Some(journal) = journal_path {
-            debug!("Cleaning up journal {:?}", journal.display());
-            self.remove_journal(&journal)?;
+    // check if the osd is up.  A down osd can't copy its data anywhere
+    fn is_osd_up(&self, osd_id: u64) -> BynarResult<bool> {
+        let up_tree = osd_tree_status(&self.cluster_handle, ceph::cmd::CrushNodeStatus::Up)?;
+        for node in up_tree.nodes {
+            if node.id as u64 == osd_id {
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        let osd_dir = Path::new("/var/lib/ceph/osd/").join(&format!("ceph-{}", osd_id));
-        //unmount the device and clean up
+    // Check removal_policy for an osd.  Returns why the osd can't be removed
+    // right now, or None if the cluster can spare it
+    fn removal_refusal(&self, osd_ids: &[u64]) -> BynarResult<Option<String>> {
+        let policy = &self.config.removal_policy;
+        if policy.refuse_health_err && cluster_health(&self.cluster_handle)? == "HEALTH_ERR" {
+            return Ok(Some("the cluster is HEALTH_ERR".to_string()));
+        }
+        let tree = osd_tree_json(&self.cluster_handle)?;
+        if let Some((bucket, down)) = failure_domain_down(&tree, osd_ids, &policy.failure_domain) {
+            debug!(
+                "{} other osds are down or out in {} {}",
+                down, policy.failure_domain, bucket
+            );
+            if down > policy.max_down_in_failure_domain {
+                return Ok(Some(format!(
+                    "{} other osds in {} {} are already down or out.  The limit is {}",
+                    down, policy.failure_domain, bucket, policy.max_down_in_failure_domain
+                )));
+            }
+        }
+        // The degradation the osds cause themselves doesn't count against them
+        let mut down = false;
+        for osd_id in osd_ids {
+            if !self.is_osd_up(*osd_id)? {
+                down = true;
+            }
+        }
+        let degraded = degraded_ratio(
+            &pg_stat_json(&self.cluster_handle)?,
+            &pg_dump_json(&self.cluster_handle)?,
+            osd_ids,
+            down,
+        );
+        if degraded > policy.max_degraded_ratio {
+            return Ok(Some(format!(
+                "{:.2}% of objects are degraded.  The limit is {:.2}%",
+                degraded * 100.0,
+                policy.max_degraded_ratio * 100.0
+            )));
+        }
+        if policy.check_nearfull {
+            let nearfull = nearfull_ratio(&osd_dump_json(&self.cluster_handle)?);
+            if let Some(fullness) = projected_fullness(&osd_df_json(&self.cluster_handle)?, osd_ids)
+            {
+                if fullness > nearfull {
+                    return Ok(Some(format!(
+                        "the cluster would be {:.1}% full without osd {}.  nearfull_ratio is {:.1}%",
+                        fullness * 100.0,
+                        osd_ids
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                        nearfull * 100.0
+                    )));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // get the osd id of a device.  Manually provisioned bluestore osds
+    // are found by their second partition
+    fn osd_id_for_device(&self, device: &Path) -> BynarResult<u64> {
+        if self.orchestrated {
+            return self
+                .orch_osd_for_device(device)?
+                .ok_or_else(|| BynarError::new(format!("No osd found on {}", device.display())));
+        }
+        let osd_config = get_osd_config_by_path(&self.config, device)?;
+        if !osd_config.is_lvm {
+            let mut part2: String = device.to_string_lossy().to_string();
+            part2.truncate(part2.len() - 1);
+            part2.push_str("2");
+            let part2 = Path::new(&part2);
+            get_osd_id_from_device(&self.cluster_handle, part2)
+        } else {
+            get_osd_id_from_device(&self.cluster_handle, device)
+        }
+    }
+
+    // get the number of pgs still mapped to an osd
+    fn pgs_on_osd(&self, osd_id: u64) -> BynarResult<u64> {
+        let cmd = pg_ls_by_osd_cmd(osd_id);
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let pgs: Value = serde_json::from_slice(&result.0)?;
+        trace!("PG List {:?}", pgs);
+        Ok(count_pgs(&pgs))
+    }
+
+    // Start draining an osd, or check on a drain that's already running.
+    // Returns true once every pg has moved off the osd and the cluster is clean
+    fn drain_osd(&self, device: &Path, osd_id: u64) -> BynarResult<bool> {
+        let mut drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        let remaining = self.pgs_on_osd(osd_id)?;
+        // Renews the lease of a drain that's already running
+        self.hold_flags(osd_id, &self.config.held_flags, false)?;
+        let drain = match drains.iter().find(|d| d.osd_id == osd_id) {
+            Some(drain) => drain,
+            None => {
+                info!("Draining osd {} with {} pgs", osd_id, remaining);
+                // The osd stays up and in so it keeps serving its copy of the data
+                // while the pgs move.  Nothing goes degraded during the drain
+                osd_crush_reweight(&self.cluster_handle, osd_id, 0.0, false)?;
+                drains.push(OsdDrain {
+                    device: device.to_path_buf(),
+                    osd_id,
+                    started: epoch_secs(),
+                    initial_pgs: remaining,
+                });
+                save_state(&self.config.drain_state_file, &drains)?;
+                // The pgs haven't been remapped yet
+                return Ok(false);
+            }
+        };
+        let running = epoch_secs().saturating_sub(drain.started);
+        if running > self.config.drain_timeout_secs {
+            return Err(BynarError::new(format!(
+                "Drain of osd {} has run for {}s with {} of {} pgs left.  It needs a human",
+                osd_id, running, remaining, drain.initial_pgs
+            )));
+        }
+        if remaining > 0 {
+            debug!("osd {} still has {} pgs", osd_id, remaining);
+            return Ok(false);
+        }
+        if !pgs_clean(&pg_stat_json(&self.cluster_handle)?) {
+            debug!(
+                "osd {} is empty.  Waiting for the cluster to be clean",
+                osd_id
+            );
+            return Ok(false);
+        }
+        // Ceph has the final say
+        if !osd_safe_to_destroy(&self.cluster_handle, osd_id) {
+            debug!(
+                "osd {} is drained but ceph doesn't consider it safe to destroy yet",
+                osd_id
+            );
+            return Ok(false);
+        }
+        info!("osd {} is drained", osd_id);
+        Ok(true)
+    }
+
+    // forget about a finished drain
+    fn end_drain(&self, osd_id: u64, simulate: bool) -> BynarResult<()> {
+        if simulate {
+            return Ok(());
+        }
+        let mut drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        drains.retain(|d| d.osd_id != osd_id);
+        save_state(&self.config.drain_state_file, &drains)
+    }
+
+    fn device_in_cluster(&self, device: &Path) -> BynarResult<bool> {
+        if self.orchestrated {
+            return Ok(self.orch_osd_for_device(device)?.is_some());
+        }
+        is_device_in_cluster(&self.cluster_handle, device)
+    }
+
+    // ceph-volume isn't installed on the hosts of a cephadm cluster.  Find the
+    // osd on device from the metadata the osds report.  A destroyed osd is
+    // waiting for a replacement disk and doesn't count
+    fn orch_osd_for_device(&self, device: &Path) -> BynarResult<Option<u64>> {
+        let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+        let cmd = json!({
+            "prefix": "osd metadata",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let osd_metadata: Value = serde_json::from_slice(&result.0)?;
+        let osd_dump = osd_dump_json(&self.cluster_handle)?;
+        Ok(osd_on_device(&osd_metadata, &host, device)
+            .into_iter()
+            .find(|id| !osd_has_state(&osd_dump, *id, "destroyed")))
+    }
+
+    // Ask cephadm for an osd on device.  cephadm gives the new osd the id of
+    // a destroyed osd on this host so a replacement keeps its crush position.
+    // noin is held until the osd is at weight 0 so it can be weighted up.
+    // orch_finish_add picks the osd up once it boots
+    fn orch_add_osd(&self, device: &Path, id: Option<u64>) -> BynarResult<()> {
+        let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+        if let Some(id) = id {
+            // ceph orch daemon add osd can't be told an id.  It only reuses
+            // destroyed ids
+            if !is_osd_destroyed(&self.cluster_handle, id)? {
+                return Err(BynarError::new(format!(
+                    "ceph orch can only give {} osd {} if it's destroyed",
+                    device.display(),
+                    id
+                )));
+            }
+        }
+        self.hold_flags_as(&device_flag_holder(device)?, &[ClusterFlag::NoIn])?;
+        info!("Adding {} with ceph orch", device.display());
+        let cmd = json!({
+            "prefix": "orch daemon add osd",
+            "svc_arg": format!("{}:{}", host, device.display()),
+        });
+        if let Err(e) = self.cluster_handle.ceph_mon_command_without_data(&cmd) {
+            self.release_flags_as(&device_flag_holder(device)?)?;
+            return Err(e.into());
+        }
+        let mut orch_adds: Vec<OrchAdd> = load_state(&self.config.orch_add_state_file)?;
+        orch_adds.retain(|a| a.device != device);
+        orch_adds.push(OrchAdd {
+            device: device.to_path_buf(),
+            osd_id: id,
+            started: epoch_secs(),
+        });
+        save_state(&self.config.orch_add_state_file, &orch_adds)
+    }
+
+    // Look for the osd ceph orch is creating.  Once it boots it's set to
+    // weight 0 and marked in.  None while it hasn't booted yet
+    fn orch_finish_add(&self, add: &OrchAdd) -> BynarResult<Option<u64>> {
+        let holder = device_flag_holder(&add.device)?;
+        // The osd reports its metadata once it boots
+        let osd_id = match self.orch_osd_for_device(&add.device)? {
+            Some(osd_id) => osd_id,
+            None => {
+                let waited = epoch_secs().saturating_sub(add.started);
+                if waited < ORCH_OSD_BOOT_SECS {
+                    debug!(
+                        "Waiting for ceph orch to start an osd on {}",
+                        add.device.display()
+                    );
+                    // Renews the noin lease
+                    self.hold_flags_as(&holder, &[ClusterFlag::NoIn])?;
+                    return Ok(None);
+                }
+                self.end_orch_add(&add.device)?;
+                self.release_flags_as(&holder)?;
+                return Err(BynarError::new(format!(
+                    "ceph orch didn't start an osd on {} in {}s",
+                    add.device.display(),
+                    waited
+                )));
+            }
+        };
+        if let Some(wanted) = add.osd_id {
+            if wanted != osd_id {
+                self.end_orch_add(&add.device)?;
+                self.release_flags_as(&holder)?;
+                return Err(BynarError::new(format!(
+                    "ceph orch gave {} osd {} instead of osd {}",
+                    add.device.display(),
+                    osd_id,
+                    wanted
+                )));
+            }
+        }
+        let host_info = Host::new()?;
+        debug!("Setting osd {} to weight 0", osd_id);
+        osd_crush_reweight(&self.cluster_handle, osd_id, 0.0, false)?;
+        self.place_osd(osd_id, 0.0, &host_info, false)?;
+        let cmd = json!({
+            "prefix": "osd in",
+            "ids": [osd_id.to_string()],
+        });
+        self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        self.end_orch_add(&add.device)?;
+        self.release_flags_as(&holder)?;
+        Ok(Some(osd_id))
+    }
+
+    // forget about a finished or cancelled orch add
+    fn end_orch_add(&self, device: &Path) -> BynarResult<()> {
+        let mut orch_adds: Vec<OrchAdd> = load_state(&self.config.orch_add_state_file)?;
+        orch_adds.retain(|a| a.device != device);
+        save_state(&self.config.orch_add_state_file, &orch_adds)
+    }
+
+    // Have cephadm drain, destroy and zap the osd on device.  --replace keeps
+    // the osd id for the replacement disk.  Returns InProgress until cephadm
+    // is done with it
+    fn orch_remove_osd(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        let osd_id = match drains.iter().find(|d| d.device == device) {
+            Some(drain) => drain.osd_id,
+            None => {
+                let osd_id = match self.orch_osd_for_device(device)? {
+                    Some(osd_id) => osd_id,
+                    None => {
+                        debug!(
+                            "Device {} is already out of the cluster.  Skipping",
+                            device.display()
+                        );
+                        return Ok(OpOutcome::SkipRepeat);
+                    }
+                };
+                info!("Removing osd {} with ceph orch", osd_id);
+                if simulate {
+                    return Ok(OpOutcome::Success);
+                }
+                let initial_pgs = self.pgs_on_osd(osd_id)?;
+                let cmd = json!({
+                    "prefix": "orch osd rm",
+                    "svc_id": [osd_id.to_string()],
+                    "replace": true,
+                });
+                self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+                drains.push(OsdDrain {
+                    device: device.to_path_buf(),
+                    osd_id,
+                    started: epoch_secs(),
+                    initial_pgs,
+                });
+                save_state(&self.config.drain_state_file, &drains)?;
+                return Ok(OpOutcome::InProgress);
+            }
+        };
+        if orch_removal_queued(&orch_osd_rm_status_json(&self.cluster_handle)?, osd_id) {
+            debug!("ceph orch is still removing osd {}", osd_id);
+            return Ok(OpOutcome::InProgress);
+        }
+        self.end_drain(osd_id, simulate)?;
+        if !is_osd_destroyed(&self.cluster_handle, osd_id)? {
+            return Err(BynarError::new(format!(
+                "ceph orch stopped removing osd {} before it was destroyed",
+                osd_id
+            )));
+        }
+        info!("ceph orch destroyed osd {}", osd_id);
+        Ok(OpOutcome::Success)
+    }
+
+    // The osds that can't run without journal.  lvm osds name their wal and
+    // db devices in their lvm tags.  Other osds have a journal, block.wal or
+    // block.db symlink in their osd directory
+    fn journal_dependents(&self, journal: &Path) -> BynarResult<Vec<JournalDependent>> {
+        let mut dependents: Vec<JournalDependent> = Vec::new();
+        debug!("initializing LVM");
+        let lvm = Lvm::new(None)?;
+        lvm.scan()?;
+        let mut vg_pvs: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for vg_name in lvm.get_volume_group_names()? {
+            let vg = lvm.vg_open(&vg_name, &OpenMode::Read)?;
+            let pvs = vg
+                .list_pvs()?
+                .iter()
+                .map(|pv| PathBuf::from(pv.get_name()))
+                .collect();
+            vg_pvs.insert(vg_name, pvs);
+        }
+        for (vg_name, pvs) in &vg_pvs {
+            let vg = lvm.vg_open(vg_name, &OpenMode::Read)?;
+            for lv in vg.list_lvs()? {
+                let tags = lv.get_tags()?;
+                if tag_value(&tags, "ceph.type") != Some("block") {
+                    continue;
+                }
+                let osd_id = match tag_value(&tags, "ceph.osd_id").and_then(|id| id.parse().ok()) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let uses_journal = ["ceph.wal_device", "ceph.db_device"]
+                    .iter()
+                    .filter_map(|t| tag_value(&tags, t))
+                    .any(|dev| on_journal(Path::new(dev), journal, &vg_pvs));
+                // The data disk is the physical volume that isn't the journal
+                let device = pvs.iter().find(|pv| !on_device(pv, journal));
+                if let (true, Some(device)) = (uses_journal, device) {
+                    dependents.push(JournalDependent {
+                        device: device.clone(),
+                        osd_id,
+                    });
+                }
+            }
+        }
+
+        let osd_dir = Path::new("/var/lib/ceph/osd");
+        if osd_dir.exists() {
+            for entry in read_dir(osd_dir)? {
+                let mount_point = entry?.path();
+                let osd_id = match mount_point
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.trim_start_matches("ceph-").parse::<u64>().ok())
+                {
+                    Some(id) => id,
+                    None => continue,
+                };
+                if dependents.iter().any(|d| d.osd_id == osd_id) {
+                    continue;
+                }
+                for link in &["journal", "block.wal", "block.db"] {
+                    let link = mount_point.join(link);
+                    match symlink_metadata(&link) {
+                        Ok(ref meta) if meta.file_type().is_symlink() => {}
+                        _ => continue,
+                    };
+                    // by-partuuid links dangle once the journal device is gone
+                    let target = link.canonicalize().or_else(|_| link.read_link())?;
+                    if on_journal(&target, journal, &vg_pvs) {
+                        dependents.push(JournalDependent {
+                            device: self.osd_data_device(osd_id)?,
+                            osd_id,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        debug!("osds using journal {}: {:?}", journal.display(), dependents);
+        Ok(dependents)
+    }
+
+    // The disk an osd keeps its data on
+    fn osd_data_device(&self, osd_id: u64) -> BynarResult<PathBuf> {
+        let osd_meta = osd_metadata_by_id(&self.cluster_handle, osd_id)?;
+        let partition = match osd_meta.objectstore_meta {
+            ObjectStoreMeta::Bluestore {
+                bluestore_bdev_partition_path,
+                ..
+            } => PathBuf::from(bluestore_bdev_partition_path),
+            ObjectStoreMeta::Filestore {
+                backend_filestore_partition_path,
+                ..
+            } => PathBuf::from(backend_filestore_partition_path),
+        };
+        match block_utils::get_parent_devpath_from_path(&partition)? {
+            Some(parent) => Ok(parent),
+            None => Ok(partition),
+        }
+    }
+
+    fn journal_group(&self, journal: &Path) -> BynarResult<Option<JournalGroup>> {
+        let groups: Vec<JournalGroup> = load_state(&self.config.journal_group_state_file)?;
+        Ok(groups.into_iter().find(|g| g.journal == journal))
+    }
+
+    // Whether a drain, reweight, orch add or journal group in this cluster has device
+    fn tracks_device(&self, device: &Path) -> BynarResult<bool> {
+        let drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        let reweights: Vec<OsdReweight> = load_state(&self.config.reweight_state_file)?;
+        let orch_adds: Vec<OrchAdd> = load_state(&self.config.orch_add_state_file)?;
+        let groups: Vec<JournalGroup> = load_state(&self.config.journal_group_state_file)?;
+        Ok(drains.iter().any(|d| d.device == device)
+            || reweights.iter().any(|r| r.device == device)
+            || orch_adds.iter().any(|a| a.device == device)
+            || groups
+                .iter()
+                .any(|g| g.journal == device || g.osds.iter().any(|osd| osd.device == device)))
+    }
+
+    // The osds a removal of journal takes with it.  Once the removal started
+    // they're whatever was recorded then
+    fn journal_group_osds(&self, journal: &Path) -> BynarResult<Vec<JournalDependent>> {
+        match self.journal_group(journal)? {
+            Some(group) => Ok(group.osds),
+            None => self.journal_dependents(journal),
+        }
+    }
+
+    // A failed journal device takes down every osd that uses it.  Remove them
+    // together and remember them so they're rebuilt on the replacement device.
+    // InProgress until every one of them is removed
+    fn remove_journal_group(&self, journal: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let osds = match self.journal_group(journal)? {
+            Some(group) => group.osds,
+            None => {
+                let osds = self.journal_dependents(journal)?;
+                if osds.is_empty() {
+                    debug!("No osds use journal {}.  Skipping", journal.display());
+                    return Ok(OpOutcome::Skipped);
+                }
+                info!(
+                    "Removing osds {:?} along with journal {}",
+                    osds,
+                    journal.display()
+                );
+                if !simulate {
+                    let mut groups: Vec<JournalGroup> =
+                        load_state(&self.config.journal_group_state_file)?;
+                    groups.push(JournalGroup {
+                        journal: journal.to_path_buf(),
+                        osds: osds.clone(),
+                    });
+                    save_state(&self.config.journal_group_state_file, &groups)?;
+                }
+                osds
+            }
+        };
+        let mut outcome = OpOutcome::Success;
+        for osd in &osds {
+            if self.remove_disk(&osd.device, simulate)? == OpOutcome::InProgress {
+                outcome = OpOutcome::InProgress;
+            }
+        }
+        Ok(outcome)
+    }
+
+    // Rebuild the osds lost with a journal device with their journals on the
+    // replacement.  Each gets its old id back and is weighted up like any new
+    // osd.  InProgress until every one of them is
+    fn add_journal_group(&self, journal: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let group = match self.journal_group(journal)? {
+            Some(group) => group,
+            None => {
+                debug!(
+                    "Device {} is a journal with no osds to rebuild.  Skipping",
+                    journal.display()
+                );
+                return Ok(OpOutcome::Skipped);
+            }
+        };
+        if !simulate {
+            label_disk(journal)?;
+        }
+        let mut outcome = OpOutcome::Success;
+        for osd in &group.osds {
+            let osd_outcome =
+                self.add_osd(&osd.device, Some(osd.osd_id), Some(journal), simulate)?;
+            if osd_outcome == OpOutcome::InProgress {
+                outcome = OpOutcome::InProgress;
+            }
+        }
+        if outcome == OpOutcome::Success && !simulate {
+            info!("Rebuilt the osds of journal {}", journal.display());
+            let mut groups: Vec<JournalGroup> = load_state(&self.config.journal_group_state_file)?;
+            groups.retain(|g| g.journal != journal);
+            save_state(&self.config.journal_group_state_file, &groups)?;
+        }
+        Ok(outcome)
+    }
+
+    // Add an osd on device.  With a journal device the osd's journal goes there
+    fn add_osd(
+        &self,
+        device: &Path,
+        id: Option<u64>,
+        journal: Option<&Path>,
+        simulate: bool,
+    ) -> BynarResult<OpOutcome> {
+        // A new osd that is still being weighted up gets its next step
+        let reweights: Vec<OsdReweight> = load_state(&self.config.reweight_state_file)?;
+        if let Some(reweight) = reweights.iter().find(|r| r.device == device) {
+            return self.continue_reweight(reweight, simulate);
+        }
+        // So does an osd ceph orch is creating.  It's weighted up once it boots
+        let orch_adds: Vec<OrchAdd> = load_state(&self.config.orch_add_state_file)?;
+        if let Some(add) = orch_adds.iter().find(|a| a.device == device) {
+            if simulate {
+                return Ok(OpOutcome::InProgress);
+            }
+            if let Some(osd_id) = self.orch_finish_add(add)? {
+                self.start_reweight(device, osd_id)?;
+            }
+            return Ok(OpOutcome::InProgress);
+        }
+        // check if the osd id, if given, is already in the cluster.
+        // A destroyed osd's id is free for the replacement disk
+        match id {
+            Some(osd_id) => {
+                if is_osd_id_in_cluster(&self.cluster_handle, osd_id)
+                    && !is_osd_destroyed(&self.cluster_handle, osd_id)?
+                {
+                    error!("Osd ID {} is already in the cluster. Skipping", osd_id);
+                    return Ok(OpOutcome::Skipped);
+                }
+            }
+            None => {}
+        }
+        // check if the disk is already in the cluster
+        if self.device_in_cluster(device)? {
+            debug!(
+                "Device {} is already in the cluster.  Skipping",
+                device.display()
+            );
+            return Ok(OpOutcome::SkipRepeat);
+        }
+        if self.orchestrated && simulate {
+            debug!("Simulating adding {} with ceph orch", device.display());
+            return Ok(OpOutcome::Success);
+        }
+        let osd_id = if self.orchestrated {
+            // disk-manager calls add_disk again until the osd boots
+            self.orch_add_osd(device, id)?;
+            return Ok(OpOutcome::InProgress);
+        } else if self.version >= CephVersion::Luminous {
+            self.add_bluestore_osd(device, id, journal, simulate)?
+        } else {
+            self.add_filestore_osd(device, id, journal, simulate)?
+        };
+        if simulate {
+            return Ok(OpOutcome::Success);
+        }
+        // The osd joined with weight 0.  disk-manager calls add_disk again
+        // until it's weighted up
+        self.start_reweight(device, osd_id)?;
+        Ok(OpOutcome::InProgress)
+    }
+
+    // Whether the cluster can take a new osd
+    fn ready_for_add(&self, device: &Path) -> BynarResult<bool> {
+        let health = cluster_health(&self.cluster_handle)?;
+        if health == "HEALTH_ERR" {
+            error!(
+                "Cluster is HEALTH_ERR.  Not safe to add {}",
+                device.display()
+            );
+            return Ok(false);
+        }
+        let backfill = self.get_current_backfill()?;
+        if backfill >= self.config.backfill_cap {
+            error!(
+                "{} pgs are already backfilling.  Not safe to add {}",
+                backfill,
+                device.display()
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn remove_filestore_osd(&self, dev_path: &Path, simulate: bool) -> BynarResult<()> {
+        //If the OSD is still running we can query its version.  If not then we
+        //should ask either another OSD or a monitor.
+        let osd_id = get_osd_id_from_device(&self.cluster_handle, dev_path)?;
+        debug!("Get the osd config");
+        let osd_config = get_osd_config_by_path(&self.config, dev_path)?;
+        debug!("Try to get the journal path");
+        let journal_path = self.get_journal_path(osd_id, &osd_config)?;
+        debug!("Toggle noscrub, nodeep-scrub flags");
+        self.hold_flags(osd_id, &self.config.held_flags, simulate)?;
+        // remove_disk drained the osd before calling this
+        debug!("Setting osd {} out", osd_id);
+        osd_out(&self.cluster_handle, osd_id, simulate)?;
+        debug!("Stop osd {}", osd_id);
+        systemctl_stop(osd_id, simulate)?;
+        self.retire_osd(osd_id, simulate)?;
+
+        // Wipe the disk
+        debug!("Erasing disk {}", dev_path.display());
+        if !simulate {
+            match block_utils::erase_block_device(&dev_path) {
+                Ok(_) => {
+                    debug!("{} erased", dev_path.display());
+                }
+                Err(e) => {
+                    // At this point the disk is about to be replaced anyways
+                    // so this doesn't really matter
+                    error!("{} failed to erase: {:?}", dev_path.display(), e);
+                }
+            };
+        }
+
+        // remove the journal device partition if one exists.  The journal
+        // device may be what failed
+        if let Some(journal) = journal_path {
+            debug!("Cleaning up journal {:?}", journal.display());
+            if let Err(e) = self.remove_journal(&journal) {
+                error!("Unable to remove journal {}: {:?}", journal.display(), e);
+            }
+        }
+
+        let osd_dir = Path::new("/var/lib/ceph/osd/").join(&format!("ceph-{}", osd_id));
+        //unmount the device and clean up
         block_utils::unmount_device(&dev_path)?;
         if osd_dir.exists() {
             debug!("Cleaning up /var/lib/ceph/osd/ceph-{}", osd_id);
@@ -1143,6 +3144,7 @@ impl CephBackend {
                 }
             };
         }
+        self.remove_artifacts(osd_id, simulate)?;
         Ok(())
     }
 
@@ -1168,8 +3170,9 @@ impl CephBackend {
         }
     }
 
-    // Find the journal device that has enough free space
-    fn select_journal(&self) -> BynarResult<Option<JournalDevice>> {
+    // Find the journal device that has enough free space.  only limits the
+    // choice to one of the journal devices
+    fn select_journal(&self, only: Option<&Path>) -> BynarResult<Option<JournalDevice>> {
         let journal_size = u64::from_str(&self.cluster_handle.config_get("osd_journal_size")?)?;
         // The config file uses MB as the journal size
         let journal_size_mb = journal_size * 1024 * 1024;
@@ -1178,6 +3181,9 @@ impl CephBackend {
             .journal_devices
             .clone()
             .unwrap_or_else(|| vec![]);
+        if let Some(only) = only {
+            journal_devices.retain(|j| j.device == only);
+        }
         // Sort by number of partitions
         journal_devices.sort_by_key(|j| j.num_partitions);
         // Clear any space that we can
@@ -1207,8 +3213,41 @@ impl CephBackend {
         }
     }
 
-    //Measure latency using Rados' benchmark command
+    // Measure latency in ms with the configured latency source
     fn get_latency(&self) -> BynarResult<f64> {
+        match self.config.latency_source {
+            LatencySource::OsdPerf => {
+                let latencies = osd_latencies(&osd_perf_json(&self.cluster_handle)?);
+                let latency = percentile(&latencies, self.config.latency_percentile);
+                info!(
+                    "p{} latency of {} osds is {} ms",
+                    self.config.latency_percentile,
+                    latencies.len(),
+                    latency
+                );
+                Ok(latency)
+            }
+            LatencySource::RadosBench => self.rados_bench_latency(),
+        }
+    }
+
+    // get the client operations per second on pool_name
+    fn get_client_ops(&self) -> BynarResult<u64> {
+        let cmd = json!({
+            "prefix": "osd pool stats",
+            "pool_name": self.config.pool_name,
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let stats: Value = serde_json::from_slice(&result.0)?;
+        trace!("Pool stats {:?}", stats);
+        let ops = client_ops(&stats);
+        debug!("{} client ops/s on pool {}", ops, self.config.pool_name);
+        Ok(ops)
+    }
+
+    //Measure latency using Rados' benchmark command
+    fn rados_bench_latency(&self) -> BynarResult<f64> {
         let output_child = Command::new("rados")
             .args(&[
                 "-p",
@@ -1284,129 +3323,119 @@ impl CephBackend {
         )));
     }
 
-    // incrementally weight the osd. return true if reweight ongoing, false if finished
-    fn incremental_weight_osd(
-        &self,
-        osd_id: u64,
-        is_add: bool,
-        simulate: bool,
-    ) -> BynarResult<bool> {
-        let latency_cap = self.config.latency_cap;
-        let backfill_cap = self.config.backfill_cap;
-        let increment = self.config.increment;
-        let target_weight = if is_add {
-            self.config.target_weight
-        } else {
-            0.0
-        };
+    // Move the osd one increment towards target_weight.  Nothing changes while
+    // the cluster is too busy, the next call tries again.  Returns true once the
+    // osd is at target_weight
+    fn reweight_step(&self, osd_id: u64, target_weight: f64) -> BynarResult<bool> {
         let crush_tree = osd_tree(&self.cluster_handle)?;
-
         let current_weight = self.get_current_weight(crush_tree, osd_id)?;
-        if current_weight >= target_weight - 0.00001 && current_weight <= target_weight + 0.00001 {
-            self.unset_noscrub(simulate)?;
-            debug!("incremental weight done");
+        if (current_weight - target_weight).abs() <= 0.00001 {
+            debug!("osd {} is at weight {}", osd_id, target_weight);
+            return Ok(true);
+        }
+        let current_backfill = self.get_current_backfill()?;
+        if current_backfill > self.config.backfill_cap {
+            warn!(
+                "Too many backfilling PGs {}, cap is {}.  Not reweighting osd {}",
+                current_backfill, self.config.backfill_cap, osd_id
+            );
             return Ok(false);
         }
-        trace!(
-            "incrementally weight osd.{} by increment {} (target weight {})",
-            osd_id,
-            increment,
-            target_weight
-        );
-
-        while {
-            let current_backfill = self.get_current_backfill()?;
-            if current_backfill > backfill_cap {
-                warn!(
-                    "Too many backfilling PGs {}, cap is {}",
-                    current_backfill, backfill_cap
-                );
-            }
-            current_backfill > backfill_cap
-        } {}
-
-        while {
-            let current_latency = self.get_latency()?;
-            if current_latency > latency_cap {
+        let current_latency = self.get_latency()?;
+        if current_latency > self.config.latency_cap {
+            warn!(
+                "Latency is {} ms, cap is {} ms.  Not reweighting osd {}",
+                current_latency, self.config.latency_cap, osd_id
+            );
+            return Ok(false);
+        }
+        if let Some(client_ops_cap) = self.config.client_ops_cap {
+            let current_ops = self.get_client_ops()?;
+            if current_ops > client_ops_cap {
                 warn!(
-                    "Latency on pool {} is {} ms, cap is {} ms",
-                    self.config.pool_name, current_latency, latency_cap
+                    "Pool {} has {} client ops/s, cap is {}.  Not reweighting osd {}",
+                    self.config.pool_name, current_ops, client_ops_cap, osd_id
                 );
+                return Ok(false);
             }
-            current_latency > latency_cap
-        } {
-            std::thread::sleep(Duration::from_secs(3));
         }
-        //get the new weight
-        let new_weight = if is_add {
-            target_weight.min(current_weight + increment)
-        } else {
-            target_weight.max(current_weight - increment)
-        };
+        let new_weight = next_weight(current_weight, target_weight, self.config.increment);
         trace!("reweight osd.{} to {}", osd_id, new_weight);
-
-        osd_crush_reweight(&self.cluster_handle, osd_id, new_weight, simulate)?;
-
-        Ok(true)
+        osd_crush_reweight(&self.cluster_handle, osd_id, new_weight, false)?;
+        Ok(false)
     }
-    // weight the osd slowly to the target weight so as not to introduce too
-    // much latency into the cluster
-    fn gradual_weight(&self, osd_id: u64, is_add: bool, simulate: bool) -> BynarResult<()> {
-        let crush_tree = osd_tree(&self.cluster_handle)?;
+
+    // Record a new osd so later add_disk calls weight it up to the target
+    // weight so as not to introduce too much latency into the cluster
+    fn start_reweight(&self, device: &Path, osd_id: u64) -> BynarResult<()> {
         debug!("Gradually weighting osd: {}", osd_id);
-        //set noscrub (remember to handle error by unsetting noscrub)
-        self.set_noscrub(simulate)?;
-        while (self.incremental_weight_osd(osd_id, is_add, simulate)?) {
-            trace!("incrementally reweighting osd");
+        self.hold_flags(osd_id, &self.config.held_flags, false)?;
+        let mut reweights: Vec<OsdReweight> = load_state(&self.config.reweight_state_file)?;
+        reweights.retain(|r| r.device != device);
+        reweights.push(OsdReweight {
+            device: device.to_path_buf(),
+            osd_id,
+            started: epoch_secs(),
+            target_weight: self.config.target_weight,
+        });
+        save_state(&self.config.reweight_state_file, &reweights)
+    }
+
+    // Take the next step of a reweight.  A restarted disk-manager picks up at
+    // whatever weight the osd has in the crush map
+    fn continue_reweight(&self, reweight: &OsdReweight, simulate: bool) -> BynarResult<OpOutcome> {
+        if simulate {
+            return Ok(OpOutcome::InProgress);
         }
-        Ok(())
+        self.hold_flags(reweight.osd_id, &self.config.held_flags, false)?;
+        if !self.reweight_step(reweight.osd_id, reweight.target_weight)? {
+            return Ok(OpOutcome::InProgress);
+        }
+        info!(
+            "osd {} reached weight {}",
+            reweight.osd_id, reweight.target_weight
+        );
+        self.end_reweight(&reweight.device)?;
+        self.release_flags(reweight.osd_id, false)?;
+        Ok(OpOutcome::Success)
+    }
+
+    // forget about a finished or cancelled reweight
+    fn end_reweight(&self, device: &Path) -> BynarResult<()> {
+        let mut reweights: Vec<OsdReweight> = load_state(&self.config.reweight_state_file)?;
+        reweights.retain(|r| r.device != device);
+        save_state(&self.config.reweight_state_file, &reweights)
     }
 }
 
 impl Backend for CephBackend {
     fn add_disk(&self, device: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
         debug!("ceph version: {:?}", self.version,);
-        // check if the disk is a system disk or journal disk first and skip evaluation if so.
-        if is_system_disk(&self.config.system_disks, device)
-            || is_journal(&self.config.journal_devices, device)
-        {
+        // check if the disk is a system disk first and skip evaluation if so.
+        if is_system_disk(&self.config.system_disks, device) {
             debug!("Device {} is not an OSD.  Skipping", device.display());
             return Ok(OpOutcome::Skipped);
         }
-        // check if the osd id, if given, is already in the cluster
-        match id {
-            Some(osd_id) => {
-                if is_osd_id_in_cluster(&self.cluster_handle, osd_id) {
-                    error!("Osd ID {} is already in the cluster. Skipping", osd_id);
-                    return Ok(OpOutcome::Skipped);
-                }
-            }
-            None => {}
-        }
-        // check if the disk is already in the cluster
-        if is_device_in_cluster(&self.cluster_handle, device)? {
-            debug!(
-                "Device {} is already in the cluster.  Skipping",
-                device.display()
-            );
-            return Ok(OpOutcome::SkipRepeat);
-        }
-        if self.version >= CephVersion::Luminous {
-            self.add_bluestore_osd(device, id, simulate)?;
-        } else {
-            self.add_filestore_osd(device, id, simulate)?;
+        // A replaced journal device gets its osds back
+        if is_journal(&self.config.journal_devices, device) {
+            return self.add_journal_group(device, simulate);
         }
-        Ok(OpOutcome::Success)
+        self.add_osd(device, id, None, simulate)
     }
 
     fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
-        // check if the disk is a system disk or journal disk first and skip evaluation if so.
-        if is_system_disk(&self.config.system_disks, device)
-            || is_journal(&self.config.journal_devices, device)
-        {
+        // check if the disk is a system disk first and skip evaluation if so.
+        if is_system_disk(&self.config.system_disks, device) {
             debug!("Device {} is not an OSD.  Skipping", device.display());
             return Ok(OpOutcome::Skipped);
         }
+        // Every osd using a journal device goes with it
+        if is_journal(&self.config.journal_devices, device) {
+            return self.remove_journal_group(device, simulate);
+        }
+        if self.orchestrated {
+            return self.orch_remove_osd(device, simulate);
+        }
         //check if manual bluestore
         let osd_config = get_osd_config_by_path(&self.config, device)?;
         let path_check;
@@ -1426,64 +3455,1240 @@ impl Backend for CephBackend {
             );
             return Ok(OpOutcome::SkipRepeat);
         }
+        // Move the data off the osd before destroying it.  disk-manager
+        // calls remove_disk again until the drain finishes
+        let osd_id = self.osd_id_for_device(device)?;
+        if !simulate && !self.drain_osd(device, osd_id)? {
+            return Ok(OpOutcome::InProgress);
+        }
         if self.version >= CephVersion::Luminous {
             // Check if the type file exists
             match self.remove_bluestore_osd(device, simulate) {
                 Ok(_) => {
-                    self.unset_noscrub(simulate)?;
+                    self.release_flags(osd_id, simulate)?;
                 }
                 Err(e) => {
                     error!("{:?}", e);
-                    self.unset_noscrub(simulate)?;
+                    self.release_flags(osd_id, simulate)?;
                     return Err(e);
                 }
             };
         } else {
             match self.remove_filestore_osd(device, simulate) {
                 Ok(_) => {
-                    self.unset_noscrub(simulate)?;
+                    self.release_flags(osd_id, simulate)?;
                 }
                 Err(e) => {
                     error!("{:?}", e);
-                    self.unset_noscrub(simulate)?;
+                    self.release_flags(osd_id, simulate)?;
                     return Err(e);
                 }
             };
         }
+        self.end_drain(osd_id, simulate)?;
         Ok(OpOutcome::Success)
     }
 
     fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
-        // check if the disk is a system disk or journal disk first and skip evaluation if so.
-        if is_system_disk(&self.config.system_disks, device)
-            || is_journal(&self.config.journal_devices, device)
-        {
+        // check if the disk is a system disk first and skip evaluation if so.
+        if is_system_disk(&self.config.system_disks, device) {
             debug!("Device {} is not an OSD.  Skipping", device.display());
             return Ok((OpOutcome::Skipped, false));
         }
-        //check if manual bluestore
-        let osd_config = get_osd_config_by_path(&self.config, device)?;
-        let osd_id;
-        if !osd_config.is_lvm {
-            let mut part2: String = device.to_string_lossy().to_string();
-            part2.truncate(part2.len() - 1);
-            part2.push_str("2");
-            let part2 = Path::new(&part2);
-            debug!("CHECKING PATH {}", part2.display());
-            //get the osd id
-            osd_id = get_osd_id_from_device(&self.cluster_handle, part2)?;
+        if is_journal(&self.config.journal_devices, device) {
+            let osds = self.journal_group_osds(device)?;
+            if osds.is_empty() {
+                debug!("No osds use journal {}.  Skipping", device.display());
+                return Ok((OpOutcome::Skipped, false));
+            }
+            // Each osd on the journal has to be safe to lose.  The ones
+            // still up have to be drained first
+            let mut outcome = OpOutcome::Success;
+            for osd in &osds {
+                if osd_safe_to_destroy(&self.cluster_handle, osd.osd_id) {
+                    continue;
+                }
+                if !self.is_osd_up(osd.osd_id)? {
+                    debug!(
+                        "osd {} on journal {} isn't safe to destroy",
+                        osd.osd_id,
+                        device.display()
+                    );
+                    return Ok((OpOutcome::Success, false));
+                }
+                outcome = OpOutcome::InProgress;
+            }
+            return Ok((outcome, outcome == OpOutcome::Success));
+        }
+        let osd_id = self.osd_id_for_device(device)?;
+        // create and send the command to check if the osd is safe to remove.
+        // A down osd is only safe once the cluster recovered without it
+        if osd_safe_to_destroy(&self.cluster_handle, osd_id) {
+            return Ok((OpOutcome::Success, true));
+        }
+        // An osd that is still up isn't safe yet, but draining it makes it safe
+        // without anything going degraded.  remove_disk destroys it once
+        // the drain finishes and ceph agrees
+        if self.is_osd_up(osd_id)? {
+            debug!("osd {} has to be drained before it's removed", osd_id);
+            return Ok((OpOutcome::InProgress, false));
+        }
+        Ok((OpOutcome::Success, false))
+    }
+
+    fn check_removal_policy(&self, device: &Path) -> BynarResult<Option<String>> {
+        if is_system_disk(&self.config.system_disks, device) {
+            // safe_to_remove skips these
+            return Ok(None);
+        }
+        if is_journal(&self.config.journal_devices, device) {
+            // The osds on a journal fail together.  They don't count against
+            // each other in their failure domain
+            let osd_ids: Vec<u64> = self
+                .journal_group_osds(device)?
+                .iter()
+                .map(|osd| osd.osd_id)
+                .collect();
+            if osd_ids.is_empty() {
+                return Ok(None);
+            }
+            return self.removal_refusal(&osd_ids);
+        }
+        let osd_id = self.osd_id_for_device(device)?;
+        self.removal_refusal(&[osd_id])
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        // check if the disk is a system disk first and skip evaluation if so.
+        if is_system_disk(&self.config.system_disks, device) {
+            debug!("Device {} is not an OSD.  Skipping", device.display());
+            return Ok((OpOutcome::Skipped, false));
+        }
+        if is_journal(&self.config.journal_devices, device) {
+            if self.journal_group(device)?.is_none() {
+                debug!(
+                    "No osds to rebuild on journal {}.  Skipping",
+                    device.display()
+                );
+                return Ok((OpOutcome::Skipped, false));
+            }
+            // The osds go back on the disks they were on
+            return Ok((OpOutcome::Success, simulate || self.ready_for_add(device)?));
+        }
+        if self.device_in_cluster(device)? {
+            debug!(
+                "Device {} is already in the cluster.  Skipping",
+                device.display()
+            );
+            return Ok((OpOutcome::SkipRepeat, false));
+        }
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        if !self.ready_for_add(device)? {
+            return Ok((OpOutcome::Success, false));
+        }
+        // crush weights are by convention the size of the osd in TiB.  Allow
+        // some slack for the difference between marketed and usable size
+        let size_tib = get_device_size(device)? as f64 / (1u64 << 40) as f64;
+        if size_tib < self.config.target_weight * 0.9 {
+            error!(
+                "{} is {:.2}TiB which is too small for target weight {}",
+                device.display(),
+                size_tib,
+                self.config.target_weight
+            );
+            return Ok((OpOutcome::Success, false));
+        }
+        Ok((OpOutcome::Success, true))
+    }
+
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>> {
+        let drains: Vec<OsdDrain> = load_state(&self.config.drain_state_file)?;
+        if let Some(drain) = drains.iter().find(|d| d.device == device) {
+            let remaining = self.pgs_on_osd(drain.osd_id)?;
+            return Ok(Some(drain_progress(drain, remaining, epoch_secs())));
+        }
+        let reweights: Vec<OsdReweight> = load_state(&self.config.reweight_state_file)?;
+        if let Some(reweight) = reweights.iter().find(|r| r.device == device) {
+            let crush_tree = osd_tree(&self.cluster_handle)?;
+            let weight = self.get_current_weight(crush_tree, reweight.osd_id)?;
+            return Ok(Some(reweight_progress(reweight, weight, epoch_secs())));
+        }
+        Ok(None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(true);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(true);
+        capabilities.set_gradual_weighting(true);
+        capabilities.set_progress(true);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        Some(format!("{:?}", self.version))
+    }
+
+    fn disk_id(&self, device: &Path) -> BynarResult<Option<u64>> {
+        if is_journal(&self.config.journal_devices, device) {
+            return Ok(None);
+        }
+        Ok(Some(self.osd_id_for_device(device)?))
+    }
+
+    fn dependent_disks(&self, device: &Path) -> BynarResult<Vec<PathBuf>> {
+        if !is_journal(&self.config.journal_devices, device) {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .journal_group_osds(device)?
+            .into_iter()
+            .map(|osd| osd.device)
+            .collect())
+    }
+
+    fn host_hygiene(&self, fix: bool) -> BynarResult<Vec<String>> {
+        let (live, in_use) = self.live_osd_devices()?;
+        let orphans = self.orphaned_artifacts(&live, &in_use, fix)?;
+        if fix {
+            self.forget_artifacts(&live)?;
+        }
+        Ok(orphans)
+    }
+
+    fn suspect_disks(&self) -> BynarResult<Vec<SuspectDisk>> {
+        self.find_suspects()
+    }
+
+    fn blocking_pgs(&self, device: &Path) -> BynarResult<Vec<BlockingPg>> {
+        if is_system_disk(&self.config.system_disks, device) {
+            return Ok(Vec::new());
+        }
+        let osd_ids: Vec<u64> = if is_journal(&self.config.journal_devices, device) {
+            self.journal_group_osds(device)?
+                .iter()
+                .map(|osd| osd.osd_id)
+                .collect()
         } else {
-            //get the osd id
-            osd_id = get_osd_id_from_device(&self.cluster_handle, device)?;
+            vec![self.osd_id_for_device(device)?]
+        };
+        if osd_ids.is_empty() {
+            return Ok(Vec::new());
         }
-        // create and send the command to check if the osd is safe to remove
-        Ok((
-            OpOutcome::Success,
-            osd_safe_to_destroy(&self.cluster_handle, osd_id),
-        ))
+        // Only the pgs on the osds can lose a copy
+        let mut pgs: Vec<Value> = Vec::new();
+        for osd_id in &osd_ids {
+            let cmd = pg_ls_by_osd_cmd(*osd_id);
+            let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+            let osd_pgs: Value = serde_json::from_slice(&result.0)?;
+            if let Some(osd_pgs) = osd_pgs.get("pg_stats").unwrap_or(&osd_pgs).as_array() {
+                for pg in osd_pgs {
+                    if !pgs.iter().any(|p| p["pgid"] == pg["pgid"]) {
+                        pgs.push(pg.clone());
+                    }
+                }
+            }
+        }
+        let pgs = Value::Array(pgs);
+        let cmd = json!({
+            "prefix": "osd pool ls",
+            "detail": "detail",
+            "format": "json",
+        });
+        let result = self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+        let pools: Value = serde_json::from_slice(&result.0)?;
+        let blocking = pgs_blocking_removal(&pgs, &pools, &osd_ids);
+        debug!(
+            "{} pgs would go below min_size without {}",
+            blocking.len(),
+            device.display()
+        );
+        Ok(blocking)
+    }
+
+    fn cancel_operation(&self, device: &Path) -> BynarResult<()> {
+        // The osds of a journal group stay wherever they got to.  Removing
+        // or adding the journal again picks the group back up
+        if let Some(group) = self.journal_group(device)? {
+            for osd in &group.osds {
+                self.cancel_operation(&osd.device)?;
+            }
+            return Ok(());
+        }
+        // ceph orch can't be stopped once it's asked for an osd.  One that
+        // boots after this joins like an osd created by hand
+        let (abandoned, orch_adds): (Vec<OrchAdd>, Vec<OrchAdd>) =
+            load_state::<OrchAdd>(&self.config.orch_add_state_file)?
+                .into_iter()
+                .partition(|a| a.device == device);
+        if !abandoned.is_empty() {
+            info!("Cancelling the ceph orch add of {}", device.display());
+            save_state(&self.config.orch_add_state_file, &orch_adds)?;
+            self.release_flags_as(&device_flag_holder(device)?)?;
+            return Ok(());
+        }
+        // The osd keeps whatever weight the drain or reweight got it to.
+        // A cancelled drain starts over if the disk is removed again
+        let (cancelled, drains): (Vec<OsdDrain>, Vec<OsdDrain>) =
+            load_state::<OsdDrain>(&self.config.drain_state_file)?
+                .into_iter()
+                .partition(|d| d.device == device);
+        let (unweighted, reweights): (Vec<OsdReweight>, Vec<OsdReweight>) =
+            load_state::<OsdReweight>(&self.config.reweight_state_file)?
+                .into_iter()
+                .partition(|r| r.device == device);
+        if cancelled.is_empty() && unweighted.is_empty() {
+            debug!("Nothing is running on {}", device.display());
+            return Ok(());
+        }
+        info!("Cancelling the drain or reweight of {}", device.display());
+        if self.orchestrated && !cancelled.is_empty() {
+            let rm_status = orch_osd_rm_status_json(&self.cluster_handle)?;
+            for drain in cancelled
+                .iter()
+                .filter(|d| orch_removal_queued(&rm_status, d.osd_id))
+            {
+                // The osd keeps the weight cephadm drained it to
+                let cmd = json!({
+                    "prefix": "orch osd rm stop",
+                    "svc_id": [drain.osd_id.to_string()],
+                });
+                self.cluster_handle.ceph_mon_command_without_data(&cmd)?;
+            }
+        }
+        save_state(&self.config.drain_state_file, &drains)?;
+        save_state(&self.config.reweight_state_file, &reweights)?;
+        let osd_ids = cancelled
+            .iter()
+            .map(|d| d.osd_id)
+            .chain(unweighted.iter().map(|r| r.osd_id));
+        for osd_id in osd_ids {
+            self.release_flags(osd_id, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl Backend for CephClusters {
+    fn add_disk(&self, device: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        let cluster = self.cluster_for(device)?;
+        if let Some(id) = id {
+            for other in self.clusters.iter().filter(|c| c.name != cluster.name) {
+                if other.host_osd_ids()?.contains(&id) {
+                    return Err(BynarError::new(format!(
+                        "Cluster {} already has osd {} on this host",
+                        other.name, id
+                    )));
+                }
+            }
+        }
+        cluster.add_disk(device, id, simulate)
+    }
+
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        self.cluster_for(device)?.remove_disk(device, simulate)
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        self.cluster_for(device)?.safe_to_remove(device, simulate)
+    }
+
+    fn check_removal_policy(&self, device: &Path) -> BynarResult<Option<String>> {
+        self.cluster_for(device)?.check_removal_policy(device)
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        self.cluster_for(device)?.safe_to_add(device, simulate)
+    }
+
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>> {
+        self.cluster_for(device)?.get_progress(device)
+    }
+
+    // What every cluster can do
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = self.clusters[0].capabilities();
+        for cluster in &self.clusters[1..] {
+            let other = cluster.capabilities();
+            capabilities.set_add_with_id(capabilities.get_add_with_id() && other.get_add_with_id());
+            capabilities.set_partition_create(
+                capabilities.get_partition_create() && other.get_partition_create(),
+            );
+            capabilities.set_simulate(capabilities.get_simulate() && other.get_simulate());
+            capabilities.set_gradual_weighting(
+                capabilities.get_gradual_weighting() && other.get_gradual_weighting(),
+            );
+            capabilities.set_progress(capabilities.get_progress() && other.get_progress());
+        }
+        capabilities
+    }
+
+    fn device_capabilities(&self, device: &Path) -> BynarResult<Capabilities> {
+        Ok(self.cluster_for(device)?.capabilities())
+    }
+
+    fn version(&self) -> Option<String> {
+        if self.clusters.len() == 1 {
+            return self.clusters[0].version();
+        }
+        let versions: Vec<String> = self
+            .clusters
+            .iter()
+            .map(|c| format!("{}: {:?}", c.name, c.version))
+            .collect();
+        Some(versions.join(", "))
+    }
+
+    fn disk_id(&self, device: &Path) -> BynarResult<Option<u64>> {
+        self.cluster_for(device)?.disk_id(device)
+    }
+
+    fn dependent_disks(&self, device: &Path) -> BynarResult<Vec<PathBuf>> {
+        self.cluster_for(device)?.dependent_disks(device)
+    }
+
+    // The clusters share the udev rules, fstab and systemd units so an
+    // artifact is only orphaned if no cluster uses it
+    fn host_hygiene(&self, fix: bool) -> BynarResult<Vec<String>> {
+        if self.clusters.len() == 1 {
+            return self.clusters[0].host_hygiene(fix);
+        }
+        let mut live = Vec::new();
+        let mut in_use = Vec::new();
+        let mut cluster_live = Vec::new();
+        for cluster in &self.clusters {
+            let (osds, devices) = cluster.live_osd_devices()?;
+            live.extend(osds.iter().cloned());
+            in_use.extend(devices);
+            cluster_live.push(osds);
+        }
+        let orphans = self.clusters[0].orphaned_artifacts(&live, &in_use, fix)?;
+        if fix {
+            for (cluster, osds) in self.clusters.iter().zip(cluster_live) {
+                cluster.forget_artifacts(&osds)?;
+            }
+        }
+        Ok(orphans)
+    }
+
+    fn suspect_disks(&self) -> BynarResult<Vec<SuspectDisk>> {
+        let mut suspects = Vec::new();
+        for cluster in &self.clusters {
+            suspects.extend(cluster.suspect_disks()?);
+        }
+        Ok(suspects)
+    }
+
+    fn blocking_pgs(&self, device: &Path) -> BynarResult<Vec<BlockingPg>> {
+        self.cluster_for(device)?.blocking_pgs(device)
+    }
+
+    fn cancel_operation(&self, device: &Path) -> BynarResult<()> {
+        self.cluster_for(device)?.cancel_operation(device)
+    }
+}
+
+// Get the overall health status of the cluster.  ie HEALTH_OK, HEALTH_WARN or HEALTH_ERR
+fn cluster_health(cluster_handle: &Rados) -> BynarResult<String> {
+    let cmd = json!({
+        "prefix": "health",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let health: Value = serde_json::from_slice(&result.0)?;
+    trace!("Cluster health {:?}", health);
+    // Luminous moved overall_status to status
+    match health["status"]
+        .as_str()
+        .or_else(|| health["overall_status"].as_str())
+    {
+        Some(status) => Ok(status.to_string()),
+        None => Err(BynarError::from("Unable to find the cluster health status")),
     }
 }
 
+fn cluster_fsid(cluster_handle: &Rados) -> BynarResult<String> {
+    let cmd = json!({
+        "prefix": "fsid",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let fsid: Value = serde_json::from_slice(&result.0)?;
+    match fsid["fsid"].as_str() {
+        Some(fsid) => Ok(fsid.to_string()),
+        None => Err(BynarError::from("Unable to find the cluster fsid")),
+    }
+}
+
+// Get the latency every osd reports as json
+fn osd_perf_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "osd perf",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let perf: Value = serde_json::from_slice(&result.0)?;
+    trace!("OSD perf {:?}", perf);
+    Ok(perf)
+}
+
+// The latency of each osd in ms, the worse of its commit and apply latency.
+// Nautilus wraps osd_perf_infos in osdstats
+fn osd_latencies(osd_perf: &Value) -> Vec<f64> {
+    osd_latencies_by_id(osd_perf)
+        .into_iter()
+        .map(|(_, latency)| latency)
+        .collect()
+}
+
+fn osd_latencies_by_id(osd_perf: &Value) -> Vec<(u64, f64)> {
+    let perf = osd_perf.get("osdstats").unwrap_or(osd_perf);
+    match perf["osd_perf_infos"].as_array() {
+        Some(infos) => infos
+            .iter()
+            .filter_map(|info| {
+                let stats = &info["perf_stats"];
+                let commit = stats["commit_latency_ms"].as_f64().unwrap_or(0.0);
+                let apply = stats["apply_latency_ms"].as_f64().unwrap_or(0.0);
+                info["id"].as_u64().map(|id| (id, commit.max(apply)))
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+// The osds over factor times the median latency and over min_latency ms
+fn slow_osds(
+    latencies: &[(u64, f64)],
+    median: f64,
+    factor: f64,
+    min_latency: f64,
+) -> Vec<(u64, f64)> {
+    latencies
+        .iter()
+        .filter(|(_, latency)| *latency > min_latency && *latency > median * factor)
+        .cloned()
+        .collect()
+}
+
+// The nearest rank percentile of values.  0.0 if there are none
+fn percentile(values: &[f64], pct: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = (pct * sorted.len() as f64 / 100.0).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+// The client read and write operations per second in osd pool stats output
+fn client_ops(pool_stats: &Value) -> u64 {
+    match pool_stats.as_array() {
+        Some(pools) => pools
+            .iter()
+            .map(|pool| {
+                let io = &pool["client_io_rate"];
+                io["read_op_per_sec"].as_u64().unwrap_or(0)
+                    + io["write_op_per_sec"].as_u64().unwrap_or(0)
+            })
+            .sum(),
+        None => 0,
+    }
+}
+
+#[test]
+fn test_osd_perf_latency() {
+    let luminous = json!({
+        "osd_perf_infos": [
+            {"id": 2, "perf_stats": {"commit_latency_ms": 4, "apply_latency_ms": 9}},
+            {"id": 1, "perf_stats": {"commit_latency_ms": 30, "apply_latency_ms": 2}},
+            {"id": 0, "perf_stats": {"commit_latency_ms": 1, "apply_latency_ms": 1}}
+        ]
+    });
+    assert_eq!(osd_latencies(&luminous), vec![9.0, 30.0, 1.0]);
+    let nautilus = json!({"osdstats": luminous});
+    assert_eq!(osd_latencies(&nautilus), vec![9.0, 30.0, 1.0]);
+    assert!(osd_latencies(&json!({})).is_empty());
+    let latencies = osd_latencies_by_id(&luminous);
+    assert_eq!(slow_osds(&latencies, 9.0, 3.0, 20.0), vec![(1, 30.0)]);
+    // Under the minimum latency nothing is slow
+    assert!(slow_osds(&latencies, 9.0, 3.0, 50.0).is_empty());
+
+    let latencies: Vec<f64> = (1..=20).map(f64::from).collect();
+    // One slow osd out of 20 doesn't hold up p95
+    assert_eq!(percentile(&latencies, 95.0), 19.0);
+    assert_eq!(percentile(&latencies, 100.0), 20.0);
+    assert_eq!(percentile(&latencies, 50.0), 10.0);
+    assert_eq!(percentile(&[], 95.0), 0.0);
+
+    let stats = json!([{
+        "pool_name": "rbd",
+        "pool_id": 1,
+        "recovery": {},
+        "recovery_rate": {},
+        "client_io_rate": {"read_op_per_sec": 120, "write_op_per_sec": 35}
+    }]);
+    assert_eq!(client_ops(&stats), 155);
+    // An idle pool has an empty client_io_rate
+    assert_eq!(
+        client_ops(&json!([{"pool_name": "rbd", "client_io_rate": {}}])),
+        0
+    );
+}
+
+// Get the crush hierarchy with the up/down status and reweight of every osd
+fn osd_tree_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "osd tree",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let tree: Value = serde_json::from_slice(&result.0)?;
+    trace!("OSD tree {:?}", tree);
+    Ok(tree)
+}
+
+// Get the capacity and usage of every osd as json
+fn osd_df_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "osd df",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let df: Value = serde_json::from_slice(&result.0)?;
+    trace!("OSD df {:?}", df);
+    Ok(df)
+}
+
+// Find the failure domain bucket an osd is in and count the other osds in it
+// that are down, out or drained.  None if the osd isn't under a bucket of
+// that type
+fn failure_domain_down(osd_tree: &Value, osd_ids: &[u64], domain: &str) -> Option<(String, u64)> {
+    let nodes = osd_tree["nodes"].as_array()?;
+    let node = |id: i64| nodes.iter().find(|n| n["id"].as_i64() == Some(id));
+    let parent = |id: i64| {
+        nodes.iter().find(|n| {
+            n["children"]
+                .as_array()
+                .map_or(false, |c| c.iter().any(|c| c.as_i64() == Some(id)))
+        })
+    };
+    // Walk up the tree to the failure domain
+    let mut bucket = parent(*osd_ids.first()? as i64)?;
+    while bucket["type"] != domain {
+        bucket = parent(bucket["id"].as_i64()?)?;
+    }
+    // Then down to every osd under it
+    let mut down = 0;
+    let mut pending: Vec<i64> = vec![bucket["id"].as_i64()?];
+    while let Some(id) = pending.pop() {
+        let n = match node(id) {
+            Some(n) => n,
+            None => continue,
+        };
+        if let Some(children) = n["children"].as_array() {
+            pending.extend(children.iter().filter_map(|c| c.as_i64()));
+        } else if !osd_ids.contains(&(id as u64))
+            && (n["status"] != "up"
+                || n["reweight"].as_f64() == Some(0.0)
+                || n["crush_weight"].as_f64() == Some(0.0))
+        {
+            // A crush weight of 0 is an osd being drained
+            down += 1;
+        }
+    }
+    Some((bucket["name"].as_str().unwrap_or("").to_string(), down))
+}
+
+// The fraction of objects that are degraded, leaving out the degraded objects
+// in the pgs of osd_ids.  A down osd already left the up and acting sets of
+// its pgs, so while one of them is down the undersized pgs are taken to be
+// theirs.  Luminous and later wrap the summary in pg_summary and pg dump
+// wraps the pgs in pg_stats
+fn degraded_ratio(pg_stat: &Value, pgs: &Value, osd_ids: &[u64], down: bool) -> f64 {
+    let summary = pg_stat.get("pg_summary").unwrap_or(pg_stat);
+    let degraded = summary["degraded_objects"].as_u64().unwrap_or(0);
+    let mut own = 0;
+    if let Some(pgs) = pgs.get("pg_stats").unwrap_or(pgs).as_array() {
+        for pg in pgs {
+            let serving = ["up", "acting"].iter().any(|set| {
+                pg[*set].as_array().map_or(false, |ids| {
+                    ids.iter()
+                        .filter_map(|id| id.as_u64())
+                        .any(|id| osd_ids.contains(&id))
+                })
+            });
+            let undersized = pg["state"]
+                .as_str()
+                .map_or(false, |state| state.split('+').any(|s| s == "undersized"));
+            if serving || (down && undersized) {
+                own += pg["stat_sum"]["num_objects_degraded"].as_u64().unwrap_or(0);
+            }
+        }
+    }
+    match summary["degraded_total"].as_u64() {
+        Some(total) if total > 0 => degraded.saturating_sub(own) as f64 / total as f64,
+        _ => 0.0,
+    }
+}
+
+// The nearfull ratio from osd dump.  Jewel keeps it in the mon config, so
+// fall back to the ceph default
+fn nearfull_ratio(osd_dump: &Value) -> f64 {
+    match osd_dump["nearfull_ratio"].as_f64() {
+        Some(ratio) if ratio > 0.0 => ratio,
+        _ => 0.85,
+    }
+}
+
+// How full the cluster would be if the osd's capacity went away and its
+// data moved to the other osds.  None if osd df doesn't list the osd
+fn projected_fullness(osd_df: &Value, osd_ids: &[u64]) -> Option<f64> {
+    let nodes = osd_df["nodes"].as_array()?;
+    let mut osd_kb = 0;
+    for osd_id in osd_ids {
+        osd_kb += nodes.iter().find(|n| n["id"].as_u64() == Some(*osd_id))?["kb"].as_u64()?;
+    }
+    let total_kb = osd_df["summary"]["total_kb"].as_u64()?;
+    let used_kb = osd_df["summary"]["total_kb_used"].as_u64()?;
+    if total_kb <= osd_kb {
+        return Some(1.0);
+    }
+    Some(used_kb as f64 / (total_kb - osd_kb) as f64)
+}
+
+#[test]
+fn test_removal_policy() {
+    let tree = json!({
+        "nodes": [
+            {"id": -1, "name": "default", "type": "root", "children": [-3, -2]},
+            {"id": -2, "name": "ceph01", "type": "host", "children": [2, 1, 0]},
+            {"id": -3, "name": "ceph02", "type": "host", "children": [4, 3]},
+            {"id": 0, "name": "osd.0", "type": "osd", "status": "down", "reweight": 1.0},
+            {"id": 1, "name": "osd.1", "type": "osd", "status": "up", "reweight": 0.0},
+            {"id": 2, "name": "osd.2", "type": "osd", "status": "up", "reweight": 1.0},
+            {"id": 3, "name": "osd.3", "type": "osd", "status": "down", "reweight": 0.0},
+            {"id": 4, "name": "osd.4", "type": "osd", "status": "up", "reweight": 1.0, "crush_weight": 0.0}
+        ],
+        "stray": []
+    });
+    // osd.0 is down and osd.1 is out
+    assert_eq!(
+        failure_domain_down(&tree, &[2], "host"),
+        Some(("ceph01".to_string(), 2))
+    );
+    // The osd being removed doesn't count against itself
+    assert_eq!(
+        failure_domain_down(&tree, &[0], "host"),
+        Some(("ceph01".to_string(), 1))
+    );
+    assert_eq!(
+        failure_domain_down(&tree, &[2], "root"),
+        Some(("default".to_string(), 4))
+    );
+    // osd.3 is down and osd.4 is draining
+    assert_eq!(
+        failure_domain_down(&tree, &[3], "host"),
+        Some(("ceph02".to_string(), 1))
+    );
+    assert_eq!(
+        failure_domain_down(&tree, &[4], "host"),
+        Some(("ceph02".to_string(), 1))
+    );
+    assert_eq!(failure_domain_down(&tree, &[2], "rack"), None);
+    // osds that fail together, ie on one journal device, don't count either
+    assert_eq!(
+        failure_domain_down(&tree, &[0, 1], "host"),
+        Some(("ceph01".to_string(), 0))
+    );
+
+    let pg_stat = json!({
+        "pg_summary": {"degraded_objects": 50, "degraded_total": 1000, "num_pgs": 192}
+    });
+    let pgs = json!({
+        "pg_stats": [
+            {"pgid": "1.0", "state": "active+undersized+degraded", "up": [1, 2], "acting": [1, 2],
+             "stat_sum": {"num_objects_degraded": 20}},
+            {"pgid": "1.1", "state": "active+recovery_wait+degraded", "up": [0, 1, 2], "acting": [0, 1, 2],
+             "stat_sum": {"num_objects_degraded": 10}},
+            {"pgid": "1.2", "state": "active+clean", "up": [1, 2, 3], "acting": [1, 2, 3],
+             "stat_sum": {"num_objects_degraded": 0}}
+        ]
+    });
+    assert_eq!(degraded_ratio(&pg_stat, &pgs, &[3], false), 0.05);
+    // osd.0's own pg doesn't count
+    assert_eq!(degraded_ratio(&pg_stat, &pgs, &[0], false), 0.04);
+    // Nor do the undersized pgs a down osd left
+    assert_eq!(degraded_ratio(&pg_stat, &pgs, &[0], true), 0.02);
+    assert_eq!(
+        degraded_ratio(&json!({"num_pgs": 192}), &pgs, &[0], true),
+        0.0
+    );
+
+    assert_eq!(nearfull_ratio(&json!({"nearfull_ratio": 0.8})), 0.8);
+    assert_eq!(nearfull_ratio(&json!({"epoch": 10})), 0.85);
+
+    let df = json!({
+        "nodes": [
+            {"id": 0, "kb": 1000, "kb_used": 600},
+            {"id": 1, "kb": 1000, "kb_used": 600},
+            {"id": 2, "kb": 2000, "kb_used": 1200}
+        ],
+        "summary": {"total_kb": 4000, "total_kb_used": 2400}
+    });
+    // 2400 used on the 3000 left
+    assert_eq!(projected_fullness(&df, &[0]), Some(0.8));
+    assert_eq!(projected_fullness(&df, &[2]), Some(1.2));
+    assert_eq!(projected_fullness(&df, &[7]), None);
+    // 2400 used on the 2000 left without osd.0 and osd.1
+    assert_eq!(projected_fullness(&df, &[0, 1]), Some(1.2));
+}
+
+// Get the stats of every pg as json
+fn pg_dump_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "pg dump",
+        "dumpcontents": ["pgs"],
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let pgs: Value = serde_json::from_slice(&result.0)?;
+    trace!("PG dump {:?}", pgs);
+    Ok(pgs)
+}
+
+// Get the pg stat summary as json
+fn pg_stat_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "pg stat",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let stat: Value = serde_json::from_slice(&result.0)?;
+    trace!("PG stat {:?}", stat);
+    Ok(stat)
+}
+
+// Check that every pg is active and clean and no objects are misplaced.
+// Scrubbing or trimming snapshots doesn't move data so those pgs are clean too.
+// Luminous and later wrap the summary in pg_summary
+fn pgs_clean(pg_stat: &Value) -> bool {
+    let summary = pg_stat.get("pg_summary").unwrap_or(pg_stat);
+    let misplaced = summary["misplaced_objects"].as_u64().unwrap_or(0);
+    let all_clean = match summary["num_pg_by_state"].as_array() {
+        Some(states) => states
+            .iter()
+            .all(|state| state["name"].as_str().map_or(false, pg_state_clean)),
+        None => false,
+    };
+    all_clean && misplaced == 0
+}
+
+// A pg state is a + separated set.  ie active+clean+scrubbing+deep
+fn pg_state_clean(state: &str) -> bool {
+    let states: Vec<&str> = state.split('+').collect();
+    states.contains(&"active")
+        && states.contains(&"clean")
+        && !states.iter().any(|s| {
+            [
+                "remapped",
+                "backfilling",
+                "backfill_wait",
+                "recovering",
+                "recovery_wait",
+            ]
+            .contains(s)
+        })
+}
+
+// The mon command listing the pgs mapped to an osd
+fn pg_ls_by_osd_cmd(osd_id: u64) -> Value {
+    json!({
+        "prefix": "pg ls-by-osd",
+        "osd": format!("osd.{}", osd_id),
+        "format": "json",
+    })
+}
+
+#[test]
+fn test_pg_ls_by_osd_cmd() {
+    assert_eq!(
+        pg_ls_by_osd_cmd(3),
+        json!({"prefix": "pg ls-by-osd", "osd": "osd.3", "format": "json"})
+    );
+}
+
+// pg ls-by-osd returns a list of pgs.  Nautilus wraps it in pg_stats
+fn count_pgs(pgs: &Value) -> u64 {
+    pgs.get("pg_stats")
+        .unwrap_or(pgs)
+        .as_array()
+        .map_or(0, |pgs| pgs.len() as u64)
+}
+
+// The pgs osd_ids are serving that would be left with fewer than min_size
+// acting copies without them.  The up set is only where the pg is headed, so
+// it doesn't count.  A down osd already dropped out of the acting sets of its
+// pgs and blocks nothing.  pg ls-by-osd returns a list of pgs that Nautilus
+// and later wrap in pg_stats
+fn pgs_blocking_removal(pgs: &Value, pools: &Value, osd_ids: &[u64]) -> Vec<BlockingPg> {
+    // pool id -> (name, min_size).  Octopus renamed pool to pool_id
+    let mut pool_map: BTreeMap<u64, (String, u64)> = BTreeMap::new();
+    if let Some(pools) = pools.as_array() {
+        for pool in pools {
+            let id = pool["pool_id"].as_u64().or_else(|| pool["pool"].as_u64());
+            let name = pool["pool_name"].as_str();
+            if let (Some(id), Some(name)) = (id, name) {
+                let min_size = pool["min_size"].as_u64().unwrap_or(1);
+                pool_map.insert(id, (name.to_string(), min_size));
+            }
+        }
+    }
+    let pgs = match pgs.get("pg_stats").unwrap_or(pgs).as_array() {
+        Some(pgs) => pgs,
+        None => return Vec::new(),
+    };
+    let mut blocking = Vec::new();
+    for pg in pgs {
+        let pgid = match pg["pgid"].as_str() {
+            Some(pgid) => pgid,
+            None => continue,
+        };
+        let pool = pgid
+            .split('.')
+            .next()
+            .and_then(|id| id.parse::<u64>().ok())
+            .and_then(|id| pool_map.get(&id));
+        let (pool_name, min_size) = match pool {
+            Some(pool) => pool,
+            None => continue,
+        };
+        // Erasure coded pgs fill missing shards with CRUSH_ITEM_NONE
+        let acting: Vec<u64> = match pg["acting"].as_array() {
+            Some(acting) => acting
+                .iter()
+                .filter_map(|id| id.as_u64())
+                .filter(|id| *id != 2_147_483_647)
+                .collect(),
+            None => continue,
+        };
+        if !acting.iter().any(|id| osd_ids.contains(id)) {
+            continue;
+        }
+        let mut others: Vec<u64> = Vec::new();
+        for id in acting {
+            if !osd_ids.contains(&id) && !others.contains(&id) {
+                others.push(id);
+            }
+        }
+        if others.len() as u64 >= *min_size {
+            continue;
+        }
+        others.sort();
+        let mut blocking_pg = BlockingPg::new();
+        blocking_pg.set_pgid(pgid.to_string());
+        blocking_pg.set_pool(pool_name.clone());
+        blocking_pg.set_state(pg["state"].as_str().unwrap_or("unknown").to_string());
+        blocking_pg.set_min_size(*min_size);
+        blocking_pg.set_osds(others);
+        blocking.push(blocking_pg);
+    }
+    blocking
+}
+
+#[test]
+fn test_pgs_blocking_removal() {
+    let pools = json!([
+        {"pool": 1, "pool_name": "rbd", "size": 3, "min_size": 2},
+        {"pool": 2, "pool_name": "ec", "size": 3, "min_size": 3},
+    ]);
+    let pgs = json!({
+        "pg_stats": [
+            {"pgid": "1.0", "state": "active+clean", "up": [0, 1, 2], "acting": [0, 1, 2]},
+            {"pgid": "1.1", "state": "active+undersized+degraded", "up": [0, 3], "acting": [0, 3]},
+            {"pgid": "1.2", "state": "undersized+degraded+peered", "up": [4], "acting": [4]},
+            {"pgid": "1.3", "state": "active+remapped+backfilling", "up": [0, 6, 7], "acting": [0, 6]},
+            {"pgid": "1.4", "state": "active+remapped+backfill_wait", "up": [0, 8], "acting": [8, 9]},
+            {"pgid": "2.0", "state": "active+undersized+degraded", "up": [0, 5, 2147483647], "acting": [0, 5, 2147483647]},
+        ]
+    });
+    // 1.2 isn't on osd.0.  1.3 only has osd.7 in its up set.  1.4 isn't
+    // served by osd.0 yet
+    let blocking = pgs_blocking_removal(&pgs, &pools, &[0]);
+    let pgids: Vec<&str> = blocking.iter().map(|pg| pg.get_pgid()).collect();
+    assert_eq!(pgids, vec!["1.1", "1.3", "2.0"]);
+    assert_eq!(blocking[0].get_pool(), "rbd");
+    assert_eq!(blocking[0].get_osds(), &[3]);
+    assert_eq!(blocking[1].get_osds(), &[6]);
+    assert_eq!(blocking[2].get_min_size(), 3);
+    assert_eq!(blocking[2].get_osds(), &[5]);
+
+    // Nautilus doesn't wrap the list
+    let pgs =
+        json!([{"pgid": "1.0", "state": "active+clean", "up": [0, 1, 2], "acting": [0, 1, 2]}]);
+    assert!(pgs_blocking_removal(&pgs, &pools, &[0]).is_empty());
+    assert_eq!(pgs_blocking_removal(&pgs, &pools, &[0, 1]).len(), 1);
+}
+
+// How far along a drain is.  The eta assumes the remaining pgs move as fast
+// as the ones that already moved
+fn drain_progress(drain: &OsdDrain, remaining: u64, now: u64) -> Progress {
+    let mut progress = Progress::new();
+    progress.set_operation("drain".to_string());
+    progress.set_remaining(remaining);
+    let moved = drain.initial_pgs.saturating_sub(remaining);
+    if drain.initial_pgs == 0 {
+        progress.set_percent_done(100.0);
+    } else {
+        progress.set_percent_done(moved as f64 * 100.0 / drain.initial_pgs as f64);
+    }
+    if moved > 0 {
+        let elapsed = now.saturating_sub(drain.started);
+        progress.set_eta_seconds(elapsed * remaining / moved);
+    }
+    progress
+}
+
+// The next weight on the way from current to target
+fn next_weight(current: f64, target: f64, increment: f64) -> f64 {
+    if current < target {
+        target.min(current + increment)
+    } else {
+        target.max(current - increment)
+    }
+}
+
+// How far along a reweight is.  New osds start at weight 0 and the eta
+// assumes the rest of the weight goes on as fast as what's on already
+fn reweight_progress(reweight: &OsdReweight, weight: f64, now: u64) -> Progress {
+    let mut progress = Progress::new();
+    progress.set_operation("reweight".to_string());
+    if reweight.target_weight <= 0.0 {
+        progress.set_percent_done(100.0);
+    } else {
+        progress.set_percent_done((weight * 100.0 / reweight.target_weight).min(100.0));
+    }
+    if weight > 0.0 {
+        let elapsed = now.saturating_sub(reweight.started) as f64;
+        let left = (reweight.target_weight - weight).max(0.0);
+        progress.set_eta_seconds((elapsed * left / weight) as u64);
+    }
+    progress
+}
+
+// Who holds a cluster flag for an operation on osd_id
+fn flag_holder(osd_id: u64) -> BynarResult<String> {
+    let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+    Ok(format!("{}/osd.{}", host, osd_id))
+}
+
+// Who holds a cluster flag for a disk that has no osd yet
+fn device_flag_holder(device: &Path) -> BynarResult<String> {
+    let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
+    let dev_name = device
+        .file_name()
+        .ok_or_else(|| BynarError::new(format!("{} has no file name", device.display())))?;
+    Ok(format!("{}/{}", host, dev_name.to_string_lossy()))
+}
+
+fn lease_key(holder: &str, flag: ClusterFlag) -> String {
+    format!("{}/{}/{}", FLAG_LEASE_PREFIX, holder, flag.name())
+}
+
+// The holder and flag of a lease key.  None for keys that aren't leases
+fn parse_lease_key(key: &str) -> Option<(String, ClusterFlag)> {
+    let prefix = format!("{}/", FLAG_LEASE_PREFIX);
+    if !key.starts_with(&prefix) {
+        return None;
+    }
+    let lease = &key[prefix.len()..];
+    let mut parts = lease.rsplitn(2, '/');
+    let flag = ClusterFlag::from_name(parts.next()?)?;
+    let holder = parts.next()?;
+    Some((holder.to_string(), flag))
+}
+
+// Drop the leases of holder along with any that expired.  Returns the
+// dropped leases and the flags nobody holds anymore
+fn drop_leases(
+    leases: &mut Vec<FlagLease>,
+    holder: Option<&str>,
+    now: u64,
+) -> (Vec<FlagLease>, Vec<ClusterFlag>) {
+    let (dropped, kept): (Vec<FlagLease>, Vec<FlagLease>) = leases
+        .drain(..)
+        .partition(|l| Some(l.holder.as_str()) == holder || l.expires <= now);
+    *leases = kept;
+    let mut free: Vec<ClusterFlag> = Vec::new();
+    for lease in &dropped {
+        if !free.contains(&lease.flag) && !leases.iter().any(|l| l.flag == lease.flag) {
+            free.push(lease.flag);
+        }
+    }
+    (dropped, free)
+}
+
+fn epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_flag_leases() {
+    let lease = |flag, holder: &str, expires| FlagLease {
+        flag,
+        holder: holder.to_string(),
+        expires,
+    };
+    let mut leases = vec![
+        lease(ClusterFlag::NoScrub, "a/osd.1", 150),
+        lease(ClusterFlag::NoDeepScrub, "a/osd.1", 100),
+        lease(ClusterFlag::NoScrub, "b/osd.7", 200),
+    ];
+
+    // b still holds noscrub
+    let (dropped, free) = drop_leases(&mut leases, Some("a/osd.1"), 50);
+    assert_eq!(dropped.len(), 2);
+    assert_eq!(free, vec![ClusterFlag::NoDeepScrub]);
+    assert_eq!(leases.len(), 1);
+
+    // b crashed and its lease ran out
+    leases.push(lease(ClusterFlag::NoOut, "c/osd.3", 500));
+    let (dropped, free) = drop_leases(&mut leases, None, 300);
+    assert_eq!(dropped[0].holder, "b/osd.7");
+    assert_eq!(free, vec![ClusterFlag::NoScrub]);
+    assert_eq!(leases[0].flag, ClusterFlag::NoOut);
+
+    let key = lease_key("ceph01/osd.3", ClusterFlag::NoDeepScrub);
+    assert_eq!(key, "bynar/flag_leases/ceph01/osd.3/nodeep-scrub");
+    assert_eq!(
+        parse_lease_key(&key),
+        Some(("ceph01/osd.3".to_string(), ClusterFlag::NoDeepScrub))
+    );
+    // A disk ceph orch is creating an osd on
+    assert_eq!(
+        parse_lease_key("bynar/flag_leases/ceph01/sdb/noin"),
+        Some(("ceph01/sdb".to_string(), ClusterFlag::NoIn))
+    );
+    assert_eq!(parse_lease_key("bynar/flag_leases"), None);
+    assert_eq!(parse_lease_key("mgr/dashboard/key"), None);
+    assert_eq!(
+        parse_lease_key("bynar/flag_leases/ceph01/osd.3/nosuch"),
+        None
+    );
+}
+
+#[test]
+fn test_drain_status() {
+    let draining = json!({
+        "pg_ready": true,
+        "pg_summary": {
+            "num_pg_by_state": [
+                {"name": "active+clean", "num": 190},
+                {"name": "active+remapped+backfilling", "num": 2}
+            ],
+            "num_pgs": 192,
+            "misplaced_objects": 1043
+        }
+    });
+    assert!(!pgs_clean(&draining));
+    let misplaced = json!({
+        "num_pg_by_state": [{"name": "active+clean", "num": 192}],
+        "num_pgs": 192,
+        "misplaced_objects": 12
+    });
+    assert!(!pgs_clean(&misplaced));
+    let clean = json!({
+        "pg_summary": {
+            "num_pg_by_state": [{"name": "active+clean", "num": 192}],
+            "num_pgs": 192
+        }
+    });
+    assert!(pgs_clean(&clean));
+    let scrubbing = json!({
+        "num_pg_by_state": [
+            {"name": "active+clean", "num": 180},
+            {"name": "active+clean+scrubbing+deep", "num": 10},
+            {"name": "active+clean+snaptrim", "num": 2}
+        ],
+        "num_pgs": 192
+    });
+    assert!(pgs_clean(&scrubbing));
+    assert!(!pg_state_clean("active+clean+remapped"));
+    assert!(!pg_state_clean("active+undersized+degraded"));
+
+    assert_eq!(count_pgs(&json!([{"pgid": "1.0"}, {"pgid": "1.1"}])), 2);
+    assert_eq!(
+        count_pgs(&json!({"pg_ready": true, "pg_stats": [{"pgid": "1.0"}]})),
+        1
+    );
+
+    let drain = OsdDrain {
+        device: PathBuf::from("/dev/sdc"),
+        osd_id: 3,
+        started: 1000,
+        initial_pgs: 100,
+    };
+    // 25 pgs moved in 500 seconds leaves 1500 seconds for the other 75
+    let progress = drain_progress(&drain, 75, 1500);
+    assert_eq!(progress.get_percent_done(), 25.0);
+    assert_eq!(progress.get_remaining(), 75);
+    assert_eq!(progress.get_eta_seconds(), 1500);
+    // Nothing has moved yet so there's no eta
+    assert!(!drain_progress(&drain, 100, 1010).has_eta_seconds());
+}
+
+#[test]
+fn test_reweight_progress() {
+    assert_eq!(next_weight(0.5, 2.0, 0.25), 0.75);
+    // The last step doesn't go past the target
+    assert_eq!(next_weight(1.995, 2.0, 0.01), 2.0);
+    assert_eq!(next_weight(0.005, 0.0, 0.01), 0.0);
+
+    let reweight = OsdReweight {
+        device: PathBuf::from("/dev/sdc"),
+        osd_id: 3,
+        started: 1000,
+        target_weight: 2.0,
+    };
+    // A quarter of the weight went on in 600 seconds
+    let progress = reweight_progress(&reweight, 0.5, 1600);
+    assert_eq!(progress.get_percent_done(), 25.0);
+    assert_eq!(progress.get_eta_seconds(), 1800);
+    assert!(!reweight_progress(&reweight, 0.0, 1010).has_eta_seconds());
+
+    // A restarted disk-manager finds the reweight where it left it
+    let dir = TempDir::new("bynar").unwrap();
+    let state_file = dir.path().join("state").join("ceph_reweights.json");
+    let empty: Vec<OsdReweight> = load_state(&state_file).unwrap();
+    assert!(empty.is_empty());
+    save_state(&state_file, &[reweight.clone()]).unwrap();
+    let loaded: Vec<OsdReweight> = load_state(&state_file).unwrap();
+    assert_eq!(loaded, vec![reweight]);
+}
+
+// Get the size of a block device in bytes
+fn get_device_size(device: &Path) -> BynarResult<u64> {
+    let output = Command::new("blockdev")
+        .args(&["--getsize64", &device.to_string_lossy()])
+        .output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    let size = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<u64>()?;
+    Ok(size)
+}
+
 // Check if a device path is already in the cluster
 fn is_device_in_cluster(cluster_handle: &Rados, dev_path: &Path) -> BynarResult<bool> {
     debug!("Check if device is in cluster");
@@ -1502,35 +4707,331 @@ fn is_device_in_cluster(cluster_handle: &Rados, dev_path: &Path) -> BynarResult<
                 }
             }
 
-            ObjectStoreMeta::Filestore {
-                backend_filestore_partition_path,
-                ..
-            } => {
-                if backend_filestore_partition_path == path && osd.hostname == host {
-                    return Ok(true);
+            ObjectStoreMeta::Filestore {
+                backend_filestore_partition_path,
+                ..
+            } => {
+                if backend_filestore_partition_path == path && osd.hostname == host {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    //might be a Bluestore lvm, check the ceph-volume
+    let ceph_volumes = ceph_volume_list(&cluster_handle)?;
+    for (id, meta) in ceph_volumes {
+        for data in meta {
+            match data.metadata {
+                LvmData::Osd(data) => {
+                    //check if devices contains the device path
+                    for device in data.devices {
+                        if device == path {
+                            return Ok(true);
+                        }
+                    }
+                }
+                //skip other lvm types
+                _ => {}
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Check if an osd was destroyed.  Its id and crush position are waiting
+// for a replacement disk
+fn is_osd_destroyed(cluster_handle: &Rados, osd_id: u64) -> BynarResult<bool> {
+    let osd_dump = osd_dump_json(cluster_handle)?;
+    Ok(osd_has_state(&osd_dump, osd_id, "destroyed"))
+}
+
+fn osd_dump_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "osd dump",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let osd_dump: Value = serde_json::from_slice(&result.0)?;
+    Ok(osd_dump)
+}
+
+// Whether cephadm manages the daemons.  orch status fails when no
+// orchestrator is enabled
+fn orch_available(cluster_handle: &Rados) -> bool {
+    let cmd = json!({
+        "prefix": "orch status",
+        "format": "json",
+    });
+    match cluster_handle.ceph_mon_command_without_data(&cmd) {
+        Ok(result) => match serde_json::from_slice::<Value>(&result.0) {
+            Ok(status) => orch_uses_cephadm(&status),
+            Err(_) => false,
+        },
+        Err(e) => {
+            debug!("ceph orch status failed: {:?}", e);
+            false
+        }
+    }
+}
+
+fn orch_uses_cephadm(orch_status: &Value) -> bool {
+    orch_status["available"].as_bool().unwrap_or(false)
+        && orch_status["backend"].as_str() == Some("cephadm")
+}
+
+fn orch_osd_rm_status_json(cluster_handle: &Rados) -> BynarResult<Value> {
+    let cmd = json!({
+        "prefix": "orch osd rm status",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    // An empty queue is reported as plain text
+    Ok(serde_json::from_slice(&result.0).unwrap_or(Value::Null))
+}
+
+// Whether an osd is still in the cephadm removal queue
+fn orch_removal_queued(rm_status: &Value, osd_id: u64) -> bool {
+    match rm_status.as_array() {
+        Some(removals) => removals
+            .iter()
+            .any(|r| r["osd_id"].as_u64() == Some(osd_id)),
+        None => false,
+    }
+}
+
+// The osds on this host whose metadata lists device.  ie "devices": "sdb"
+fn osd_on_device(osd_metadata: &Value, host: &str, device: &Path) -> Vec<u64> {
+    let dev_name = match device.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Vec::new(),
+    };
+    let mut osds = Vec::new();
+    if let Some(metadata) = osd_metadata.as_array() {
+        for osd in metadata {
+            let on_device = osd["devices"]
+                .as_str()
+                .map(|d| d.split(',').any(|d| d == dev_name))
+                .unwrap_or(false);
+            if on_device && osd["hostname"].as_str() == Some(host) {
+                if let Some(id) = osd["id"].as_u64() {
+                    osds.push(id);
+                }
+            }
+        }
+    }
+    osds
+}
+
+#[test]
+fn test_orch() {
+    assert!(orch_uses_cephadm(
+        &json!({"available": true, "backend": "cephadm"})
+    ));
+    assert!(!orch_uses_cephadm(
+        &json!({"available": false, "backend": "cephadm", "reason": "not configured"})
+    ));
+    let rm_status = json!([
+        {"osd_id": 7, "started": true, "draining": true, "replace": true, "pg_count": 41}
+    ]);
+    assert!(orch_removal_queued(&rm_status, 7));
+    assert!(!orch_removal_queued(&rm_status, 8));
+    assert!(!orch_removal_queued(&Value::Null, 7));
+
+    let osd_metadata = json!([
+        {"id": 3, "hostname": "ceph01", "devices": "sdb"},
+        {"id": 4, "hostname": "ceph01", "devices": "sdc,nvme0n1"},
+        {"id": 5, "hostname": "ceph02", "devices": "sdc"}
+    ]);
+    assert_eq!(
+        osd_on_device(&osd_metadata, "ceph01", Path::new("/dev/sdc")),
+        vec![4]
+    );
+    assert!(osd_on_device(&osd_metadata, "ceph01", Path::new("/dev/sdd")).is_empty());
+}
+
+// Check the osd dump state list of an osd.  ie ["destroyed", "exists"]
+fn osd_has_state(osd_dump: &Value, osd_id: u64, state: &str) -> bool {
+    match osd_dump["osds"].as_array() {
+        Some(osds) => osds
+            .iter()
+            .filter(|osd| osd["osd"].as_u64() == Some(osd_id))
+            .filter_map(|osd| osd["state"].as_array())
+            .any(|states| states.iter().any(|s| s == state)),
+        None => false,
+    }
+}
+
+// Note each osd that came up in a later osdmap epoch than last time, ie it
+// went down and came back.  Flaps older than since are forgotten and so
+// are osds that aren't in osd_ids anymore
+fn record_flaps(
+    history: &mut Vec<OsdHistory>,
+    osd_dump: &Value,
+    osd_ids: &[u64],
+    since: u64,
+    now: u64,
+) {
+    history.retain(|h| osd_ids.contains(&h.osd_id));
+    let osds = match osd_dump["osds"].as_array() {
+        Some(osds) => osds,
+        None => return,
+    };
+    for osd in osds {
+        let (osd_id, up_from) = match (osd["osd"].as_u64(), osd["up_from"].as_u64()) {
+            (Some(osd_id), Some(up_from)) if osd_ids.contains(&osd_id) => (osd_id, up_from),
+            _ => continue,
+        };
+        match history.iter_mut().find(|h| h.osd_id == osd_id) {
+            Some(h) => {
+                if up_from > h.up_from {
+                    h.flaps.push(now);
                 }
+                h.up_from = up_from;
             }
+            None => history.push(OsdHistory {
+                osd_id,
+                up_from,
+                flaps: Vec::new(),
+                slow_runs: 0,
+            }),
         }
     }
-    //might be a Bluestore lvm, check the ceph-volume
-    let ceph_volumes = ceph_volume_list(&cluster_handle)?;
-    for (id, meta) in ceph_volumes {
-        for data in meta {
-            match data.metadata {
-                LvmData::Osd(data) => {
-                    //check if devices contains the device path
-                    for device in data.devices {
-                        if device == path {
-                            return Ok(true);
-                        }
-                    }
-                }
-                //skip other lvm types
-                _ => {}
-            }
+    for h in history.iter_mut() {
+        h.flaps.retain(|t| *t >= since);
+    }
+}
+
+// Count the checks in a row each osd was slow in.  An osd that isn't slow
+// this time starts over
+fn record_slow(history: &mut Vec<OsdHistory>, slow: &[(u64, f64)]) {
+    for h in history.iter_mut() {
+        if slow.iter().any(|(osd_id, _)| *osd_id == h.osd_id) {
+            h.slow_runs += 1;
+        } else {
+            h.slow_runs = 0;
         }
     }
-    Ok(false)
+}
+
+#[test]
+fn test_record_flaps() {
+    let dump = |up_from: u64| {
+        json!({"osds": [
+            {"osd": 3, "up": 1, "up_from": up_from},
+            {"osd": 4, "up": 1, "up_from": 10}
+        ]})
+    };
+    let mut history = vec![OsdHistory {
+        osd_id: 9,
+        up_from: 5,
+        flaps: vec![100],
+        slow_runs: 0,
+    }];
+    record_flaps(&mut history, &dump(10), &[3, 4], 0, 100);
+    // osd.9 left the host and the first sighting isn't a flap
+    assert_eq!(history.len(), 2);
+    assert!(history.iter().all(|h| h.flaps.is_empty()));
+
+    record_flaps(&mut history, &dump(20), &[3, 4], 0, 200);
+    record_flaps(&mut history, &dump(20), &[3, 4], 0, 300);
+    record_flaps(&mut history, &dump(35), &[3, 4], 0, 400);
+    assert_eq!(history[0].flaps, vec![200, 400]);
+    assert!(history[1].flaps.is_empty());
+
+    // Flaps older than the window are dropped
+    record_flaps(&mut history, &dump(35), &[3, 4], 300, 500);
+    assert_eq!(history[0].flaps, vec![400]);
+
+    record_slow(&mut history, &[(3, 250.0)]);
+    record_slow(&mut history, &[(3, 300.0), (4, 120.0)]);
+    assert_eq!(history[0].slow_runs, 2);
+    assert_eq!(history[1].slow_runs, 1);
+    // One fast sample starts the count over
+    record_slow(&mut history, &[(4, 130.0)]);
+    assert_eq!(history[0].slow_runs, 0);
+    assert_eq!(history[1].slow_runs, 2);
+}
+
+// Mark an osd down and destroy it.  Unlike osd rm this keeps the id and
+// crush entry around for a replacement disk
+fn osd_destroy(cluster_handle: &Rados, osd_id: u64, simulate: bool) -> BynarResult<()> {
+    let down = json!({
+        "prefix": "osd down",
+        "ids": [osd_id.to_string()],
+    });
+    // Luminous checks sure and later releases check yes_i_really_mean_it
+    let destroy = json!({
+        "prefix": "osd destroy",
+        "id": osd_id,
+        "sure": "--yes-i-really-mean-it",
+        "yes_i_really_mean_it": true,
+    });
+    debug!("cmd: {:?}, {:?}", down, destroy);
+    if simulate {
+        return Ok(());
+    }
+    cluster_handle.ceph_mon_command_without_data(&down)?;
+    cluster_handle.ceph_mon_command_without_data(&destroy)?;
+    Ok(())
+}
+
+// Create the osd in the crush map at location, or move it there if it's
+// already in the map.  ie location = ["root=default", "rack=r12", "host=ceph01"]
+fn osd_crush_create_or_move(
+    cluster_handle: &Rados,
+    osd_id: u64,
+    weight: f64,
+    location: &[String],
+    simulate: bool,
+) -> BynarResult<()> {
+    let cmd = json!({
+        "prefix": "osd crush create-or-move",
+        "id": osd_id,
+        "weight": weight,
+        "args": location,
+    });
+    debug!("cmd: {:?}", cmd);
+    if simulate {
+        return Ok(());
+    }
+    cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    Ok(())
+}
+
+// Give a destroyed osd's id to a new osd
+fn osd_new(
+    cluster_handle: &Rados,
+    osd_id: u64,
+    osd_fsid: &uuid::Uuid,
+    simulate: bool,
+) -> BynarResult<()> {
+    let cmd = json!({
+        "prefix": "osd new",
+        "uuid": osd_fsid.to_hyphenated().to_string(),
+        "id": osd_id,
+    });
+    debug!("cmd: {:?}", cmd);
+    if simulate {
+        return Ok(());
+    }
+    cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    Ok(())
+}
+
+#[test]
+fn test_osd_has_state() {
+    let osd_dump = json!({
+        "epoch": 1042,
+        "osds": [
+            {"osd": 3, "up": 0, "in": 0, "state": ["autoout", "exists"]},
+            {"osd": 4, "up": 0, "in": 0, "state": ["destroyed", "exists"]},
+            {"osd": 5, "up": 1, "in": 1, "state": ["exists", "up"]}
+        ]
+    });
+    assert!(osd_has_state(&osd_dump, 4, "destroyed"));
+    assert!(!osd_has_state(&osd_dump, 3, "destroyed"));
+    assert!(osd_has_state(&osd_dump, 5, "up"));
+    assert!(!osd_has_state(&osd_dump, 6, "exists"));
 }
 
 // Check if an osd_id is already in the cluster
@@ -1550,49 +5051,88 @@ fn get_osd_id_from_device(cluster_handle: &Rados, dev_path: &Path) -> BynarResul
     let host = get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?;
     trace!("Hostname is {:?}", host);
     let path = dev_path.to_string_lossy();
-    let osd_meta = osd_metadata(cluster_handle)?;
-    for osd in osd_meta {
-        match osd.objectstore_meta {
+    for (osd_id, device) in metadata_osd_devices(cluster_handle, &host)? {
+        if device == path {
+            return Ok(osd_id);
+        }
+    }
+    //Probably a Bluestore lvm, check the ceph-volume
+    for (osd_id, device) in ceph_volume_osd_devices(cluster_handle)? {
+        //check if devices contains the device path
+        if device == path {
+            return Ok(osd_id);
+        }
+    }
+    Err(BynarError::new(format!(
+        "unable to find the osd in the osd metadata"
+    )))
+}
+
+// The disk an osd on this host keeps its data on.  The reverse of
+// get_osd_id_from_device
+fn osd_disk(cluster_handle: &Rados, host: &str, osd_id: u64) -> BynarResult<Option<PathBuf>> {
+    // The metadata of lvm osds has their device mapper path
+    let mut devices: Vec<String> = metadata_osd_devices(cluster_handle, host)?
+        .into_iter()
+        .filter(|(id, device)| {
+            *id == osd_id && !device.starts_with("/dev/dm-") && !device.starts_with("/dev/mapper")
+        })
+        .map(|(_, device)| device)
+        .collect();
+    if devices.is_empty() {
+        devices = ceph_volume_osd_devices(cluster_handle)?
+            .into_iter()
+            .filter(|(id, _)| *id == osd_id)
+            .map(|(_, device)| device)
+            .collect();
+    }
+    Ok(devices.first().map(|device| {
+        let device = Path::new(device);
+        match block_utils::get_parent_devpath_from_path(device) {
+            Ok(Some(disk)) => disk,
+            _ => device.to_path_buf(),
+        }
+    }))
+}
+
+// The data partition of each osd on host in the osd metadata
+fn metadata_osd_devices(cluster_handle: &Rados, host: &str) -> BynarResult<Vec<(u64, String)>> {
+    let mut devices = Vec::new();
+    for osd in osd_metadata(cluster_handle)? {
+        if osd.hostname != host {
+            continue;
+        }
+        let device = match osd.objectstore_meta {
             ObjectStoreMeta::Bluestore {
                 bluestore_bdev_partition_path,
                 ..
-            } => {
-                if bluestore_bdev_partition_path == path && osd.hostname == host {
-                    return Ok(osd.id);
-                }
-            }
-
+            } => bluestore_bdev_partition_path,
             ObjectStoreMeta::Filestore {
                 backend_filestore_partition_path,
                 ..
-            } => {
-                if backend_filestore_partition_path == path && osd.hostname == host {
-                    return Ok(osd.id);
-                }
-            }
-        }
+            } => backend_filestore_partition_path,
+        };
+        devices.push((osd.id, device));
     }
-    //Probably a Bluestore lvm, check the ceph-volume
-    let ceph_volumes = ceph_volume_list(&cluster_handle)?;
-    for (id, meta) in ceph_volumes {
+    Ok(devices)
+}
+
+// The physical volumes under each lvm osd on this host
+fn ceph_volume_osd_devices(cluster_handle: &Rados) -> BynarResult<Vec<(u64, String)>> {
+    let mut devices = Vec::new();
+    for (id, meta) in ceph_volume_list(&cluster_handle)? {
         for data in meta {
             match data.metadata {
                 LvmData::Osd(data) => {
-                    //check if devices contains the device path
-                    for device in data.devices {
-                        if device == path {
-                            return Ok(id.parse::<u64>()?);
-                        }
-                    }
+                    let osd_id = id.parse::<u64>()?;
+                    devices.extend(data.devices.into_iter().map(|device| (osd_id, device)));
                 }
                 //skip other lvm types
                 _ => {}
             }
         }
     }
-    Err(BynarError::new(format!(
-        "unable to find the osd in the osd metadata"
-    )))
+    Ok(devices)
 }
 
 fn save_keyring(
@@ -1624,7 +5164,7 @@ fn add_osd_to_fstab(
     device_info: &block_utils::Device,
     osd_id: u64,
     simulate: bool,
-) -> BynarResult<()> {
+) -> BynarResult<HostArtifact> {
     let fstab = FsTab::default();
     let fstab_entry = fstab::FsEntry {
         fs_spec: format!(
@@ -1644,6 +5184,10 @@ fn add_osd_to_fstab(
         fsck_order: 2,
     };
     debug!("Saving Fstab entry {:?}", fstab_entry);
+    let artifact = HostArtifact::FstabEntry {
+        fs_spec: fstab_entry.fs_spec.clone(),
+        mountpoint: fstab_entry.mountpoint.clone(),
+    };
     if !simulate {
         let result = fstab.add_entry(fstab_entry)?;
         if result {
@@ -1652,7 +5196,7 @@ fn add_osd_to_fstab(
             debug!("Fstab entry was updated");
         }
     }
-    Ok(())
+    Ok(artifact)
 }
 
 // Look through all the /var/lib/ceph/osd/ directories and try to find
@@ -1736,12 +5280,14 @@ fn partition_in_use(partition_uuid: &uuid::Uuid) -> BynarResult<bool> {
     Ok(false)
 }
 
-fn systemctl_disable(osd_id: u64, osd_uuid: &uuid::Uuid, simulate: bool) -> BynarResult<()> {
+// The unit ceph-volume activates an lvm osd with at boot
+fn ceph_volume_unit(osd_id: u64, osd_uuid: &uuid::Uuid) -> String {
+    format!("ceph-volume@lvm-{}-{}", osd_id, osd_uuid.to_hyphenated())
+}
+
+fn systemctl_disable(unit: &str, simulate: bool) -> BynarResult<()> {
     if !simulate {
-        let args: Vec<String> = vec![
-            "disable".to_string(),
-            format!("ceph-volume@lvm-{}-{}", osd_id, osd_uuid.to_hyphenated()),
-        ];
+        let args: Vec<String> = vec!["disable".to_string(), unit.to_string()];
         debug!("cmd: systemctl {:?}", args);
         let output = Command::new("systemctl").args(&args).output()?;
         if !output.status.success() {
@@ -1753,12 +5299,9 @@ fn systemctl_disable(osd_id: u64, osd_uuid: &uuid::Uuid, simulate: bool) -> Byna
     Ok(())
 }
 
-fn systemctl_enable(osd_id: u64, osd_uuid: &uuid::Uuid, simulate: bool) -> BynarResult<()> {
+fn systemctl_enable(unit: &str, simulate: bool) -> BynarResult<()> {
     if !simulate {
-        let args: Vec<String> = vec![
-            "enable".to_string(),
-            format!("ceph-volume@lvm-{}-{}", osd_id, osd_uuid.to_hyphenated()),
-        ];
+        let args: Vec<String> = vec!["enable".to_string(), unit.to_string()];
         debug!("cmd: systemctl {:?}", args);
         let output = Command::new("systemctl").args(&args).output()?;
         if !output.status.success() {
@@ -1826,6 +5369,124 @@ fn setup_osd_init(osd_id: u64, simulate: bool) -> BynarResult<()> {
     }
 }
 
+// The dm-crypt mapping of an encrypted osd is named after its fsid
+fn dmcrypt_path(osd_fsid: &uuid::Uuid) -> PathBuf {
+    Path::new("/dev/mapper").join(osd_fsid.to_hyphenated().to_string())
+}
+
+// Where the dm-crypt key of an osd is kept in Vault
+fn dmcrypt_key_path(vault_key_path: &str, osd_fsid: &uuid::Uuid) -> String {
+    format!(
+        "{}/{}",
+        vault_key_path.trim_end_matches('/'),
+        osd_fsid.to_hyphenated()
+    )
+}
+
+// 256 random bits, hex encoded so the key can be stored as a Vault secret
+fn generate_dmcrypt_key() -> BynarResult<String> {
+    let mut bytes = [0u8; 32];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(hex_encode(&bytes))
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn save_dmcrypt_key(config: &CephConfig, osd_fsid: &uuid::Uuid, key: &str) -> BynarResult<()> {
+    let (endpoint, token) = vault_settings(config)?;
+    let client = VaultClient::new(endpoint, token)?;
+    client.set_secret(dmcrypt_key_path(&config.vault_key_path, osd_fsid), key)?;
+    Ok(())
+}
+
+fn get_dmcrypt_key(config: &CephConfig, osd_fsid: &uuid::Uuid) -> BynarResult<String> {
+    let (endpoint, token) = vault_settings(config)?;
+    let client = VaultClient::new(endpoint, token)?;
+    let key = client.get_secret(&dmcrypt_key_path(&config.vault_key_path, osd_fsid))?;
+    Ok(key)
+}
+
+fn revoke_dmcrypt_key(config: &CephConfig, osd_fsid: &uuid::Uuid) -> BynarResult<()> {
+    let (endpoint, token) = vault_settings(config)?;
+    let client = VaultClient::new(endpoint, token)?;
+    client.delete_secret(&dmcrypt_key_path(&config.vault_key_path, osd_fsid))?;
+    Ok(())
+}
+
+fn vault_settings(config: &CephConfig) -> BynarResult<(&str, &str)> {
+    match (&config.vault_endpoint, &config.vault_token) {
+        (Some(endpoint), Some(token)) => Ok((endpoint.as_str(), token.as_str())),
+        _ => Err(BynarError::from(
+            "vault_endpoint and vault_token must be set to encrypt osds",
+        )),
+    }
+}
+
+fn luks_format(device: &Path, key: &str, simulate: bool) -> BynarResult<()> {
+    let dev_str = device.to_string_lossy().into_owned();
+    cryptsetup(
+        &["--batch-mode", "--key-file", "-", "luksFormat", &dev_str],
+        Some(key),
+        simulate,
+    )
+}
+
+fn luks_open(device: &Path, osd_fsid: &uuid::Uuid, key: &str, simulate: bool) -> BynarResult<()> {
+    let dev_str = device.to_string_lossy().into_owned();
+    let name = osd_fsid.to_hyphenated().to_string();
+    cryptsetup(
+        &["--key-file", "-", "luksOpen", &dev_str, &name],
+        Some(key),
+        simulate,
+    )
+}
+
+fn luks_close(osd_fsid: &uuid::Uuid, simulate: bool) -> BynarResult<()> {
+    let name = osd_fsid.to_hyphenated().to_string();
+    cryptsetup(&["luksClose", &name], None, simulate)
+}
+
+// Run cryptsetup with the key, if there is one, on stdin so it never shows
+// up in the process list
+fn cryptsetup(args: &[&str], key: Option<&str>, simulate: bool) -> BynarResult<()> {
+    debug!("cmd: cryptsetup {:?}", args);
+    if simulate {
+        return Ok(());
+    }
+    let mut child = Command::new("cryptsetup")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(key), Some(mut stdin)) = (key, child.stdin.take()) {
+        stdin.write_all(key.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_dmcrypt_paths() {
+    let osd_fsid = uuid::Uuid::parse_str("b2c6a4d4-2b8e-4f4a-9a3d-1c5e7f9b0a12").unwrap();
+    assert_eq!(
+        dmcrypt_key_path("bynar/dmcrypt/", &osd_fsid),
+        "bynar/dmcrypt/b2c6a4d4-2b8e-4f4a-9a3d-1c5e7f9b0a12"
+    );
+    assert_eq!(
+        dmcrypt_path(&osd_fsid),
+        PathBuf::from("/dev/mapper/b2c6a4d4-2b8e-4f4a-9a3d-1c5e7f9b0a12")
+    );
+    assert_eq!(hex_encode(&[0, 15, 255]), "000fff");
+}
+
 fn settle_udev() -> BynarResult<()> {
     let output = Command::new("udevadm").arg("settle").output()?;
     if !output.status.success() {
@@ -1881,8 +5542,19 @@ fn symlink_bluestore_devices(
     Ok(())
 }
 
-/// add block device to udev rules if necessary
-fn add_block_to_udev(dev_path: &Path, udev_rule_path: &str) -> BynarResult<()> {
+/// add block device to udev rules if necessary.  Returns the device name the rule is for
+fn add_block_to_udev(
+    dev_path: &Path,
+    udev_rule_path: &str,
+    simulate: bool,
+) -> BynarResult<Option<String>> {
+    let dev = match dev_path.file_name() {
+        Some(dev) => dev.to_string_lossy().into_owned(),
+        None => return Ok(None),
+    };
+    if simulate {
+        return Ok(Some(dev));
+    }
     let udev_path = Path::new(udev_rule_path);
     let udev_rules = OpenOptions::new()
         .read(true)
@@ -1892,29 +5564,232 @@ fn add_block_to_udev(dev_path: &Path, udev_rule_path: &str) -> BynarResult<()> {
     let reader = BufReader::new(udev_rules);
 
     let mut found = false;
-    if let Some(dev) = dev_path.file_name() {
-        for line in reader.lines() {
-            if line?.contains(&dev.to_string_lossy().to_string()) {
-                found = true;
-            }
-        }
-        if !found {
-            let mut udev_rules = OpenOptions::new()
-                .read(true)
-                .append(true)
-                .create(true)
-                .open(udev_path)?;
-            udev_rules.write_all(&format!(r#"KERNEL="{}*", SUBSYSTEM=="block", ENV{{DEVTYPE}}=="partition", OWNER="ceph", GROUP="ceph", MODE="0660""#, dev.to_string_lossy()).as_bytes())?;
-            // reload udev rules
-            Command::new("udevadm")
-                .args(&["control", "--reload-rules"])
-                .output()?;
-            Command::new("udevadm").arg("trigger").output()?;
+    for line in reader.lines() {
+        if udev_rule_devices(&line?).contains(&dev) {
+            found = true;
+        }
+    }
+    if !found {
+        let mut udev_rules = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(udev_path)?;
+        udev_rules.write_all(format!("{}\n", udev_rule(&dev)).as_bytes())?;
+        reload_udev_rules()?;
+    }
+    Ok(Some(dev))
+}
+
+fn udev_rule(dev: &str) -> String {
+    format!(
+        r#"KERNEL="{}*", SUBSYSTEM=="block", ENV{{DEVTYPE}}=="partition", OWNER="ceph", GROUP="ceph", MODE="0660""#,
+        dev
+    )
+}
+
+// The devices the ceph udev rules in contents are for.  ie sdc
+fn udev_rule_devices(contents: &str) -> Vec<String> {
+    // Older releases wrote the rules without a newline between them
+    contents
+        .split("KERNEL=\"")
+        .skip(1)
+        .filter(|rule| rule.contains(r#"OWNER="ceph""#))
+        .filter_map(|rule| rule.split("*\"").next())
+        .map(|dev| dev.to_string())
+        .collect()
+}
+
+// The devices of the rules Bynar wrote on a line of the udev rules file.
+// None if the line isn't exactly Bynar's, ie an operator's own rule
+fn bynar_udev_rules(line: &str) -> Option<Vec<String>> {
+    let devices = udev_rule_devices(line);
+    if devices.is_empty() {
+        return None;
+    }
+    // Older releases wrote the rules without a newline between them
+    let rules: String = devices.iter().map(|d| udev_rule(d)).collect();
+    if rules == line.trim() {
+        Some(devices)
+    } else {
+        None
+    }
+}
+
+// contents without Bynar's rule for dev.  Every other line stays as it is
+fn without_udev_rule(contents: &str, dev: &str) -> String {
+    let mut kept = String::new();
+    for line in contents.lines() {
+        match bynar_udev_rules(line) {
+            Some(ref devices) if devices.iter().any(|d| d == dev) => {
+                for d in devices.iter().filter(|d| *d != dev) {
+                    kept.push_str(&udev_rule(d));
+                    kept.push('\n');
+                }
+            }
+            _ => {
+                kept.push_str(line);
+                kept.push('\n');
+            }
         }
     }
+    kept
+}
+
+fn remove_udev_rule(dev: &str, udev_rule_path: &str) -> BynarResult<()> {
+    let udev_path = Path::new(udev_rule_path);
+    let contents = read_to_string(udev_path)?;
+    let mut f = File::create(udev_path)?;
+    f.write_all(without_udev_rule(&contents, dev).as_bytes())?;
+    reload_udev_rules()
+}
+
+fn reload_udev_rules() -> BynarResult<()> {
+    Command::new("udevadm")
+        .args(&["control", "--reload-rules"])
+        .output()?;
+    Command::new("udevadm").arg("trigger").output()?;
     Ok(())
 }
 
+// The osd a udev rule, fstab entry or unit is for, if it names one
+fn artifact_osd_id(artifact: &HostArtifact) -> Option<u64> {
+    match artifact {
+        HostArtifact::UdevRule { .. } => None,
+        HostArtifact::FstabEntry { mountpoint, .. } => mountpoint
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.trim_start_matches("ceph-").parse().ok()),
+        // ceph-volume@lvm-{id}-{fsid}
+        HostArtifact::SystemdUnit { unit } => unit
+            .trim_start_matches("ceph-volume@lvm-")
+            .split('-')
+            .next()
+            .and_then(|id| id.parse().ok()),
+    }
+}
+
+// Whether something is mounted on path
+fn is_mountpoint(path: &Path) -> BynarResult<bool> {
+    let mounts = read_to_string("/proc/mounts")?;
+    Ok(mounts
+        .lines()
+        .any(|line| line.split_whitespace().nth(1).map(Path::new) == Some(path)))
+}
+
+fn unit_active(unit: &str) -> BynarResult<bool> {
+    let status = Command::new("systemctl")
+        .args(&["is-active", "--quiet", unit])
+        .status()?;
+    Ok(status.success())
+}
+
+// Whether the osd an artifact is for is still mounted or running
+fn artifact_running(artifact: &HostArtifact) -> BynarResult<bool> {
+    let running = match artifact {
+        HostArtifact::UdevRule { .. } => false,
+        HostArtifact::FstabEntry { mountpoint, .. } => is_mountpoint(mountpoint)?,
+        HostArtifact::SystemdUnit { unit } => unit_active(unit)?,
+    };
+    if running {
+        return Ok(true);
+    }
+    match artifact_osd_id(artifact) {
+        Some(osd_id) => {
+            let osd_dir = Path::new("/var/lib/ceph/osd").join(format!("ceph-{}", osd_id));
+            Ok(is_mountpoint(&osd_dir)? || unit_active(&format!("ceph-osd@{}", osd_id))?)
+        }
+        None => Ok(false),
+    }
+}
+
+// An artifact is orphaned once its osd is gone from the host.  A udev rule
+// is for a disk, so it's orphaned when no live osd uses the disk
+fn is_orphaned(artifact: &HostArtifact, live: &[u64], in_use: &[PathBuf]) -> bool {
+    match artifact {
+        HostArtifact::UdevRule { device } => {
+            let device = Path::new("/dev").join(device);
+            !in_use.iter().any(|d| on_device(d, &device))
+        }
+        _ => match artifact_osd_id(artifact) {
+            Some(osd_id) => !live.contains(&osd_id),
+            None => false,
+        },
+    }
+}
+
+// The osds osd metadata puts on host and the device names they use
+fn host_osds(osd_metadata: &Value, host: &str) -> Vec<(u64, Vec<String>)> {
+    let mut osds = Vec::new();
+    if let Some(metadata) = osd_metadata.as_array() {
+        for osd in metadata {
+            if osd["hostname"].as_str() != Some(host) {
+                continue;
+            }
+            if let Some(id) = osd["id"].as_u64() {
+                let devices = osd["devices"]
+                    .as_str()
+                    .map(|d| d.split(',').map(|d| d.to_string()).collect())
+                    .unwrap_or_default();
+                osds.push((id, devices));
+            }
+        }
+    }
+    osds
+}
+
+#[test]
+fn test_host_artifacts() {
+    // Rules written before they ended with a newline run together
+    let contents = format!(
+        "# local rules\n{}{}\n",
+        udev_rule("sdc"),
+        udev_rule("nvme0n1")
+    );
+    assert_eq!(udev_rule_devices(&contents), vec!["sdc", "nvme0n1"]);
+    let operator = r#"KERNEL=="sdb*", SUBSYSTEM=="block", OWNER="ceph", GROUP="ceph""#;
+    assert_eq!(bynar_udev_rules(operator), None);
+    assert_eq!(bynar_udev_rules("# local rules"), None);
+    let contents = format!("{}\n{}", operator, contents);
+    assert_eq!(
+        without_udev_rule(&contents, "sdc"),
+        format!("{}\n# local rules\n{}\n", operator, udev_rule("nvme0n1"))
+    );
+    assert_eq!(without_udev_rule(&contents, "sdb"), contents);
+
+    let fstab = HostArtifact::FstabEntry {
+        fs_spec: "UUID=0b0b7c53-6d6f-4d47-9a3c-6a1b6b1b2a3e".to_string(),
+        mountpoint: PathBuf::from("/var/lib/ceph/osd/ceph-12"),
+    };
+    let unit = HostArtifact::SystemdUnit {
+        unit: "ceph-volume@lvm-3-5d8f1c1e-2f4b-4d3a-9e0e-8a7b6c5d4e3f".to_string(),
+    };
+    assert_eq!(artifact_osd_id(&fstab), Some(12));
+    assert_eq!(artifact_osd_id(&unit), Some(3));
+
+    let live = vec![3];
+    let in_use = vec![PathBuf::from("/dev/nvme0n1"), PathBuf::from("/dev/sdd")];
+    assert!(is_orphaned(&fstab, &live, &in_use));
+    assert!(!is_orphaned(&unit, &live, &in_use));
+    let sdc = HostArtifact::UdevRule {
+        device: "sdc".to_string(),
+    };
+    let nvme = HostArtifact::UdevRule {
+        device: "nvme0n1".to_string(),
+    };
+    assert!(is_orphaned(&sdc, &live, &in_use));
+    assert!(!is_orphaned(&nvme, &live, &in_use));
+
+    let osd_metadata = json!([
+        {"id": 3, "hostname": "ceph1", "devices": "nvme0n1,sdd"},
+        {"id": 4, "hostname": "ceph2", "devices": "sdc"},
+    ]);
+    assert_eq!(
+        host_osds(&osd_metadata, "ceph1"),
+        vec![(3, vec!["nvme0n1".to_string(), "sdd".to_string()])]
+    );
+}
+
 fn ceph_chown(mount_point: &Path, simulate: bool) -> BynarResult<()> {
     debug!("chown osd directoy");
     if simulate {
@@ -1936,13 +5811,20 @@ fn ceph_chown(mount_point: &Path, simulate: bool) -> BynarResult<()> {
 }
 
 // ceph_mkkey_mkfs
-fn ceph_mkkey_mkfs(osd_id: u64, simulate: bool) -> BynarResult<()> {
+fn ceph_mkkey_mkfs(osd_id: u64, osd_uuid: &uuid::Uuid, simulate: bool) -> BynarResult<()> {
     debug!("Running ceph-osd --mkkey --mkfs");
     if simulate {
         return Ok(());
     }
     let output = Command::new("ceph-osd")
-        .args(&["-i", &osd_id.to_string(), "--mkkey", "--mkfs"])
+        .args(&[
+            "-i",
+            &osd_id.to_string(),
+            "--mkkey",
+            "--mkfs",
+            "--osd-uuid",
+            &osd_uuid.to_hyphenated().to_string(),
+        ])
         .output()?;
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
@@ -2446,6 +6328,115 @@ fn is_system_disk(system_disks: &[SystemDisk], device: &Path) -> bool {
     false
 }
 
+// A partition is treated as part of its disk.  ie /dev/sdc1 and /dev/nvme0n1p1
+// are on /dev/sdc and /dev/nvme0n1
+fn on_device(path: &Path, device: &Path) -> bool {
+    if path == device {
+        return true;
+    }
+    match block_utils::get_parent_devpath_from_path(path) {
+        Ok(Some(parent)) => parent == device,
+        // udev doesn't know the path, fall back to its name
+        _ => is_same_device(path, device),
+    }
+}
+
+// Whether a wal, db or journal path is on journal.  A logical volume is on it
+// when its volume group has a physical volume there.  ie /dev/ceph-db/db-1
+fn on_journal(path: &Path, journal: &Path, vg_pvs: &BTreeMap<String, Vec<PathBuf>>) -> bool {
+    if on_device(path, journal) {
+        return true;
+    }
+    let vg_name = path
+        .strip_prefix("/dev")
+        .ok()
+        .and_then(|p| p.components().next())
+        .map(|c| c.as_os_str().to_string_lossy().into_owned());
+    match vg_name.and_then(|name| vg_pvs.get(&name)) {
+        Some(pvs) => pvs.iter().any(|pv| on_device(pv, journal)),
+        None => false,
+    }
+}
+
+// The value of an lvm tag.  ie ceph.osd_id=3
+fn tag_value<'a>(tags: &'a [String], name: &str) -> Option<&'a str> {
+    tags.iter()
+        .filter_map(|t| {
+            let mut parts = t.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(v)) if n == name => Some(v),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+#[test]
+fn test_manual_osd_config() {
+    let disk = Path::new("/dev/sdc");
+    let osd_config = manual_osd_config(
+        disk,
+        Path::new("/dev/sdc2"),
+        Some(PathBuf::from("/dev/nvme0n1p3")),
+        Some(PathBuf::from("/dev/nvme0n1p4")),
+    )
+    .unwrap();
+    assert!(!osd_config.is_lvm);
+    assert_eq!(osd_config.dev_path, "/dev/sdc");
+    assert_eq!(osd_config.journal_path, Some("/dev/nvme0n1p3".to_string()));
+    assert_eq!(osd_config.rdb_path, Some("/dev/nvme0n1p4".to_string()));
+
+    // lvm and other disks' osds
+    assert!(manual_osd_config(disk, Path::new("/dev/dm-3"), None, None).is_none());
+    assert!(manual_osd_config(disk, Path::new("/dev/sdc"), None, None).is_none());
+    assert!(manual_osd_config(disk, Path::new("/dev/sdd2"), None, None).is_none());
+}
+
+#[test]
+fn test_journal_dependents() {
+    let tags = vec![
+        "ceph.type=block".to_string(),
+        "ceph.osd_id=12".to_string(),
+        "ceph.wal_device=/dev/sdk2".to_string(),
+    ];
+    assert_eq!(tag_value(&tags, "ceph.osd_id"), Some("12"));
+    assert_eq!(tag_value(&tags, "ceph.db_device"), None);
+
+    let mut vg_pvs = BTreeMap::new();
+    vg_pvs.insert("ceph-db".to_string(), vec![PathBuf::from("/dev/nvme0n1p3")]);
+    vg_pvs.insert("ceph-1234".to_string(), vec![PathBuf::from("/dev/sdc")]);
+    let journal = Path::new("/dev/sdk");
+    assert!(on_journal(Path::new("/dev/sdk2"), journal, &vg_pvs));
+    assert!(!on_journal(
+        Path::new("/dev/sdk"),
+        Path::new("/dev/sd"),
+        &vg_pvs
+    ));
+    assert!(!on_journal(Path::new("/dev/sdka1"), journal, &vg_pvs));
+    let nvme = Path::new("/dev/nvme0n1");
+    assert!(on_journal(Path::new("/dev/ceph-db/db-12"), nvme, &vg_pvs));
+    assert!(!on_journal(
+        Path::new("/dev/ceph-1234/osd-block-1"),
+        nvme,
+        &vg_pvs
+    ));
+}
+
+// Write an empty gpt to a blank replacement disk so partitions can be made on it
+fn label_disk(device: &Path) -> BynarResult<()> {
+    let cfg = gpt::GptConfig::new().writable(false).initialized(true);
+    if cfg.open(device).is_ok() {
+        return Ok(());
+    }
+    debug!("Writing a gpt to {}", device.display());
+    let cfg = gpt::GptConfig::new().writable(true).initialized(false);
+    let mut disk = cfg.open(device)?;
+    disk.update_partitions(BTreeMap::new())?;
+    disk.write()?;
+    update_partition_cache(device)?;
+    Ok(())
+}
+
 /// check if a device is in the list of Journal Disks
 fn is_journal(journal_devices: &Option<Vec<JournalDevice>>, device: &Path) -> bool {
     debug!("Checking config journal list for {}", device.display());
@@ -0,0 +1,376 @@
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+use api::service::{Capabilities, OpOutcome, Progress};
+
+use dirs::home_dir;
+use helpers::{error::*, load_config};
+use log::{debug, error};
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::{setsid, Pid},
+};
+use serde_derive::*;
+
+/*
+    Delegate disk operations to an external executable.
+    The executable gets one JSON request on stdin:
+        {"op": "remove_disk", "device": "/dev/sdb", "id": null, "simulate": false}
+    op is one of add_disk, remove_disk, safe_to_remove, safe_to_add or get_progress.
+    It answers with one JSON response on stdout:
+        {"outcome": "Success", "value": true, "error": null}
+    outcome is one of Success, Skipped, SkipRepeat or InProgress.  An add_disk or
+    remove_disk answering InProgress gets called again later until it's done or
+    the job is cancelled.  value is only needed for the safe_to_* checks.
+    get_progress can answer with an optional
+    "progress": {"operation": "rebuild", "percent_done": 50.0, "eta_seconds": 60}
+    Setting error, exiting non zero or running past the timeout fails the operation.
+*/
+
+// default seconds an executable gets to answer
+fn default_timeout() -> u64 {
+    600
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ExecConfig {
+    /// The executable to run
+    path: PathBuf,
+    /// Extra arguments to pass to the executable before the request is written
+    #[serde(default)]
+    args: Vec<String>,
+    /// Seconds to wait for the executable before killing it
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+struct ExecRequest {
+    op: String,
+    device: PathBuf,
+    id: Option<u64>,
+    simulate: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+enum ExecOutcome {
+    Success,
+    Skipped,
+    SkipRepeat,
+    InProgress,
+}
+
+impl From<ExecOutcome> for OpOutcome {
+    fn from(outcome: ExecOutcome) -> OpOutcome {
+        match outcome {
+            ExecOutcome::Success => OpOutcome::Success,
+            ExecOutcome::Skipped => OpOutcome::Skipped,
+            ExecOutcome::SkipRepeat => OpOutcome::SkipRepeat,
+            ExecOutcome::InProgress => OpOutcome::InProgress,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct ExecProgress {
+    operation: String,
+    percent_done: f64,
+    eta_seconds: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct ExecResponse {
+    outcome: ExecOutcome,
+    #[serde(default)]
+    value: bool,
+    error: Option<String>,
+    progress: Option<ExecProgress>,
+}
+
+/// An external executable that manages disks
+pub struct ExecBackend {
+    config: ExecConfig,
+}
+
+impl ExecBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<ExecBackend> {
+        let config: ExecConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "exec.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "exec.json")?
+            }
+        };
+        debug!("Loaded exec config: {:?}", config);
+        if !config.path.exists() {
+            return Err(BynarError::new(format!(
+                "{} does not exist",
+                config.path.display()
+            )));
+        }
+        Ok(ExecBackend { config })
+    }
+
+    fn call(&self, request: &ExecRequest) -> BynarResult<ExecResponse> {
+        let input = serde_json::to_string(request)?;
+        debug!(
+            "cmd: {} {:?} <<< {}",
+            self.config.path.display(),
+            self.config.args,
+            input
+        );
+        let mut cmd = Command::new(&self.config.path);
+        cmd.args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Its own session and process group so a timeout can kill anything it started
+        unsafe {
+            cmd.pre_exec(|| {
+                setsid()
+                    .map(|_| ())
+                    .map_err(|_| std::io::Error::last_os_error())
+            });
+        }
+        let child = cmd.spawn()?;
+        let (success, stdout, stderr) =
+            run_with_timeout(child, &input, Duration::from_secs(self.config.timeout))?;
+        if !success {
+            return Err(BynarError::new(format!(
+                "{} {} failed: {}",
+                self.config.path.display(),
+                request.op,
+                stderr.trim()
+            )));
+        }
+        let response: ExecResponse = match serde_json::from_str(stdout.trim()) {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(BynarError::new(format!(
+                    "{} {} returned an invalid response {:?}: {}. stderr: {}",
+                    self.config.path.display(),
+                    request.op,
+                    stdout.trim(),
+                    e,
+                    stderr.trim()
+                )));
+            }
+        };
+        if let Some(ref err) = response.error {
+            error!(
+                "{} {} failed: {}",
+                self.config.path.display(),
+                request.op,
+                err
+            );
+            if stderr.trim().is_empty() {
+                return Err(BynarError::new(err.clone()));
+            }
+            return Err(BynarError::new(format!(
+                "{}. stderr: {}",
+                err,
+                stderr.trim()
+            )));
+        }
+        Ok(response)
+    }
+
+    fn request(&self, op: &str, device: &Path, id: Option<u64>, simulate: bool) -> ExecRequest {
+        ExecRequest {
+            op: op.to_string(),
+            device: device.to_path_buf(),
+            id,
+            simulate,
+        }
+    }
+}
+
+impl Backend for ExecBackend {
+    fn add_disk(&self, device: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        let response = self.call(&self.request("add_disk", device, id, simulate))?;
+        Ok(response.outcome.into())
+    }
+
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let response = self.call(&self.request("remove_disk", device, None, simulate))?;
+        Ok(response.outcome.into())
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let response = self.call(&self.request("safe_to_remove", device, None, simulate))?;
+        Ok((response.outcome.into(), response.value))
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let response = self.call(&self.request("safe_to_add", device, None, simulate))?;
+        Ok((response.outcome.into(), response.value))
+    }
+
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>> {
+        let response = self.call(&self.request("get_progress", device, None, false))?;
+        Ok(response.progress.map(|p| {
+            let mut progress = Progress::new();
+            progress.set_operation(p.operation);
+            progress.set_percent_done(p.percent_done);
+            if let Some(eta) = p.eta_seconds {
+                progress.set_eta_seconds(eta);
+            }
+            progress
+        }))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // The executable decides what to do with the id and progress requests.
+        // Nothing makes it honor simulate, so it's treated as changing things
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(true);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(false);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(true);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+// Read a pipe to the end on its own thread so a chatty child can't block on a
+// full pipe.  The contents arrive on the returned channel
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut buf);
+        }
+        let _ = tx.send(buf);
+    });
+    rx
+}
+
+// Write input to the child's stdin and collect stdout and stderr, killing the
+// child's process group if it runs longer than timeout.
+// Returns (exited successfully, stdout, stderr)
+fn run_with_timeout(
+    mut child: Child,
+    input: &str,
+    timeout: Duration,
+) -> BynarResult<(bool, String, String)> {
+    let stdout_reader = read_pipe(child.stdout.take());
+    let stderr_reader = read_pipe(child.stderr.take());
+    if let Some(mut stdin) = child.stdin.take() {
+        // The child may exit without reading its input.  That shows up in the exit status
+        let _ = stdin.write_all(input.as_bytes());
+    }
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if start.elapsed() >= timeout {
+            error!("Killing process group {} after {:?}", child.id(), timeout);
+            let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    match status {
+        Some(status) => {
+            let stdout = stdout_reader.recv().unwrap_or_default();
+            let stderr = stderr_reader.recv().unwrap_or_default();
+            Ok((status.success(), stdout, stderr))
+        }
+        None => {
+            // Something that left the process group can still hold the pipes
+            // open.  Don't wait on it
+            let stderr = stderr_reader
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap_or_default();
+            Err(BynarError::new(format!(
+                "timed out after {} seconds. stderr: {}",
+                timeout.as_secs(),
+                stderr.trim()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh_backend(script: &str, timeout: u64) -> ExecBackend {
+        ExecBackend {
+            config: ExecConfig {
+                path: PathBuf::from("/bin/sh"),
+                args: vec!["-c".to_string(), script.to_string()],
+                timeout,
+            },
+        }
+    }
+
+    #[test]
+    fn test_exec_response() {
+        let backend = sh_backend(
+            r#"cat > /dev/null; echo '{"outcome": "Success", "value": true, "error": null}'"#,
+            10,
+        );
+        let (outcome, safe) = backend
+            .safe_to_remove(Path::new("/dev/sdb"), false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::Success);
+        assert!(safe);
+
+        let backend = sh_backend(r#"cat > /dev/null; echo '{"outcome": "SkipRepeat"}'"#, 10);
+        let outcome = backend
+            .add_disk(Path::new("/dev/sdb"), None, false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::SkipRepeat);
+    }
+
+    #[test]
+    fn test_exec_errors() {
+        // stderr ends up in the error message
+        let backend = sh_backend("echo 'disk is busy' >&2; exit 1", 10);
+        let err = backend
+            .remove_disk(Path::new("/dev/sdb"), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("disk is busy"));
+
+        let backend = sh_backend(
+            r#"echo 'pool offline' >&2; echo '{"outcome": "Success", "error": "nope"}'"#,
+            10,
+        );
+        let err = backend
+            .remove_disk(Path::new("/dev/sdb"), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("nope"));
+        assert!(err.to_string().contains("pool offline"));
+
+        let backend = sh_backend("sleep 30", 1);
+        let err = backend
+            .remove_disk(Path::new("/dev/sdb"), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        // A grandchild holding the pipes open doesn't hang the timeout
+        let backend = sh_backend("sleep 30 & sleep 30", 1);
+        let start = Instant::now();
+        let err = backend
+            .remove_disk(Path::new("/dev/sdb"), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -1,42 +1,320 @@
+use std::fs::{create_dir_all, read_to_string, remove_dir};
+use std::path::{Path, PathBuf};
+
+use crate::backend::util::{is_same_device, load_state, run_cmd, run_cmd_output, save_state};
 use crate::backend::Backend;
-use api::service::OpOutcome;
+use api::service::{Capabilities, OpOutcome, Progress};
 
-use helpers::error::*;
-use std::path::Path;
+use dirs::home_dir;
+use fstab::FsTab;
+use helpers::{error::*, load_config};
+use hostname::get_hostname;
+use log::{debug, error, info, warn};
+use serde_derive::*;
 
-pub struct GlusterBackend;
 /*
     Add a disk
     0. Make sure the brick pid isn't running
     1. Need to know which brick we're replacing.
-      a. Pull from sqlite3
+      a. Pull from the brick state file
     2. Create a dir on the gluster mount that doesn't exist
     Using Temp
          mkdir /mnt/r2/<name-of-nonexistent-dir>
          rmdir /mnt/r2/<name-of-nonexistent-dir>
          setfattr -n trusted.non-existent-key -v abc /mnt/r2
          setfattr -x trusted.non-existent-key  /mnt/r2
+    3. Replace the old brick with the new brick directory
 
     Remove a disk
-    1. Kill the brick pid
-    2. wipe it
-    3. Record in sqlite where it was mounted so we can replace it
-    later
+    1. Record in the brick state file where it was mounted so we can replace it
+    later.  Once the brick is killed gluster no longer knows its device
+    2. Kill the brick pid
+    3. wipe it and mark the record wiped
 */
 
+// default location to record removed bricks
+fn default_state_file() -> String {
+    "/var/lib/bynar/gluster_bricks.json".to_string()
+}
+
+// default filesystem to format new bricks with
+fn default_fs_type() -> String {
+    "xfs".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct GlusterConfig {
+    /// The name of the gluster volume the bricks on this host belong to
+    volume: String,
+    /// The host name the bricks on this host are registered under.
+    /// Defaults to the hostname of this server
+    brick_host: Option<String>,
+    /// Optional fuse mount of the volume on this host.  Used to mark
+    /// the volume dirty so the replacement brick is healed
+    client_mount: Option<String>,
+    /// The file used to remember removed bricks until the disk is replaced
+    #[serde(default = "default_state_file")]
+    state_file: String,
+    /// The filesystem to create on a replacement disk
+    #[serde(default = "default_fs_type")]
+    fs_type: String,
+}
+
+/// A brick that was removed and is waiting for a replacement disk
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct RemovedBrick {
+    /// The device the brick lived on
+    device: PathBuf,
+    /// host:/path of the brick
+    brick: String,
+    /// Where the device was mounted
+    mount_point: PathBuf,
+    /// Set once the device is unmounted and wiped
+    #[serde(default)]
+    wiped: bool,
+}
+
+/// The status of a single brick as reported by gluster volume status detail
+#[derive(Clone, Debug, PartialEq)]
+struct BrickStatus {
+    /// host:/path of the brick
+    brick: String,
+    online: bool,
+    pid: Option<u32>,
+    device: Option<PathBuf>,
+}
+
+/// Gluster volume
+pub struct GlusterBackend {
+    config: GlusterConfig,
+}
+
+impl GlusterBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<GlusterBackend> {
+        let config: GlusterConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "gluster.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "gluster.json")?
+            }
+        };
+        debug!("Loaded gluster config: {:?}", config);
+
+        Ok(GlusterBackend { config })
+    }
+
+    fn brick_host(&self) -> BynarResult<String> {
+        match self.config.brick_host {
+            Some(ref host) => Ok(host.clone()),
+            None => get_hostname().ok_or_else(|| BynarError::from("hostname not found")),
+        }
+    }
+
+    // Find the brick on this host that lives on the device
+    fn find_brick(&self, device: &Path) -> BynarResult<Option<BrickStatus>> {
+        let host = self.brick_host()?;
+        let status = volume_status_detail(&self.config.volume)?;
+        Ok(status.into_iter().find(|b| {
+            brick_host_name(&b.brick) == host
+                && b.device
+                    .as_ref()
+                    .map(|d| is_same_device(d, device))
+                    .unwrap_or(false)
+        }))
+    }
+
+    fn load_removed_bricks(&self) -> BynarResult<Vec<RemovedBrick>> {
+        load_state(Path::new(&self.config.state_file))
+    }
+
+    fn save_removed_bricks(&self, bricks: &[RemovedBrick]) -> BynarResult<()> {
+        save_state(Path::new(&self.config.state_file), bricks)
+    }
+
+    // Mark the volume root dirty so the self heal daemon picks up the new brick
+    fn mark_volume_dirty(&self, simulate: bool) -> BynarResult<()> {
+        let client_mount = match self.config.client_mount {
+            Some(ref m) => Path::new(m),
+            None => return Ok(()),
+        };
+        let tmp_dir = client_mount.join(format!("bynar-{}", uuid::Uuid::new_v4()));
+        debug!("Creating and removing {}", tmp_dir.display());
+        if !simulate {
+            create_dir_all(&tmp_dir)?;
+            remove_dir(&tmp_dir)?;
+        }
+        run_cmd(
+            "setfattr",
+            &[
+                "-n",
+                "trusted.non-existent-key",
+                "-v",
+                "abc",
+                &client_mount.to_string_lossy(),
+            ],
+            simulate,
+        )?;
+        run_cmd(
+            "setfattr",
+            &[
+                "-x",
+                "trusted.non-existent-key",
+                &client_mount.to_string_lossy(),
+            ],
+            simulate,
+        )?;
+        Ok(())
+    }
+}
+
 impl Backend for GlusterBackend {
-    fn add_disk(
-        &self,
-        _device: &Path,
-        _id: Option<u64>,
-        _simulate: bool,
-    ) -> BynarResult<OpOutcome> {
+    fn add_disk(&self, device: &Path, _id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut removed = self.load_removed_bricks()?;
+        let pos = match removed.iter().position(|b| b.device == device) {
+            Some(pos) => pos,
+            None => {
+                if self.find_brick(device)?.is_some() {
+                    debug!("Device {} is already a brick.  Skipping", device.display());
+                    return Ok(OpOutcome::SkipRepeat);
+                }
+                debug!(
+                    "No removed brick recorded for {}.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::Skipped);
+            }
+        };
+        let old = removed[pos].clone();
+        info!("Replacing brick {} with {}", old.brick, device.display());
+
+        // Make sure the old brick process is really gone
+        let status = volume_status_detail(&self.config.volume)?;
+        if let Some(b) = status.iter().find(|b| b.brick == old.brick) {
+            if b.online {
+                if let Some(pid) = b.pid {
+                    kill_brick(pid, simulate)?;
+                }
+            }
+        }
+        let dev = device.to_string_lossy();
+        let mount_point = old.mount_point.to_string_lossy();
+        let mkfs = format!("mkfs.{}", self.config.fs_type);
+        match self.config.fs_type.as_str() {
+            "xfs" => run_cmd(&mkfs, &["-f", "-i", "size=512", &dev], simulate)?,
+            _ => run_cmd(&mkfs, &[&dev], simulate)?,
+        };
+        if !simulate {
+            create_dir_all(&old.mount_point)?;
+        }
+        run_cmd("mount", &[&dev, &mount_point], simulate)?;
+        save_brick_mount(device, &old.mount_point, &self.config.fs_type, simulate)?;
+
+        let old_path = brick_path(&old.brick);
+        let dir_name = Path::new(old_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "brick".to_string());
+        let new_path = old.mount_point.join(format!(
+            "{}-{}",
+            dir_name,
+            &uuid::Uuid::new_v4().to_simple().to_string()[..8]
+        ));
+        debug!("Creating brick directory {}", new_path.display());
+        if !simulate {
+            create_dir_all(&new_path)?;
+        }
+        self.mark_volume_dirty(simulate)?;
+
+        let new_brick = format!("{}:{}", brick_host_name(&old.brick), new_path.display());
+        run_cmd(
+            "gluster",
+            &[
+                "--mode=script",
+                "volume",
+                "replace-brick",
+                &self.config.volume,
+                &old.brick,
+                &new_brick,
+                "commit",
+                "force",
+            ],
+            simulate,
+        )?;
+        run_cmd(
+            "gluster",
+            &["--mode=script", "volume", "heal", &self.config.volume],
+            simulate,
+        )?;
+        if !simulate {
+            removed.remove(pos);
+            self.save_removed_bricks(&removed)?;
+        }
         Ok(OpOutcome::Success)
     }
 
     /// Remove a disk from a cluster
     /// If simulate is passed no action should be taken
-    fn remove_disk(&self, _device: &Path, _simulate: bool) -> BynarResult<OpOutcome> {
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut removed = self.load_removed_bricks()?;
+        let pos = match removed.iter().position(|b| b.device == device) {
+            Some(pos) if removed[pos].wiped => {
+                debug!(
+                    "Brick on {} was already removed.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::SkipRepeat);
+            }
+            Some(pos) => {
+                // The brick was killed by an earlier call that failed to wipe
+                // the disk.  Gluster doesn't know its device anymore
+                debug!("Finishing the removal of brick {}", removed[pos].brick);
+                pos
+            }
+            None => {
+                let brick = match self.find_brick(device)? {
+                    Some(b) => b,
+                    None => {
+                        debug!("Device {} is not a brick.  Skipping", device.display());
+                        return Ok(OpOutcome::Skipped);
+                    }
+                };
+                let mount_point = match get_mount_point(device)? {
+                    Some(m) => m,
+                    None => {
+                        // The brick directory lives on the device so fall back to its parent
+                        let p = Path::new(brick_path(&brick.brick));
+                        p.parent().unwrap_or(p).to_path_buf()
+                    }
+                };
+                let record = RemovedBrick {
+                    device: device.to_path_buf(),
+                    brick: brick.brick,
+                    mount_point,
+                    wiped: false,
+                };
+                debug!("Recording removed brick {:?}", record);
+                removed.push(record);
+                if !simulate {
+                    self.save_removed_bricks(&removed)?;
+                }
+                if let Some(pid) = brick.pid {
+                    kill_brick(pid, simulate)?;
+                }
+                removed.len() - 1
+            }
+        };
+        if get_mount_point(device)?.is_some() {
+            run_cmd(
+                "umount",
+                &[&removed[pos].mount_point.to_string_lossy()],
+                simulate,
+            )?;
+        }
+        run_cmd("wipefs", &["-a", &device.to_string_lossy()], simulate)?;
+        if !simulate {
+            removed[pos].wiped = true;
+            self.save_removed_bricks(&removed)?;
+        }
         Ok(OpOutcome::Success)
     }
 
@@ -44,7 +322,385 @@ impl Backend for GlusterBackend {
     /// If simulate is passed then this always returns true
     /// Take any actions needed with this call to figure out if a disk is safe
     /// to remove from the cluster.
-    fn safe_to_remove(&self, _device: &Path, _simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        let brick = match self.find_brick(device)? {
+            Some(b) => b,
+            None => {
+                debug!("Device {} is not a brick.  Skipping", device.display());
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        let info = run_cmd_output(
+            "gluster",
+            &["--mode=script", "volume", "info", &self.config.volume],
+        )?;
+        let peers = match replica_set(&info, &brick.brick) {
+            Some(peers) => peers,
+            None => {
+                warn!(
+                    "Brick {} is not replicated.  Removing it loses data",
+                    brick.brick
+                );
+                return Ok((OpOutcome::Success, false));
+            }
+        };
+        let status = volume_status_detail(&self.config.volume)?;
+        for peer in peers.iter().filter(|p| **p != brick.brick) {
+            match status.iter().find(|s| &s.brick == peer) {
+                Some(s) if s.online => {}
+                _ => {
+                    error!("Replica peer {} of {} is offline", peer, brick.brick);
+                    return Ok((OpOutcome::Success, false));
+                }
+            }
+        }
+        let heal_info = run_cmd_output(
+            "gluster",
+            &[
+                "--mode=script",
+                "volume",
+                "heal",
+                &self.config.volume,
+                "info",
+            ],
+        )?;
+        let pending = pending_heals(&heal_info);
+        for peer in &peers {
+            if let Some((_, entries)) = pending.iter().find(|(b, _)| b == peer) {
+                if *entries > 0 {
+                    error!("Brick {} has {} pending heals", peer, entries);
+                    return Ok((OpOutcome::Success, false));
+                }
+            }
+        }
         Ok((OpOutcome::Success, true))
     }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let removed = self.load_removed_bricks()?;
+        let old = match removed.iter().find(|b| b.device == device) {
+            Some(b) => b.clone(),
+            None => {
+                if self.find_brick(device)?.is_some() {
+                    debug!("Device {} is already a brick.  Skipping", device.display());
+                    return Ok((OpOutcome::SkipRepeat, false));
+                }
+                debug!(
+                    "No removed brick recorded for {}.  Skipping",
+                    device.display()
+                );
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        // The replica peers are what the new brick gets healed from
+        let info = run_cmd_output(
+            "gluster",
+            &["--mode=script", "volume", "info", &self.config.volume],
+        )?;
+        let status = volume_status_detail(&self.config.volume)?;
+        if let Some(peers) = replica_set(&info, &old.brick) {
+            for peer in peers.iter().filter(|p| **p != old.brick) {
+                match status.iter().find(|s| &s.brick == peer) {
+                    Some(s) if s.online => {}
+                    _ => {
+                        error!(
+                            "Replica peer {} of {} is offline.  Not safe to add {}",
+                            peer,
+                            old.brick,
+                            device.display()
+                        );
+                        return Ok((OpOutcome::Success, false));
+                    }
+                }
+            }
+        }
+        Ok((OpOutcome::Success, true))
+    }
+
+    fn get_progress(&self, _device: &Path) -> BynarResult<Option<Progress>> {
+        Ok(None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(false);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(true);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(false);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+fn kill_brick(pid: u32, simulate: bool) -> BynarResult<()> {
+    debug!("Killing brick process {}", pid);
+    run_cmd("kill", &["-15", &pid.to_string()], simulate)
+}
+
+fn volume_status_detail(volume: &str) -> BynarResult<Vec<BrickStatus>> {
+    let output = run_cmd_output(
+        "gluster",
+        &["--mode=script", "volume", "status", volume, "detail"],
+    )?;
+    Ok(parse_status_detail(&output))
+}
+
+// Parse the output of gluster volume status <vol> detail
+fn parse_status_detail(output: &str) -> Vec<BrickStatus> {
+    let mut bricks: Vec<BrickStatus> = Vec::new();
+    for line in output.lines() {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        let value = match parts.next() {
+            Some(v) => v.trim(),
+            None => continue,
+        };
+        match key {
+            "Brick" => bricks.push(BrickStatus {
+                brick: value.trim_start_matches("Brick ").to_string(),
+                online: false,
+                pid: None,
+                device: None,
+            }),
+            "Online" => {
+                if let Some(b) = bricks.last_mut() {
+                    b.online = value == "Y";
+                }
+            }
+            "Pid" => {
+                if let Some(b) = bricks.last_mut() {
+                    b.pid = value.parse().ok();
+                }
+            }
+            "Device" => {
+                if let Some(b) = bricks.last_mut() {
+                    if value.starts_with('/') {
+                        b.device = Some(PathBuf::from(value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    bricks
+}
+
+// Parse gluster volume info and find the bricks in the same replica set as brick
+fn replica_set(info: &str, brick: &str) -> Option<Vec<String>> {
+    let mut replica_count = 1;
+    let mut bricks: Vec<String> = Vec::new();
+    for line in info.lines() {
+        let line = line.trim();
+        if line.starts_with("Number of Bricks:") {
+            // 1 x 3 = 3 or 1 x (2 + 1) = 3 for arbiter volumes
+            if let Some(set) = line.split(" x ").nth(1) {
+                let set = set.split('=').next().unwrap_or("");
+                replica_count = set
+                    .split('+')
+                    .filter_map(|n| {
+                        n.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+                            .parse::<usize>()
+                            .ok()
+                    })
+                    .sum();
+            }
+        } else if line.starts_with("Brick") {
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap_or("");
+            if key.len() > 5 && key[5..].chars().all(|c| c.is_digit(10)) {
+                if let Some(b) = parts.next() {
+                    bricks.push(b.trim().trim_end_matches(" (arbiter)").to_string());
+                }
+            }
+        }
+    }
+    if replica_count < 2 {
+        return None;
+    }
+    let pos = bricks.iter().position(|b| b == brick)?;
+    let start = pos - (pos % replica_count);
+    Some(
+        bricks
+            .iter()
+            .skip(start)
+            .take(replica_count)
+            .cloned()
+            .collect(),
+    )
+}
+
+// Parse gluster volume heal <vol> info into (brick, pending entries)
+fn pending_heals(output: &str) -> Vec<(String, u64)> {
+    let mut heals: Vec<(String, u64)> = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        if line.starts_with("Brick ") {
+            heals.push((line.trim_start_matches("Brick ").to_string(), 0));
+        } else if line.starts_with("Number of entries:") {
+            if let Some(h) = heals.last_mut() {
+                // Disconnected bricks report "-" which can't be trusted
+                h.1 = line
+                    .trim_start_matches("Number of entries:")
+                    .trim()
+                    .parse()
+                    .unwrap_or(u64::max_value());
+            }
+        }
+    }
+    heals
+}
+
+fn brick_host_name(brick: &str) -> &str {
+    brick.splitn(2, ':').next().unwrap_or("")
+}
+
+fn brick_path(brick: &str) -> &str {
+    brick.splitn(2, ':').nth(1).unwrap_or("")
+}
+
+// Point the fstab entry of a brick mount point at the replacement disk's
+// filesystem so the brick is mounted again after a reboot
+fn save_brick_mount(
+    device: &Path,
+    mount_point: &Path,
+    fs_type: &str,
+    simulate: bool,
+) -> BynarResult<()> {
+    if simulate {
+        return Ok(());
+    }
+    let uuid = run_cmd_output(
+        "blkid",
+        &["-s", "UUID", "-o", "value", &device.to_string_lossy()],
+    )?;
+    let fstab = FsTab::default();
+    // The entry of the failed disk
+    for entry in fstab.get_entries()? {
+        if entry.mountpoint == mount_point {
+            debug!(
+                "Removing fstab entry {} {}",
+                entry.fs_spec,
+                mount_point.display()
+            );
+            fstab.remove_entry(&entry.fs_spec)?;
+        }
+    }
+    let entry = fstab::FsEntry {
+        fs_spec: format!("UUID={}", uuid.trim()),
+        mountpoint: mount_point.to_path_buf(),
+        vfs_type: fs_type.to_string(),
+        mount_options: vec!["defaults".into()],
+        dump: false,
+        fsck_order: 2,
+    };
+    debug!("Saving fstab entry {:?}", entry);
+    fstab.add_entry(entry)?;
+    Ok(())
+}
+
+// Look through /proc/mounts for where the device, or a partition on it, is mounted
+fn get_mount_point(device: &Path) -> BynarResult<Option<PathBuf>> {
+    let mounts = read_to_string("/proc/mounts")?;
+    for line in mounts.lines() {
+        let mut parts = line.split_whitespace();
+        let (dev, mount) = match (parts.next(), parts.next()) {
+            (Some(d), Some(m)) => (d, m),
+            _ => continue,
+        };
+        if is_same_device(Path::new(dev), device) {
+            return Ok(Some(PathBuf::from(mount)));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_parse_status_detail() {
+    let output = r#"Status of volume: r2
+------------------------------------------------------------------------------
+Brick                : Brick server1:/bricks/b1/brick
+TCP Port             : 49152
+RDMA Port            : 0
+Online               : Y
+Pid                  : 1234
+File System          : xfs
+Device               : /dev/sdb1
+------------------------------------------------------------------------------
+Brick                : Brick server2:/bricks/b1/brick
+TCP Port             : N/A
+RDMA Port            : N/A
+Online               : N
+Pid                  : N/A
+File System          : xfs
+Device               : /dev/sdc
+"#;
+    let bricks = parse_status_detail(output);
+    assert_eq!(
+        bricks,
+        vec![
+            BrickStatus {
+                brick: "server1:/bricks/b1/brick".to_string(),
+                online: true,
+                pid: Some(1234),
+                device: Some(PathBuf::from("/dev/sdb1")),
+            },
+            BrickStatus {
+                brick: "server2:/bricks/b1/brick".to_string(),
+                online: false,
+                pid: None,
+                device: Some(PathBuf::from("/dev/sdc")),
+            },
+        ]
+    );
+    assert!(is_same_device(
+        Path::new("/dev/sdb1"),
+        Path::new("/dev/sdb")
+    ));
+    assert!(!is_same_device(
+        Path::new("/dev/sdbc"),
+        Path::new("/dev/sdb")
+    ));
+}
+
+#[test]
+fn test_replica_set() {
+    let info = r#"Volume Name: r2
+Type: Distributed-Replicate
+Status: Started
+Number of Bricks: 2 x 3 = 6
+Transport-type: tcp
+Bricks:
+Brick1: server1:/bricks/b1/brick
+Brick2: server2:/bricks/b1/brick
+Brick3: server3:/bricks/b1/brick
+Brick4: server1:/bricks/b2/brick
+Brick5: server2:/bricks/b2/brick
+Brick6: server3:/bricks/b2/brick (arbiter)
+"#;
+    assert_eq!(
+        replica_set(info, "server2:/bricks/b2/brick"),
+        Some(vec![
+            "server1:/bricks/b2/brick".to_string(),
+            "server2:/bricks/b2/brick".to_string(),
+            "server3:/bricks/b2/brick".to_string(),
+        ])
+    );
+    let heal = "Brick server1:/bricks/b1/brick\nStatus: Connected\nNumber of entries: 0\n\nBrick server2:/bricks/b1/brick\nStatus: Transport endpoint is not connected\nNumber of entries: -\n";
+    assert_eq!(
+        pending_heals(heal),
+        vec![
+            ("server1:/bricks/b1/brick".to_string(), 0),
+            ("server2:/bricks/b1/brick".to_string(), u64::max_value()),
+        ]
+    );
 }
@@ -0,0 +1,502 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::util::{is_same_device, load_state, run_cmd, run_cmd_output, save_state};
+use crate::backend::Backend;
+use api::service::{Capabilities, OpOutcome, Progress};
+
+use dirs::home_dir;
+use helpers::{error::*, load_config};
+use log::{debug, info};
+use serde_derive::*;
+
+// default file to remember the physical volumes being removed in
+fn default_state_file() -> String {
+    "/var/lib/bynar/lvm_pvs.json".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LvmConfig {
+    /// The volume group that Bynar manages
+    vg_name: String,
+    /// The file used to remember physical volumes until they're removed
+    #[serde(default = "default_state_file")]
+    state_file: String,
+}
+
+/// A physical volume Bynar saw while its disk was present.  LVM only knows
+/// a missing physical volume by its uuid
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct PvRecord {
+    /// The device the physical volume lives on
+    device: PathBuf,
+    pv_uuid: String,
+    /// Set once a pvmove off of the physical volume was started
+    #[serde(default)]
+    pvmove_started: bool,
+}
+
+/// A physical volume in the volume group and how many of its extents are
+/// allocated
+#[derive(Clone, Debug, PartialEq)]
+struct PvUsage {
+    /// [unknown] for a physical volume whose disk is gone
+    name: String,
+    uuid: String,
+    pe_count: u64,
+    pe_alloc_count: u64,
+    missing: bool,
+}
+
+/// A pvmove running in the volume group
+#[derive(Clone, Debug, PartialEq)]
+struct PvMove {
+    /// The physical volume the extents are moved off of
+    source: String,
+    percent: f64,
+}
+
+/// Physical volumes in an LVM volume group.  Failing disks are evacuated
+/// with a background pvmove before they're taken out of the volume group
+pub struct LvmBackend {
+    config: LvmConfig,
+}
+
+impl LvmBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<LvmBackend> {
+        let config: LvmConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "lvm.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "lvm.json")?
+            }
+        };
+        debug!("Loaded lvm config: {:?}", config);
+        Ok(LvmBackend { config })
+    }
+
+    // The physical volumes of the volume group
+    fn physical_volumes(&self) -> BynarResult<Vec<PvUsage>> {
+        let output = run_cmd_output(
+            "pvs",
+            &[
+                "--noheadings",
+                "--separator",
+                ":",
+                "-o",
+                "pv_name,vg_name,pv_uuid,pv_pe_count,pv_pe_alloc_count,pv_missing",
+            ],
+        )?;
+        let pvs = parse_pvs(&output, &self.config.vg_name);
+        debug!("{} physical volumes: {:?}", self.config.vg_name, pvs);
+        Ok(pvs)
+    }
+
+    // The pvmoves running in the volume group
+    fn pvmoves(&self) -> BynarResult<Vec<PvMove>> {
+        let output = run_cmd_output(
+            "lvs",
+            &[
+                "-a",
+                "--noheadings",
+                "--separator",
+                ":",
+                "-o",
+                "lv_name,move_pv,copy_percent",
+                &self.config.vg_name,
+            ],
+        )?;
+        Ok(parse_pvmoves(&output))
+    }
+
+    fn load_records(&self) -> BynarResult<Vec<PvRecord>> {
+        load_state(Path::new(&self.config.state_file))
+    }
+
+    fn save_records(&self, records: &[PvRecord]) -> BynarResult<()> {
+        save_state(Path::new(&self.config.state_file), records)
+    }
+
+    // Remember the uuid of the physical volume on device so it can still be
+    // found after the disk is gone
+    fn remember_pv(&self, device: &Path, pv: &PvUsage, simulate: bool) -> BynarResult<()> {
+        let mut records = self.load_records()?;
+        if records
+            .iter()
+            .any(|r| r.device == device && r.pv_uuid == pv.uuid)
+        {
+            return Ok(());
+        }
+        // A replacement disk gets a new uuid
+        records.retain(|r| r.device != device);
+        let record = PvRecord {
+            device: device.to_path_buf(),
+            pv_uuid: pv.uuid.clone(),
+            pvmove_started: false,
+        };
+        debug!("Recording physical volume {:?}", record);
+        if !simulate {
+            records.push(record);
+            self.save_records(&records)?;
+        }
+        Ok(())
+    }
+
+    fn forget_pv(&self, device: &Path, simulate: bool) -> BynarResult<()> {
+        let mut records = self.load_records()?;
+        records.retain(|r| r.device != device);
+        if !simulate {
+            self.save_records(&records)?;
+        }
+        Ok(())
+    }
+
+    fn find_pv(&self, device: &Path) -> BynarResult<Option<PvUsage>> {
+        Ok(self
+            .physical_volumes()?
+            .into_iter()
+            .find(|pv| is_same_device(Path::new(&pv.name), device)))
+    }
+}
+
+impl Backend for LvmBackend {
+    fn add_disk(&self, device: &Path, _id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        if self.find_pv(device)?.is_some() {
+            debug!(
+                "{} is already in volume group {}.  Skipping",
+                device.display(),
+                self.config.vg_name
+            );
+            return Ok(OpOutcome::SkipRepeat);
+        }
+        let dev = device.to_string_lossy();
+        run_cmd("pvcreate", &[&dev], simulate)?;
+        run_cmd("vgextend", &[&self.config.vg_name, &dev], simulate)?;
+        if let Some(pv) = self.find_pv(device)? {
+            self.remember_pv(device, &pv, simulate)?;
+        }
+        info!(
+            "Added {} to volume group {}",
+            device.display(),
+            self.config.vg_name
+        );
+        Ok(OpOutcome::Success)
+    }
+
+    // Starts a background pvmove and returns InProgress until it's done.
+    // disk-manager calls it again until then
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        // Look for the pvmove before the extents so a pvmove that finishes
+        // in between isn't mistaken for one that stopped
+        let pvmoves = self.pvmoves()?;
+        let pvs = self.physical_volumes()?;
+        let records = self.load_records()?;
+        let record = records.iter().find(|r| r.device == device);
+        let pv = match pvs
+            .iter()
+            .find(|pv| is_same_device(Path::new(&pv.name), device))
+        {
+            Some(pv) => pv.clone(),
+            None => {
+                if missing_pv(&pvs, record) {
+                    // vgreduce refuses if a logical volume still uses them
+                    info!(
+                        "{} is gone.  Removing the missing physical volumes from {}",
+                        device.display(),
+                        self.config.vg_name
+                    );
+                    run_cmd(
+                        "vgreduce",
+                        &["--removemissing", &self.config.vg_name],
+                        simulate,
+                    )?;
+                    self.forget_pv(device, simulate)?;
+                    return Ok(OpOutcome::Success);
+                }
+                debug!(
+                    "{} is not in volume group {}.  Skipping",
+                    device.display(),
+                    self.config.vg_name
+                );
+                return Ok(OpOutcome::Skipped);
+            }
+        };
+        if pv.pe_alloc_count > 0 {
+            // Move the allocated extents onto the other physical volumes
+            // first.  This can take a long time on a large disk.  One pvmove
+            // runs in the volume group at a time
+            if pvmoves
+                .iter()
+                .any(|m| is_same_device(Path::new(&m.source), device))
+            {
+                return Ok(OpOutcome::InProgress);
+            }
+            if record
+                .iter()
+                .any(|r| r.pvmove_started && r.pv_uuid == pv.uuid)
+            {
+                return Err(BynarError::new(format!(
+                    "pvmove of {} stopped with {} extents still allocated on it",
+                    pv.name, pv.pe_alloc_count
+                )));
+            }
+            if !pvmoves.is_empty() {
+                debug!(
+                    "Waiting for the pvmove in {} to finish before evacuating {}",
+                    self.config.vg_name, pv.name
+                );
+                return Ok(OpOutcome::InProgress);
+            }
+            self.remember_pv(device, &pv, simulate)?;
+            info!("Evacuating {} with pvmove", pv.name);
+            run_cmd("pvmove", &["-b", &pv.name], simulate)?;
+            if simulate {
+                return Ok(OpOutcome::Success);
+            }
+            let mut records = self.load_records()?;
+            for r in records.iter_mut().filter(|r| r.device == device) {
+                r.pvmove_started = true;
+            }
+            self.save_records(&records)?;
+            return Ok(OpOutcome::InProgress);
+        }
+        run_cmd("vgreduce", &[&self.config.vg_name, &pv.name], simulate)?;
+        run_cmd("pvremove", &[&pv.name], simulate)?;
+        self.forget_pv(device, simulate)?;
+        info!(
+            "Removed {} from volume group {}",
+            pv.name, self.config.vg_name
+        );
+        Ok(OpOutcome::Success)
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let pvs = self.physical_volumes()?;
+        let pv = match pvs
+            .iter()
+            .find(|pv| is_same_device(Path::new(&pv.name), device))
+        {
+            Some(pv) => pv,
+            None => {
+                let records = self.load_records()?;
+                let record = records.iter().find(|r| r.device == device);
+                if missing_pv(&pvs, record) {
+                    return Ok((OpOutcome::Success, true));
+                }
+                debug!(
+                    "{} is not in volume group {}.  Skipping",
+                    device.display(),
+                    self.config.vg_name
+                );
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        // Remember it while the disk is still here in case it's gone by
+        // the time it's removed
+        self.remember_pv(device, pv, simulate)?;
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        Ok((OpOutcome::Success, can_evacuate(&pvs, pv)))
+    }
+
+    fn safe_to_add(&self, device: &Path, _simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        if self.find_pv(device)?.is_some() {
+            debug!(
+                "{} is already in volume group {}.  Skipping",
+                device.display(),
+                self.config.vg_name
+            );
+            return Ok((OpOutcome::SkipRepeat, false));
+        }
+        // Extending the volume group doesn't move any data around so
+        // nothing in the volume group can make adding a disk unsafe
+        Ok((OpOutcome::Success, true))
+    }
+
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>> {
+        Ok(self
+            .pvmoves()?
+            .into_iter()
+            .find(|m| is_same_device(Path::new(&m.source), device))
+            .map(|m| {
+                let mut progress = Progress::new();
+                progress.set_operation("pvmove".to_string());
+                progress.set_percent_done(m.percent);
+                progress
+            }))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(false);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(true);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(true);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+// Whether record is of a physical volume LVM lists as missing.  LVM only
+// knows a missing physical volume by its uuid so without a record of the
+// uuid there's no telling which disk it was
+fn missing_pv(pvs: &[PvUsage], record: Option<&PvRecord>) -> bool {
+    match record {
+        Some(record) => pvs.iter().any(|pv| pv.missing && pv.uuid == record.pv_uuid),
+        None => false,
+    }
+}
+
+// Whether the other physical volumes have enough free extents to absorb the
+// extents allocated on pv.  The free extents of missing physical volumes
+// can't be used
+fn can_evacuate(pvs: &[PvUsage], pv: &PvUsage) -> bool {
+    let free_elsewhere: u64 = pvs
+        .iter()
+        .filter(|p| p.name != pv.name && !p.missing)
+        .map(|p| p.pe_count.saturating_sub(p.pe_alloc_count))
+        .sum();
+    debug!(
+        "{} has {} extents allocated. {} extents free on the other physical volumes",
+        pv.name, pv.pe_alloc_count, free_elsewhere
+    );
+    pv.pe_alloc_count <= free_elsewhere
+}
+
+// Parse pvs -o pv_name,vg_name,pv_uuid,pv_pe_count,pv_pe_alloc_count,pv_missing
+// output for the physical volumes of vg_name
+fn parse_pvs(output: &str, vg_name: &str) -> Vec<PvUsage> {
+    let mut pvs = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.trim().split(':').collect();
+        if fields.len() < 6 || fields[1] != vg_name {
+            continue;
+        }
+        if let (Ok(pe_count), Ok(pe_alloc_count)) = (fields[3].parse(), fields[4].parse()) {
+            pvs.push(PvUsage {
+                name: fields[0].to_string(),
+                uuid: fields[2].to_string(),
+                pe_count,
+                pe_alloc_count,
+                missing: fields[5] == "missing",
+            });
+        }
+    }
+    pvs
+}
+
+// The pvmove logical volumes in lvs -a -o lv_name,move_pv,copy_percent
+// output, ie [pvmove0]:/dev/sdb:42.17
+fn parse_pvmoves(output: &str) -> Vec<PvMove> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let name = fields.next()?.trim_matches(|c| c == '[' || c == ']');
+            if !name.starts_with("pvmove") {
+                return None;
+            }
+            Some(PvMove {
+                source: fields.next()?.to_string(),
+                percent: fields.next()?.parse().unwrap_or(0.0),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pv(name: &str, pe_count: u64, pe_alloc_count: u64, missing: bool) -> PvUsage {
+        PvUsage {
+            name: name.to_string(),
+            uuid: format!("uuid-{}", name),
+            pe_count,
+            pe_alloc_count,
+            missing,
+        }
+    }
+
+    #[test]
+    fn test_can_evacuate() {
+        let pvs = vec![
+            pv("/dev/sdb", 1000, 800, false),
+            pv("/dev/sdc", 1000, 500, false),
+            pv("/dev/sdd1", 1000, 600, false),
+        ];
+        // 800 allocated, 900 free on sdc and sdd1
+        assert!(can_evacuate(&pvs, &pvs[0]));
+        // 500 allocated, 600 free on sdb and sdd1
+        assert!(can_evacuate(&pvs, &pvs[1]));
+
+        let full = vec![
+            pv("/dev/sdb", 2000, 1500, false),
+            pv("/dev/sdc", 1000, 100, false),
+        ];
+        // 1500 allocated, 900 free on sdc
+        assert!(!can_evacuate(&full, &full[0]));
+        // 100 allocated, 500 free on sdb
+        assert!(can_evacuate(&full, &full[1]));
+
+        // Nothing can move onto a missing disk
+        let missing = vec![
+            pv("/dev/sdb", 1000, 800, false),
+            pv("[unknown]", 1000, 0, true),
+        ];
+        assert!(!can_evacuate(&missing, &missing[0]));
+    }
+
+    #[test]
+    fn test_parse_pvs() {
+        let output = "  /dev/sdb:data:uuid-/dev/sdb:25599:12800:\n  /dev/sdc:data:uuid-/dev/sdc:25599:0:\n  [unknown]:data:uuid-[unknown]:25599:100:missing\n  /dev/sda2:root:uuid-/dev/sda2:10000:10000:\n";
+        let pvs = parse_pvs(output, "data");
+        assert_eq!(
+            pvs,
+            vec![
+                pv("/dev/sdb", 25599, 12800, false),
+                pv("/dev/sdc", 25599, 0, false),
+                pv("[unknown]", 25599, 100, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_pv() {
+        let pvs = vec![
+            pv("/dev/sdb", 1000, 800, false),
+            PvUsage {
+                uuid: "xBfd2K-3pQ1".to_string(),
+                ..pv("[unknown]", 1000, 100, true)
+            },
+        ];
+        let record = |uuid: &str| PvRecord {
+            device: PathBuf::from("/dev/sdc"),
+            pv_uuid: uuid.to_string(),
+            pvmove_started: false,
+        };
+        assert!(missing_pv(&pvs, Some(&record("xBfd2K-3pQ1"))));
+        // Some other disk is the missing one
+        assert!(!missing_pv(&pvs, Some(&record("9aLm0c-7Tz2"))));
+        assert!(!missing_pv(&pvs, None));
+        // A present physical volume isn't missing even if the uuid matches
+        assert!(!missing_pv(&pvs, Some(&record("uuid-/dev/sdb"))));
+    }
+
+    #[test]
+    fn test_parse_pvmoves() {
+        let output = "  [pvmove0]:/dev/sdb:42.17\n  lv_home::\n  lv_var::\n";
+        assert_eq!(
+            parse_pvmoves(output),
+            vec![PvMove {
+                source: "/dev/sdb".to_string(),
+                percent: 42.17,
+            }]
+        );
+        assert!(parse_pvmoves("  lv_home::\n").is_empty());
+    }
+}
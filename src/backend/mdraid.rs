@@ -0,0 +1,577 @@
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::backend::util::{is_same_device, load_state, run_cmd, save_state};
+use crate::backend::Backend;
+use api::service::{Capabilities, OpOutcome, Progress};
+
+use dirs::home_dir;
+use helpers::{error::*, load_config};
+use log::{debug, error, info};
+use serde_derive::*;
+
+// default location to record removed array members
+fn default_state_file() -> String {
+    "/var/lib/bynar/mdraid_removed.json".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MdraidConfig {
+    /// Only manage these arrays.  ie md0.  Defaults to every array in /proc/mdstat
+    arrays: Option<Vec<String>>,
+    /// The file used to remember removed array members until the disk is replaced
+    #[serde(default = "default_state_file")]
+    state_file: String,
+}
+
+/// An array member that was removed and is waiting for a replacement disk
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct RemovedMember {
+    /// The device the member lived on
+    device: PathBuf,
+    /// ie /dev/md0
+    array: String,
+    /// The member as the array knows it.  ie /dev/sdc1
+    member: String,
+    /// A surviving disk whose partition table gets copied to the
+    /// replacement when the member was a partition
+    partition_table_from: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct MdMember {
+    /// ie sdc1
+    name: String,
+    faulty: bool,
+    spare: bool,
+}
+
+/// A resync, recovery or reshape running on an array
+#[derive(Clone, Debug, PartialEq)]
+pub struct RebuildProgress {
+    /// resync, recovery, reshape or check
+    pub action: String,
+    pub percent_done: f64,
+    /// Estimated minutes until done
+    pub finish_minutes: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct MdArray {
+    /// ie md0
+    name: String,
+    /// ie raid1
+    level: String,
+    members: Vec<MdMember>,
+    /// The number of devices the array should have
+    raid_disks: usize,
+    /// The number of devices currently in sync
+    active_disks: usize,
+    rebuild: Option<RebuildProgress>,
+}
+
+impl MdArray {
+    // How many devices can fail before the array loses data
+    fn redundancy(&self) -> usize {
+        match self.level.as_str() {
+            "raid1" => self.raid_disks.saturating_sub(1),
+            "raid4" | "raid5" | "raid10" => 1,
+            "raid6" => 2,
+            _ => 0,
+        }
+    }
+
+    fn members_on<'a>(&'a self, device: &'a Path) -> impl Iterator<Item = &'a MdMember> + 'a {
+        self.members
+            .iter()
+            .filter(move |m| is_same_device(&Path::new("/dev").join(&m.name), device))
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.rebuild.is_none() && self.active_disks >= self.raid_disks
+    }
+}
+
+/// Linux software raid
+pub struct MdraidBackend {
+    config: MdraidConfig,
+}
+
+impl MdraidBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<MdraidBackend> {
+        let config: MdraidConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "mdraid.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "mdraid.json")?
+            }
+        };
+        debug!("Loaded mdraid config: {:?}", config);
+        Ok(MdraidBackend { config })
+    }
+
+    fn arrays(&self) -> BynarResult<Vec<MdArray>> {
+        let mdstat = read_to_string("/proc/mdstat")?;
+        let arrays = parse_mdstat(&mdstat);
+        Ok(match self.config.arrays {
+            Some(ref names) => arrays
+                .into_iter()
+                .filter(|a| names.contains(&a.name))
+                .collect(),
+            None => arrays,
+        })
+    }
+
+    // Every array member on a device.  A partitioned disk can be in several
+    // arrays, ie sdc1 in md0 and sdc2 in md1
+    fn find_device(&self, device: &Path) -> BynarResult<Vec<(MdArray, MdMember)>> {
+        Ok(members_on(&self.arrays()?, device))
+    }
+
+    /// Get the rebuild progress of an array a device belongs to, or was
+    /// removed from.  None means none of them are rebuilding
+    pub fn rebuild_progress(&self, device: &Path) -> BynarResult<Option<RebuildProgress>> {
+        let mut array_names: Vec<String> = self
+            .find_device(device)?
+            .into_iter()
+            .map(|(array, _)| array.name)
+            .collect();
+        for r in self
+            .load_removed()?
+            .into_iter()
+            .filter(|r| r.device == device)
+        {
+            array_names.push(r.array.trim_start_matches("/dev/").to_string());
+        }
+        Ok(self
+            .arrays()?
+            .into_iter()
+            .filter(|a| array_names.contains(&a.name))
+            .filter_map(|a| a.rebuild)
+            .next())
+    }
+
+    fn load_removed(&self) -> BynarResult<Vec<RemovedMember>> {
+        load_state(Path::new(&self.config.state_file))
+    }
+
+    fn save_removed(&self, removed: &[RemovedMember]) -> BynarResult<()> {
+        save_state(Path::new(&self.config.state_file), removed)
+    }
+}
+
+impl Backend for MdraidBackend {
+    fn add_disk(&self, device: &Path, _id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        let (records, mut removed): (Vec<RemovedMember>, Vec<RemovedMember>) = self
+            .load_removed()?
+            .into_iter()
+            .partition(|r| r.device == device);
+        if records.is_empty() {
+            if !self.find_device(device)?.is_empty() {
+                debug!(
+                    "Device {} is already in an array.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::SkipRepeat);
+            }
+            debug!(
+                "No removed array member recorded for {}.  Skipping",
+                device.display()
+            );
+            return Ok(OpOutcome::Skipped);
+        }
+        // Every partitioned member had the same partition table
+        if let Some(source) = records.iter().find_map(|r| r.partition_table_from.as_ref()) {
+            copy_partition_table(Path::new(source), device, simulate)?;
+        }
+        let mut result = Ok(OpOutcome::Success);
+        for record in records {
+            info!("Adding {} to {}", record.member, record.array);
+            if let Err(e) = run_cmd("mdadm", &[&record.array, "--add", &record.member], simulate) {
+                error!(
+                    "Adding {} to {} failed: {:?}",
+                    record.member, record.array, e
+                );
+                // Tried again on the next add
+                removed.push(record);
+                result = Err(e);
+            }
+        }
+        if !simulate {
+            self.save_removed(&removed)?;
+        }
+        result
+    }
+
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut removed = self.load_removed()?;
+        // Members a partial removal left behind are removed on the retry
+        let found = self.find_device(device)?;
+        if found.is_empty() {
+            if removed.iter().any(|r| r.device == device) {
+                debug!(
+                    "Array members on {} were already removed.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::SkipRepeat);
+            }
+            debug!("Device {} is not in an array.  Skipping", device.display());
+            return Ok(OpOutcome::Skipped);
+        }
+        let mut result = Ok(OpOutcome::Success);
+        for (array, member) in found {
+            let array_path = format!("/dev/{}", array.name);
+            let member_path = format!("/dev/{}", member.name);
+            let fail_remove = if member.faulty {
+                Ok(())
+            } else {
+                run_cmd("mdadm", &[&array_path, "--fail", &member_path], simulate)
+            }
+            .and_then(|_| run_cmd("mdadm", &[&array_path, "--remove", &member_path], simulate));
+            if let Err(e) = fail_remove {
+                // The members that did come out are still recorded
+                error!(
+                    "Removing {} from {} failed: {:?}",
+                    member_path, array_path, e
+                );
+                result = Err(e);
+                continue;
+            }
+
+            // A partitioned member needs the same partition layout on the replacement
+            let partition_table_from = if Path::new(&member_path) != device {
+                array
+                    .members
+                    .iter()
+                    .filter(|m| m.name != member.name && !m.faulty)
+                    .map(|m| parent_device(&format!("/dev/{}", m.name)))
+                    .find(|p| Path::new(p) != device)
+            } else {
+                None
+            };
+            removed.push(RemovedMember {
+                device: device.to_path_buf(),
+                array: array_path,
+                member: member_path,
+                partition_table_from,
+            });
+        }
+        if !simulate {
+            self.save_removed(&removed)?;
+        }
+        result
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let found = self.find_device(device)?;
+        if found.is_empty() {
+            debug!("Device {} is not in an array.  Skipping", device.display());
+            return Ok((OpOutcome::Skipped, false));
+        }
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        // Every array the disk is in has to be able to lose it
+        Ok((
+            OpOutcome::Success,
+            found
+                .iter()
+                .all(|(array, member)| array_can_lose(array, member)),
+        ))
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let removed = self.load_removed()?;
+        if !removed.iter().any(|r| r.device == device) {
+            if !self.find_device(device)?.is_empty() {
+                return Ok((OpOutcome::SkipRepeat, false));
+            }
+            return Ok((OpOutcome::Skipped, false));
+        }
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        // Adding during a resync just restarts the resync
+        match self.rebuild_progress(device)? {
+            Some(progress) => {
+                error!(
+                    "Array is in {} at {}%.  Not safe to add {}",
+                    progress.action,
+                    progress.percent_done,
+                    device.display()
+                );
+                Ok((OpOutcome::Success, false))
+            }
+            None => Ok((OpOutcome::Success, true)),
+        }
+    }
+
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>> {
+        Ok(self.rebuild_progress(device)?.map(|rebuild| {
+            let mut progress = Progress::new();
+            progress.set_operation(rebuild.action);
+            progress.set_percent_done(rebuild.percent_done);
+            if let Some(minutes) = rebuild.finish_minutes {
+                progress.set_eta_seconds((minutes * 60.0) as u64);
+            }
+            progress
+        }))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(false);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(true);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(true);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+// Check that the array keeps its data if the member is taken out of it
+fn array_can_lose(array: &MdArray, member: &MdMember) -> bool {
+    // Faulty and spare members hold no data the array depends on
+    if member.faulty || member.spare {
+        return true;
+    }
+    if array.redundancy() == 0 {
+        error!("Array {} ({}) has no redundancy", array.name, array.level);
+        return false;
+    }
+    if !array.is_healthy() {
+        error!(
+            "Array {} is already degraded [{}/{}] {:?}",
+            array.name, array.raid_disks, array.active_disks, array.rebuild
+        );
+        return false;
+    }
+    true
+}
+
+// sfdisk -d source | sfdisk dest
+fn copy_partition_table(source: &Path, dest: &Path, simulate: bool) -> BynarResult<()> {
+    debug!(
+        "Copying partition table from {} to {}",
+        source.display(),
+        dest.display()
+    );
+    if simulate {
+        return Ok(());
+    }
+    let dump = Command::new("sfdisk").arg("-d").arg(source).output()?;
+    if !dump.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&dump.stderr).into_owned(),
+        ));
+    }
+    let mut child = Command::new("sfdisk")
+        .arg(dest)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(ref mut stdin) = child.stdin {
+        stdin.write_all(&dump.stdout)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(())
+}
+
+// Parse /proc/mdstat
+// md1 : active raid5 sde1[3] sdd1[1] sdc1[0](F)
+//       2093056 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]
+//       [==>..................]  recovery = 12.6% (132480/1046528) finish=0.6min speed=22080K/sec
+fn parse_mdstat(mdstat: &str) -> Vec<MdArray> {
+    let mut arrays: Vec<MdArray> = Vec::new();
+    for line in mdstat.lines() {
+        let trimmed = line.trim();
+        if line.starts_with("md") && trimmed.contains(" : ") {
+            let mut parts = trimmed.split_whitespace();
+            let name = parts.next().unwrap_or("").to_string();
+            // skip the colon and active/inactive
+            let _ = parts.next();
+            let _ = parts.next();
+            let mut level = String::new();
+            let mut members: Vec<MdMember> = Vec::new();
+            for part in parts {
+                if part.starts_with('(') {
+                    // (auto-read-only) and friends
+                    continue;
+                }
+                match part.find('[') {
+                    Some(idx) => members.push(MdMember {
+                        name: part[..idx].to_string(),
+                        faulty: part.ends_with("(F)"),
+                        spare: part.ends_with("(S)"),
+                    }),
+                    None => level = part.to_string(),
+                }
+            }
+            arrays.push(MdArray {
+                name,
+                level,
+                members,
+                raid_disks: 0,
+                active_disks: 0,
+                rebuild: None,
+            });
+            continue;
+        }
+        let array = match arrays.last_mut() {
+            Some(a) => a,
+            None => continue,
+        };
+        if trimmed.contains(" blocks") {
+            // [3/2] is raid disks/active disks
+            for part in trimmed.split_whitespace() {
+                if part.starts_with('[') && part.contains('/') {
+                    let counts: Vec<&str> = part
+                        .trim_matches(|c: char| c == '[' || c == ']')
+                        .split('/')
+                        .collect();
+                    if counts.len() == 2 {
+                        array.raid_disks = counts[0].parse().unwrap_or(0);
+                        array.active_disks = counts[1].parse().unwrap_or(0);
+                    }
+                }
+            }
+            // raid0 and linear don't report disk counts
+            if array.raid_disks == 0 {
+                array.raid_disks = array.members.len();
+                array.active_disks = array.members.iter().filter(|m| !m.faulty).count();
+            }
+        } else if trimmed.contains('%') && trimmed.contains(" = ") {
+            let mut action = String::new();
+            let mut percent_done = 0.0;
+            let mut finish_minutes = None;
+            let mut prev = "";
+            for part in trimmed.split_whitespace() {
+                if part == "=" {
+                    action = prev.to_string();
+                } else if part.ends_with('%') {
+                    percent_done = part.trim_end_matches('%').parse().unwrap_or(0.0);
+                } else if part.starts_with("finish=") {
+                    finish_minutes = part
+                        .trim_start_matches("finish=")
+                        .trim_end_matches("min")
+                        .parse()
+                        .ok();
+                }
+                prev = part;
+            }
+            array.rebuild = Some(RebuildProgress {
+                action,
+                percent_done,
+                finish_minutes,
+            });
+        } else if trimmed.contains("resync=DELAYED") || trimmed.contains("recovery=DELAYED") {
+            array.rebuild = Some(RebuildProgress {
+                action: trimmed.split('=').next().unwrap_or("").to_string(),
+                percent_done: 0.0,
+                finish_minutes: None,
+            });
+        }
+    }
+    arrays
+}
+
+// Every member on device across the arrays
+fn members_on(arrays: &[MdArray], device: &Path) -> Vec<(MdArray, MdMember)> {
+    let mut found = Vec::new();
+    for array in arrays {
+        for member in array.members_on(device) {
+            found.push((array.clone(), member.clone()));
+        }
+    }
+    found
+}
+
+// /dev/sda1 -> /dev/sda, /dev/nvme0n1p1 -> /dev/nvme0n1
+fn parent_device(partition: &str) -> String {
+    let trimmed = partition.trim_end_matches(|c: char| c.is_digit(10));
+    // nvme and mmc devices put a p between the disk and the partition number
+    if trimmed.ends_with('p') {
+        let disk = &trimmed[..trimmed.len() - 1];
+        if disk.ends_with(|c: char| c.is_digit(10)) {
+            return disk.to_string();
+        }
+    }
+    trimmed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MDSTAT: &str = r#"Personalities : [raid1] [raid6] [raid5] [raid4]
+md0 : active raid1 sdb1[1] sda1[0]
+      1046528 blocks super 1.2 [2/2] [UU]
+
+md1 : active raid5 sde1[3] sdd1[1] sdc1[0](F)
+      2093056 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [UU_]
+      [==>..................]  recovery = 12.6% (132480/1046528) finish=0.6min speed=22080K/sec
+
+unused devices: <none>
+"#;
+
+    #[test]
+    fn test_parse_mdstat() {
+        let arrays = parse_mdstat(MDSTAT);
+        assert_eq!(arrays.len(), 2);
+        assert_eq!(arrays[0].name, "md0");
+        assert_eq!(arrays[0].level, "raid1");
+        assert_eq!(arrays[0].members.len(), 2);
+        assert!(arrays[0].is_healthy());
+        assert_eq!(arrays[1].raid_disks, 3);
+        assert_eq!(arrays[1].active_disks, 2);
+        assert!(arrays[1].members[2].faulty);
+        assert_eq!(
+            arrays[1].rebuild,
+            Some(RebuildProgress {
+                action: "recovery".to_string(),
+                percent_done: 12.6,
+                finish_minutes: Some(0.6),
+            })
+        );
+    }
+
+    #[test]
+    fn test_array_can_lose() {
+        let arrays = parse_mdstat(MDSTAT);
+        let md0 = &arrays[0];
+        let member = md0.members_on(Path::new("/dev/sdb")).next().unwrap();
+        assert!(array_can_lose(md0, member));
+
+        // md1 is already degraded, only the failed member can go
+        let md1 = &arrays[1];
+        let failed = md1.members_on(Path::new("/dev/sdc")).next().unwrap();
+        assert!(array_can_lose(md1, failed));
+        let good = md1.members_on(Path::new("/dev/sdd")).next().unwrap();
+        assert!(!array_can_lose(md1, good));
+
+        // sdd is in both arrays
+        let mdstat = MDSTAT.replace("sdb1[1]", "sdd2[1]");
+        let arrays = parse_mdstat(&mdstat);
+        let found = members_on(&arrays, Path::new("/dev/sdd"));
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0.name, "md0");
+        assert_eq!(found[0].1.name, "sdd2");
+        assert_eq!(found[1].0.name, "md1");
+        assert_eq!(found[1].1.name, "sdd1");
+
+        assert_eq!(parent_device("/dev/sda1"), "/dev/sda");
+        assert_eq!(parent_device("/dev/nvme0n1p2"), "/dev/nvme0n1");
+    }
+}
@@ -1,13 +1,28 @@
+#[cfg(feature = "ceph")]
 pub mod ceph;
-//#[cfg(feature = "gluster")]
+pub mod exec;
+#[cfg(feature = "gluster")]
 pub mod gluster;
+pub mod lvm;
+pub mod mdraid;
+pub mod scaleio;
+pub mod selector;
+pub mod util;
+pub mod zfs;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use self::ceph::CephBackend;
+#[cfg(feature = "ceph")]
+use self::ceph::CephClusters;
+use self::exec::ExecBackend;
+#[cfg(feature = "gluster")]
 use self::gluster::GlusterBackend;
-use api::service::OpOutcome;
+use self::lvm::LvmBackend;
+use self::mdraid::MdraidBackend;
+use self::scaleio::ScaleioBackend;
+use self::zfs::ZfsBackend;
+use api::service::{BlockingPg, Capabilities, OpOutcome, Progress, SuspectDisk};
 use helpers::error::*;
 use serde_derive::*;
 
@@ -19,10 +34,14 @@ pub trait Backend {
     /// For gluster or other services it might be much easier
     /// If simulate is passed no action should be taken
     /// An optional osd_id can be provided to ensure the osd is set to that
+    /// Like remove_disk this can return InProgress to be called again later
     fn add_disk(&self, device: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome>;
 
     /// Remove a disk from a cluster
     /// If simulate is passed no action should be taken
+    /// A backend that has to move data off the disk first can return
+    /// InProgress.  disk-manager calls remove_disk again later until it
+    /// returns something else
     fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome>;
 
     /// Check if it's safe to remove a disk from a cluster
@@ -30,14 +49,97 @@ pub trait Backend {
     /// Take any actions needed with this call to figure out if a disk is safe
     /// to remove from the cluster.
     fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)>;
+
+    /// Check the cluster wide policy every removal has to pass, such as the
+    /// health of the cluster or how full it would get without the disk.
+    /// disk-manager asks before safe_to_remove.  Some(reason) refuses the removal
+    /// None by default, for backends without a policy of their own
+    fn check_removal_policy(&self, _device: &Path) -> BynarResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Check if it's safe to add a disk to a cluster
+    /// If simulate is passed then this always returns true
+    /// A disk can be safe to remove but not safe to add, for example when
+    /// the cluster is unhealthy or already busy moving data around
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)>;
+
+    /// Report on a long running operation involving a disk, such as an array
+    /// rebuild after the disk was added.  None means nothing is running
+    fn get_progress(&self, device: &Path) -> BynarResult<Option<Progress>>;
+
+    /// Describe which operations and Operation fields this backend honors
+    /// so clients can adapt instead of guessing
+    fn capabilities(&self) -> Capabilities;
+
+    /// The capabilities that apply to one disk.  A backend serving more than
+    /// one storage cluster answers for the cluster the disk is in
+    fn device_capabilities(&self, _device: &Path) -> BynarResult<Capabilities> {
+        Ok(self.capabilities())
+    }
+
+    /// The version of the storage service, if the backend knows it
+    fn version(&self) -> Option<String>;
+
+    /// The id a disk has in the cluster, ie the ceph osd id.  remove_disk
+    /// keeps the id free so add_disk can give it to the replacement disk.
+    /// None if the backend doesn't use ids
+    fn disk_id(&self, _device: &Path) -> BynarResult<Option<u64>> {
+        Ok(None)
+    }
+
+    /// The other disks that stop working when device fails, ie the ceph osds
+    /// sharing a journal device.  remove_disk on device removes them too and
+    /// add_disk on its replacement brings them back
+    fn dependent_disks(&self, _device: &Path) -> BynarResult<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// The placement groups that would go inactive or below min_size without
+    /// device.  Explains why safe_to_remove said no
+    fn blocking_pgs(&self, _device: &Path) -> BynarResult<Vec<BlockingPg>> {
+        Ok(Vec::new())
+    }
+
+    /// Find what earlier adds left on the host that nothing uses anymore,
+    /// ie an fstab entry for a removed disk.  Removes them too if fix is set
+    fn host_hygiene(&self, _fix: bool) -> BynarResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// The disks the storage service sees failing before SMART does, ie a
+    /// ceph osd that keeps going down.  bynar evaluates them again even if
+    /// SMART passes
+    fn suspect_disks(&self) -> BynarResult<Vec<SuspectDisk>> {
+        Ok(Vec::new())
+    }
+
+    /// Stop a long running operation that add_disk or remove_disk answered
+    /// with InProgress.  The disk is left wherever the operation got to.
+    /// disk-manager calls this when a job is cancelled.  Backends whose adds
+    /// and removes finish within the call have nothing to stop
+    fn cancel_operation(&self, _device: &Path) -> BynarResult<()> {
+        Ok(())
+    }
 }
 
 /// The supported backend types
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum BackendType {
     #[serde(alias = "ceph")]
     Ceph,
+    #[serde(alias = "gluster")]
     Gluster,
+    #[serde(alias = "scaleio")]
+    Scaleio,
+    #[serde(alias = "zfs")]
+    Zfs,
+    #[serde(alias = "mdraid")]
+    Mdraid,
+    #[serde(alias = "exec")]
+    Exec,
+    #[serde(alias = "lvm")]
+    Lvm,
 }
 
 impl FromStr for BackendType {
@@ -48,6 +150,11 @@ impl FromStr for BackendType {
         match match_str.as_ref() {
             "ceph" => Ok(BackendType::Ceph),
             "gluster" => Ok(BackendType::Gluster),
+            "scaleio" | "powerflex" => Ok(BackendType::Scaleio),
+            "zfs" => Ok(BackendType::Zfs),
+            "mdraid" => Ok(BackendType::Mdraid),
+            "exec" => Ok(BackendType::Exec),
+            "lvm" => Ok(BackendType::Lvm),
             _ => Err(BynarError::new(format!("Unknown backend type: {}", s))),
         }
     }
@@ -59,10 +166,30 @@ pub fn load_backend(
     config_dir: Option<&Path>,
 ) -> BynarResult<Box<dyn Backend>> {
     let backend: Box<dyn Backend> = match *backend_type {
-        BackendType::Ceph => Box::new(CephBackend::new(config_dir)?),
-        //#[cfg(feature = "gluster")]
-        BackendType::Gluster => Box::new(GlusterBackend {}),
+        #[cfg(feature = "ceph")]
+        BackendType::Ceph => Box::new(CephClusters::new(config_dir)?),
+        #[cfg(not(feature = "ceph"))]
+        BackendType::Ceph => return Err(not_built("ceph")),
+        #[cfg(feature = "gluster")]
+        BackendType::Gluster => Box::new(GlusterBackend::new(config_dir)?),
+        #[cfg(not(feature = "gluster"))]
+        BackendType::Gluster => return Err(not_built("gluster")),
+        BackendType::Scaleio => Box::new(ScaleioBackend::new(config_dir)?),
+        BackendType::Zfs => Box::new(ZfsBackend::new(config_dir)?),
+        BackendType::Mdraid => Box::new(MdraidBackend::new(config_dir)?),
+        BackendType::Exec => Box::new(ExecBackend::new(config_dir)?),
+        BackendType::Lvm => Box::new(LvmBackend::new(config_dir)?),
     };
 
     Ok(backend)
 }
+
+// The backend types stay around so configs still parse when a backend
+// isn't compiled in.  Loading one of those is an error
+#[cfg(not(all(feature = "ceph", feature = "gluster")))]
+fn not_built(feature: &str) -> BynarError {
+    BynarError::new(format!(
+        "Bynar was built without the {} feature.  Rebuild with --features {}",
+        feature, feature
+    ))
+}
@@ -0,0 +1,642 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::backend::Backend;
+use api::service::{Capabilities, OpOutcome, Progress};
+
+use dirs::home_dir;
+use helpers::{error::*, load_config};
+use hostname::get_hostname;
+use log::{debug, error, info};
+use serde_derive::*;
+
+// default path to the scli binary
+fn default_scli_path() -> String {
+    "scli".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ScaleioConfig {
+    /// Path to the scli binary
+    #[serde(default = "default_scli_path")]
+    scli_path: String,
+    /// The MDM to send commands to.  scli defaults to the local MDM
+    mdm_ip: Option<String>,
+    /// Credentials used to log into the MDM
+    username: Option<String>,
+    password: Option<String>,
+    /// The name of this SDS.  Defaults to the hostname of this server
+    sds_name: Option<String>,
+    /// The protection domain the storage pool is in
+    protection_domain_name: String,
+    /// The storage pool replacement devices are added to
+    storage_pool_name: String,
+}
+
+/// Runs scli commands and returns stdout.  This is a trait so the
+/// backend can be tested without a ScaleIO cluster
+pub trait Scli {
+    fn run(&self, args: &[&str]) -> BynarResult<String>;
+    /// Run a command that reads what it's asked for, ie a password, on stdin
+    fn run_with_input(&self, args: &[&str], input: &str) -> BynarResult<String>;
+}
+
+/// Runs the real scli binary
+struct ScliCommand {
+    path: String,
+    mdm_ip: Option<String>,
+}
+
+impl ScliCommand {
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.path);
+        if let Some(ref mdm_ip) = self.mdm_ip {
+            cmd.args(&["--mdm_ip", mdm_ip]);
+        }
+        cmd.args(args);
+        debug!("cmd: {:?}", cmd);
+        cmd
+    }
+}
+
+impl Scli for ScliCommand {
+    fn run(&self, args: &[&str]) -> BynarResult<String> {
+        let output = self.command(args).output()?;
+        if !output.status.success() {
+            return Err(BynarError::new(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn run_with_input(&self, args: &[&str], input: &str) -> BynarResult<String> {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(BynarError::new(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// A device attached to this SDS
+#[derive(Clone, Debug, PartialEq)]
+struct SdsDevice {
+    id: String,
+    path: String,
+    storage_pool: String,
+    capacity: u64,
+    state: String,
+}
+
+/// The capacity figures of a storage pool, in bytes
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PoolCapacity {
+    total: u64,
+    unused: u64,
+    spare: u64,
+    degraded: u64,
+    failed: u64,
+}
+
+/// ScaleIO / PowerFlex SDS
+pub struct ScaleioBackend {
+    config: ScaleioConfig,
+    sds_name: String,
+    scli: Box<dyn Scli>,
+}
+
+impl ScaleioBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<ScaleioBackend> {
+        let config: ScaleioConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "scaleio.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "scaleio.json")?
+            }
+        };
+        let scli = Box::new(ScliCommand {
+            path: config.scli_path.clone(),
+            mdm_ip: config.mdm_ip.clone(),
+        });
+        let backend = ScaleioBackend::with_scli(config, scli)?;
+        backend.login()?;
+        Ok(backend)
+    }
+
+    // Log into the MDM.  scli prompts for the password when it isn't on the
+    // command line, so it goes in on stdin and never shows up in ps
+    fn login(&self) -> BynarResult<()> {
+        if let (Some(user), Some(password)) = (&self.config.username, &self.config.password) {
+            info!("Logging into the ScaleIO MDM");
+            self.scli
+                .run_with_input(&["--login", "--username", user], &format!("{}\n", password))?;
+        }
+        Ok(())
+    }
+
+    // Run an scli command, logging in again if the MDM session ran out
+    fn run_scli(&self, args: &[&str]) -> BynarResult<String> {
+        match self.scli.run(args) {
+            Err(ref e) if self.config.username.is_some() && is_auth_error(&e.to_string()) => {
+                debug!("The MDM session ran out: {}", e);
+                self.login()?;
+                self.scli.run(args)
+            }
+            result => result,
+        }
+    }
+
+    fn with_scli(config: ScaleioConfig, scli: Box<dyn Scli>) -> BynarResult<ScaleioBackend> {
+        let sds_name = match config.sds_name {
+            Some(ref name) => name.clone(),
+            None => get_hostname().ok_or_else(|| BynarError::from("hostname not found"))?,
+        };
+        Ok(ScaleioBackend {
+            config,
+            sds_name,
+            scli,
+        })
+    }
+
+    fn sds_devices(&self) -> BynarResult<Vec<SdsDevice>> {
+        let output = self.run_scli(&["--query_sds", "--sds_name", &self.sds_name])?;
+        Ok(parse_sds_devices(&output))
+    }
+
+    fn find_device(&self, device: &Path) -> BynarResult<Option<SdsDevice>> {
+        let path = device.to_string_lossy();
+        Ok(self.sds_devices()?.into_iter().find(|d| d.path == path))
+    }
+
+    fn pool_capacity(&self, storage_pool: &str) -> BynarResult<PoolCapacity> {
+        let output = self.run_scli(&[
+            "--query_storage_pool",
+            "--protection_domain_name",
+            &self.config.protection_domain_name,
+            "--storage_pool_name",
+            storage_pool,
+        ])?;
+        Ok(parse_pool_capacity(&output))
+    }
+}
+
+impl Backend for ScaleioBackend {
+    fn add_disk(&self, device: &Path, _id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        if self.find_device(device)?.is_some() {
+            debug!(
+                "Device {} is already in the storage pool.  Skipping",
+                device.display()
+            );
+            return Ok(OpOutcome::SkipRepeat);
+        }
+        let path = device.to_string_lossy();
+        let args: &[&str] = &[
+            "--add_sds_device",
+            "--sds_name",
+            &self.sds_name,
+            "--device_path",
+            &path,
+            "--protection_domain_name",
+            &self.config.protection_domain_name,
+            "--storage_pool_name",
+            &self.config.storage_pool_name,
+        ];
+        debug!("scli {:?}", args);
+        if !simulate {
+            self.run_scli(args)?;
+        }
+        Ok(OpOutcome::Success)
+    }
+
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let sds_device = match self.find_device(device)? {
+            Some(d) => d,
+            None => {
+                debug!(
+                    "Device {} is not in a storage pool.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::SkipRepeat);
+            }
+        };
+        let args: &[&str] = &[
+            "--remove_sds_device",
+            "--sds_name",
+            &self.sds_name,
+            "--device_id",
+            &sds_device.id,
+        ];
+        debug!("scli {:?}", args);
+        if !simulate {
+            self.run_scli(args)?;
+        }
+        Ok(OpOutcome::Success)
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        let sds_device = match self.find_device(device)? {
+            Some(d) => d,
+            None => {
+                debug!(
+                    "Device {} is not in a storage pool.  Skipping",
+                    device.display()
+                );
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        let capacity = self.pool_capacity(&sds_device.storage_pool)?;
+        debug!(
+            "Storage pool {} capacity: {:?}",
+            sds_device.storage_pool, capacity
+        );
+        // A rebuild is already running.  Removing another device now risks data
+        if capacity.degraded > 0 || capacity.failed > 0 {
+            error!(
+                "Storage pool {} is rebuilding.  Not safe to remove {}",
+                sds_device.storage_pool,
+                device.display()
+            );
+            return Ok((OpOutcome::Success, false));
+        }
+        // The data on the device has to be rebuilt/rebalanced into the unused
+        // capacity without eating into the spare capacity
+        let remaining = capacity.unused.saturating_sub(sds_device.capacity);
+        if remaining < capacity.spare {
+            error!(
+                "Storage pool {} doesn't have the capacity to rebuild {}",
+                sds_device.storage_pool,
+                device.display()
+            );
+            return Ok((OpOutcome::Success, false));
+        }
+        Ok((OpOutcome::Success, true))
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        if self.find_device(device)?.is_some() {
+            debug!(
+                "Device {} is already in the storage pool.  Skipping",
+                device.display()
+            );
+            return Ok((OpOutcome::SkipRepeat, false));
+        }
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        // A pool with failed capacity is still rebuilding from the last failure.
+        // Adding a device now triggers a rebalance on top of the rebuild
+        let capacity = self.pool_capacity(&self.config.storage_pool_name)?;
+        if capacity.failed > 0 {
+            error!(
+                "Storage pool {} is rebuilding.  Not safe to add {}",
+                self.config.storage_pool_name,
+                device.display()
+            );
+            return Ok((OpOutcome::Success, false));
+        }
+        Ok((OpOutcome::Success, true))
+    }
+
+    fn get_progress(&self, _device: &Path) -> BynarResult<Option<Progress>> {
+        Ok(None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Loading the backend logs into the MDM even when simulating
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(false);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(false);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(false);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+// Parse the device section of scli --query_sds
+//  1: Name: N/A  Path: /dev/sdb  Original-path: /dev/sdb  ID: ddfb5d5d00010000
+//      Storage Pool: sp1, Capacity: 99 GB, State: Normal
+fn parse_sds_devices(output: &str) -> Vec<SdsDevice> {
+    let mut devices: Vec<SdsDevice> = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        if line.contains("Path:") && line.contains("ID:") {
+            let path = field_after(line, "Path:").unwrap_or_default();
+            let id = field_after(line, "ID:").unwrap_or_default();
+            devices.push(SdsDevice {
+                id,
+                path,
+                storage_pool: String::new(),
+                capacity: 0,
+                state: String::new(),
+            });
+        } else if line.starts_with("Storage Pool:") {
+            if let Some(d) = devices.last_mut() {
+                for part in line.split(',') {
+                    let mut kv = part.splitn(2, ':');
+                    let key = kv.next().unwrap_or("").trim();
+                    let value = kv.next().unwrap_or("").trim();
+                    match key {
+                        "Storage Pool" => d.storage_pool = value.to_string(),
+                        "Capacity" => d.capacity = parse_size(value).unwrap_or(0),
+                        "State" => d.state = value.to_string(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    devices
+}
+
+// Parse the capacity section of scli --query_storage_pool
+//  3.3 TB (3372 GB) total capacity
+//  2.0 TB (2086 GB) unused capacity
+fn parse_pool_capacity(output: &str) -> PoolCapacity {
+    let mut capacity = PoolCapacity::default();
+    for line in output.lines() {
+        let line = line.trim();
+        let size = match parse_size(line) {
+            Some(s) => s,
+            None => continue,
+        };
+        if line.ends_with(" total capacity") {
+            capacity.total = size;
+        } else if line.ends_with(" unused capacity") {
+            capacity.unused = size;
+        } else if line.ends_with(" spare capacity") {
+            capacity.spare = size;
+        } else if line.ends_with(" degraded-failed capacity")
+            || line.ends_with(" degraded-healthy capacity")
+        {
+            capacity.degraded += size;
+        } else if line.ends_with(" failed capacity") {
+            capacity.failed = size;
+        }
+    }
+    capacity
+}
+
+// Get the whitespace delimited word after a key
+fn field_after(line: &str, key: &str) -> Option<String> {
+    let start = line.find(key)? + key.len();
+    line[start..]
+        .split_whitespace()
+        .next()
+        .map(|s| s.to_string())
+}
+
+// Whether scli failed because it isn't logged in, ie
+// "Error: MDM failed command.  Status: Invalid session. Please login and try again"
+fn is_auth_error(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    [
+        "please login",
+        "not logged in",
+        "invalid session",
+        "session expired",
+    ]
+    .iter()
+    .any(|m| msg.contains(m))
+}
+
+// Convert a leading "99 GB" into bytes
+fn parse_size(s: &str) -> Option<u64> {
+    let mut parts = s.split_whitespace();
+    let num: f64 = parts.next()?.parse().ok()?;
+    let multiplier: u64 = match parts.next()? {
+        "Bytes" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        "TB" => 1 << 40,
+        "PB" => 1 << 50,
+        _ => return None,
+    };
+    Some((num * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    static QUERY_SDS: &str = r#"SDS 6d3a2fca00000001 Name: sds1
+Protection Domain: 9b5f8c2a00000000, Name: pd1
+Device information (total 2 devices):
+	1: Name: N/A  Path: /dev/sdb  Original-path: /dev/sdb  ID: ddfb5d5d00010000
+		Storage Pool: sp1, Capacity: 100 GB, State: Normal
+	2: Name: N/A  Path: /dev/sdc  Original-path: /dev/sdc  ID: ddfb5d5e00010001
+		Storage Pool: sp1, Capacity: 100 GB, State: Normal
+"#;
+
+    static QUERY_POOL: &str = r#"Storage Pool sp1 (Id: 1d4c7e5c00000000) has 1 volumes and 600.0 GB (614400 MB) available for volume allocation
+Capacity information:
+	1.0 TB (1024 GB) total capacity
+	500.0 GB (512000 MB) unused capacity
+	0 Bytes snapshots capacity
+	100.0 GB (102400 MB) spare capacity
+	0 Bytes degraded-healthy capacity
+	0 Bytes failed capacity
+"#;
+
+    // Answers scli commands from canned output and remembers what was run.
+    // Until --login is run while logged_out is set every command fails
+    struct FakeScli {
+        responses: HashMap<String, String>,
+        calls: Rc<RefCell<Vec<String>>>,
+        logged_out: Rc<Cell<bool>>,
+    }
+
+    impl Scli for FakeScli {
+        fn run(&self, args: &[&str]) -> BynarResult<String> {
+            self.calls.borrow_mut().push(args.join(" "));
+            if self.logged_out.get() {
+                return Err(BynarError::from(
+                    "Error: MDM failed command.  Status: Invalid session. Please login and try again",
+                ));
+            }
+            Ok(self.responses.get(args[0]).cloned().unwrap_or_default())
+        }
+
+        fn run_with_input(&self, args: &[&str], input: &str) -> BynarResult<String> {
+            self.calls.borrow_mut().push(args.join(" "));
+            if args[0] == "--login" && input == "secret\n" {
+                self.logged_out.set(false);
+            }
+            Ok(String::new())
+        }
+    }
+
+    fn fake_backend(pool: &str) -> (ScaleioBackend, Rc<RefCell<Vec<String>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut responses = HashMap::new();
+        responses.insert("--query_sds".to_string(), QUERY_SDS.to_string());
+        responses.insert("--query_storage_pool".to_string(), pool.to_string());
+        let config = ScaleioConfig {
+            scli_path: default_scli_path(),
+            mdm_ip: None,
+            username: None,
+            password: None,
+            sds_name: Some("sds1".to_string()),
+            protection_domain_name: "pd1".to_string(),
+            storage_pool_name: "sp1".to_string(),
+        };
+        let scli = Box::new(FakeScli {
+            responses,
+            calls: calls.clone(),
+            logged_out: Rc::new(Cell::new(false)),
+        });
+        (ScaleioBackend::with_scli(config, scli).unwrap(), calls)
+    }
+
+    #[test]
+    fn test_parse_sds_devices() {
+        let devices = parse_sds_devices(QUERY_SDS);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices[0],
+            SdsDevice {
+                id: "ddfb5d5d00010000".to_string(),
+                path: "/dev/sdb".to_string(),
+                storage_pool: "sp1".to_string(),
+                capacity: 100 << 30,
+                state: "Normal".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_pool_capacity() {
+        let capacity = parse_pool_capacity(QUERY_POOL);
+        assert_eq!(capacity.total, 1 << 40);
+        assert_eq!(capacity.unused, 500 << 30);
+        assert_eq!(capacity.spare, 100 << 30);
+        assert_eq!(capacity.degraded, 0);
+        assert_eq!(capacity.failed, 0);
+    }
+
+    #[test]
+    fn test_safe_to_remove() {
+        let (backend, _) = fake_backend(QUERY_POOL);
+        let (outcome, safe) = backend
+            .safe_to_remove(Path::new("/dev/sdb"), false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::Success);
+        assert!(safe);
+
+        // Not enough unused capacity to rebuild the device outside the spare
+        let tight =
+            QUERY_POOL.replace("500.0 GB (512000 MB) unused", "150.0 GB (153600 MB) unused");
+        let (backend, _) = fake_backend(&tight);
+        let (_, safe) = backend
+            .safe_to_remove(Path::new("/dev/sdb"), false)
+            .unwrap();
+        assert!(!safe);
+
+        // Already rebuilding
+        let degraded = QUERY_POOL.replace(
+            "0 Bytes degraded-healthy capacity",
+            "10.0 GB (10240 MB) degraded-healthy capacity",
+        );
+        let (backend, _) = fake_backend(&degraded);
+        let (_, safe) = backend
+            .safe_to_remove(Path::new("/dev/sdb"), false)
+            .unwrap();
+        assert!(!safe);
+
+        let (backend, _) = fake_backend(QUERY_POOL);
+        let (outcome, safe) = backend
+            .safe_to_remove(Path::new("/dev/sdz"), false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::Skipped);
+        assert!(!safe);
+    }
+
+    #[test]
+    fn test_remove_and_add_disk() {
+        let (backend, calls) = fake_backend(QUERY_POOL);
+        let outcome = backend.remove_disk(Path::new("/dev/sdc"), false).unwrap();
+        assert_eq!(outcome, OpOutcome::Success);
+        assert!(calls.borrow().contains(
+            &"--remove_sds_device --sds_name sds1 --device_id ddfb5d5e00010001".to_string()
+        ));
+
+        // /dev/sdc is still reported by the fake so adding it is a repeat
+        let outcome = backend
+            .add_disk(Path::new("/dev/sdc"), None, false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::SkipRepeat);
+
+        let outcome = backend
+            .add_disk(Path::new("/dev/sdd"), None, false)
+            .unwrap();
+        assert_eq!(outcome, OpOutcome::Success);
+        assert!(calls.borrow().contains(
+            &"--add_sds_device --sds_name sds1 --device_path /dev/sdd --protection_domain_name pd1 --storage_pool_name sp1".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_login_again() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let logged_out = Rc::new(Cell::new(true));
+        let mut responses = HashMap::new();
+        responses.insert("--query_sds".to_string(), QUERY_SDS.to_string());
+        let config = ScaleioConfig {
+            scli_path: default_scli_path(),
+            mdm_ip: None,
+            username: Some("admin".to_string()),
+            password: Some("secret".to_string()),
+            sds_name: Some("sds1".to_string()),
+            protection_domain_name: "pd1".to_string(),
+            storage_pool_name: "sp1".to_string(),
+        };
+        let scli = Box::new(FakeScli {
+            responses,
+            calls: calls.clone(),
+            logged_out: logged_out.clone(),
+        });
+        let backend = ScaleioBackend::with_scli(config, scli).unwrap();
+        assert!(backend
+            .find_device(Path::new("/dev/sdb"))
+            .unwrap()
+            .is_some());
+        assert!(!logged_out.get());
+        // The password never goes on the command line
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                "--query_sds --sds_name sds1".to_string(),
+                "--login --username admin".to_string(),
+                "--query_sds --sds_name sds1".to_string(),
+            ]
+        );
+        assert!(is_auth_error("Error: Not logged in. Please login first"));
+        assert!(!is_auth_error(
+            "Error: MDM failed command.  Status: Could not find the SDS"
+        ));
+    }
+}
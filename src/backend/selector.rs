@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::backend::BackendType;
+
+use glob::Pattern;
+use helpers::error::*;
+use log::debug;
+use serde_derive::*;
+
+/// Match a device by its path, serial number, WWN or enclosure slot.
+/// Every field that is set has to match
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DeviceSelector {
+    /// A glob on the device path.  ie /dev/sd[b-f]
+    pub path: Option<String>,
+    /// The udev ID_SERIAL or ID_SERIAL_SHORT of the disk
+    pub serial: Option<String>,
+    /// ie 0x5000c500a1b2c3d4.  A naa. prefix is also accepted
+    pub wwn: Option<String>,
+    /// The enclosure slot name from sysfs.  ie Slot 01
+    pub enclosure_slot: Option<String>,
+}
+
+/// A backend and the devices it owns
+#[derive(Clone, Debug, Deserialize)]
+pub struct BackendRoute {
+    pub backend: BackendType,
+    /// The devices this backend manages.  An empty list matches every device
+    #[serde(default)]
+    pub devices: Vec<DeviceSelector>,
+}
+
+/// What udev and sysfs know about a device
+#[derive(Clone, Debug, Default, PartialEq)]
+struct DeviceIds {
+    serials: Vec<String>,
+    wwn: Option<String>,
+    enclosure_slot: Option<String>,
+}
+
+impl DeviceSelector {
+    fn needs_ids(&self) -> bool {
+        self.serial.is_some() || self.wwn.is_some() || self.enclosure_slot.is_some()
+    }
+
+    fn matches(&self, device: &Path, ids: &DeviceIds) -> BynarResult<bool> {
+        if let Some(ref path) = self.path {
+            let pattern = Pattern::new(path)
+                .map_err(|e| BynarError::new(format!("Invalid device glob {}: {}", path, e)))?;
+            if !pattern.matches_path(device) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref serial) = self.serial {
+            if !ids.serials.iter().any(|s| s == serial) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref wwn) = self.wwn {
+            match ids.wwn {
+                Some(ref w) if normalize_wwn(w) == normalize_wwn(wwn) => {}
+                _ => return Ok(false),
+            }
+        }
+        if let Some(ref slot) = self.enclosure_slot {
+            match ids.enclosure_slot {
+                Some(ref s) if s.trim() == slot.trim() => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Find the backend that owns a device.  The first route with a matching
+/// selector wins.  None means no backend manages the device
+pub fn route_device(routes: &[BackendRoute], device: &Path) -> BynarResult<Option<BackendType>> {
+    // Only ask udev and sysfs if a selector needs them
+    let ids = if routes
+        .iter()
+        .any(|r| r.devices.iter().any(DeviceSelector::needs_ids))
+    {
+        get_device_ids(device)?
+    } else {
+        DeviceIds::default()
+    };
+    debug!("Routing {} with {:?}", device.display(), ids);
+    find_route(routes, device, &ids)
+}
+
+/// Whether any of selectors matches device.  An empty list matches nothing
+pub fn device_matches(selectors: &[DeviceSelector], device: &Path) -> BynarResult<bool> {
+    let ids = if selectors.iter().any(DeviceSelector::needs_ids) {
+        get_device_ids(device)?
+    } else {
+        DeviceIds::default()
+    };
+    for selector in selectors {
+        if selector.matches(device, &ids)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn find_route(
+    routes: &[BackendRoute],
+    device: &Path,
+    ids: &DeviceIds,
+) -> BynarResult<Option<BackendType>> {
+    for route in routes {
+        if route.devices.is_empty() {
+            return Ok(Some(route.backend.clone()));
+        }
+        for selector in &route.devices {
+            if selector.matches(device, ids)? {
+                debug!(
+                    "{} matched {:?} for backend {:?}",
+                    device.display(),
+                    selector,
+                    route.backend
+                );
+                return Ok(Some(route.backend.clone()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn get_device_ids(device: &Path) -> BynarResult<DeviceIds> {
+    let mut ids = DeviceIds::default();
+    debug!(
+        "cmd: udevadm info --query=property --name={}",
+        device.display()
+    );
+    let output = Command::new("udevadm")
+        .args(&["info", "--query=property"])
+        .arg(format!("--name={}", device.display()))
+        .output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    let properties = parse_udev_properties(&String::from_utf8_lossy(&output.stdout));
+    for key in &["ID_SERIAL", "ID_SERIAL_SHORT", "ID_SCSI_SERIAL"] {
+        if let Some(serial) = properties.get(*key) {
+            ids.serials.push(serial.clone());
+        }
+    }
+    ids.wwn = properties
+        .get("ID_WWN_WITH_EXTENSION")
+        .or_else(|| properties.get("ID_WWN"))
+        .cloned();
+
+    // Partitions don't have an enclosure slot.  Their disk does
+    let disk: PathBuf = match block_utils::get_parent_devpath_from_path(device) {
+        Ok(Some(parent)) => parent,
+        _ => device.to_path_buf(),
+    };
+    if let Some(name) = disk.file_name() {
+        let sys_dev = Path::new("/sys/class/block").join(name).join("device");
+        if let Ok(entries) = read_dir(&sys_dev) {
+            for entry in entries {
+                let entry = entry?;
+                let entry_name = entry.file_name().to_string_lossy().into_owned();
+                if entry_name.starts_with("enclosure_device:") {
+                    ids.enclosure_slot = Some(entry_name["enclosure_device:".len()..].to_string());
+                }
+            }
+        }
+    }
+    Ok(ids)
+}
+
+// Parse udevadm info --query=property output.  ie ID_SERIAL=ST4000NM0023_Z1Z0ABCD
+fn parse_udev_properties(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => {
+                    Some((key.trim().to_string(), value.trim().to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// udev reports 0x5000c500a1b2c3d4 and sysfs naa.5000c500a1b2c3d4
+fn normalize_wwn(wwn: &str) -> String {
+    let wwn = wwn.trim().to_lowercase();
+    for prefix in &["0x", "naa."] {
+        if wwn.starts_with(prefix) {
+            return wwn[prefix.len()..].to_string();
+        }
+    }
+    wwn
+}
+
+#[test]
+fn test_find_route() {
+    let routes = vec![
+        BackendRoute {
+            backend: BackendType::Zfs,
+            devices: vec![DeviceSelector {
+                serial: Some("Z1Z0ABCD".to_string()),
+                ..Default::default()
+            }],
+        },
+        BackendRoute {
+            backend: BackendType::Lvm,
+            devices: vec![
+                DeviceSelector {
+                    wwn: Some("naa.5000c500a1b2c3d4".to_string()),
+                    ..Default::default()
+                },
+                DeviceSelector {
+                    enclosure_slot: Some("Slot 12".to_string()),
+                    ..Default::default()
+                },
+            ],
+        },
+        BackendRoute {
+            backend: BackendType::Ceph,
+            devices: vec![DeviceSelector {
+                path: Some("/dev/sd[b-f]*".to_string()),
+                ..Default::default()
+            }],
+        },
+    ];
+    let udev = parse_udev_properties(
+        "DEVNAME=/dev/sdc\nID_SERIAL=ST4000NM0023_Z1Z0ABCD\nID_SERIAL_SHORT=Z1Z0ABCD\n",
+    );
+    let serial_ids = DeviceIds {
+        serials: vec![udev["ID_SERIAL"].clone(), udev["ID_SERIAL_SHORT"].clone()],
+        ..Default::default()
+    };
+    let wwn_ids = DeviceIds {
+        wwn: Some("0x5000C500A1B2C3D4".to_string()),
+        ..Default::default()
+    };
+    let slot_ids = DeviceIds {
+        enclosure_slot: Some("Slot 12".to_string()),
+        ..Default::default()
+    };
+    let dev = Path::new("/dev/sdc");
+    assert_eq!(
+        find_route(&routes, dev, &serial_ids).unwrap(),
+        Some(BackendType::Zfs)
+    );
+    assert_eq!(
+        find_route(&routes, dev, &wwn_ids).unwrap(),
+        Some(BackendType::Lvm)
+    );
+    assert_eq!(
+        find_route(&routes, dev, &slot_ids).unwrap(),
+        Some(BackendType::Lvm)
+    );
+    assert_eq!(
+        find_route(&routes, Path::new("/dev/sdd1"), &DeviceIds::default()).unwrap(),
+        Some(BackendType::Ceph)
+    );
+    assert!(
+        find_route(&routes, Path::new("/dev/sdg"), &DeviceIds::default())
+            .unwrap()
+            .is_none()
+    );
+}
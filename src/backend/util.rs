@@ -0,0 +1,110 @@
+//! Helpers the backends share
+use std::fs::{create_dir_all, read_to_string, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use helpers::error::*;
+use log::debug;
+
+/// Run a command.  Nothing is run if simulate is set
+pub fn run_cmd(cmd: &str, args: &[&str], simulate: bool) -> BynarResult<()> {
+    debug!("cmd: {} {:?}", cmd, args);
+    if simulate {
+        return Ok(());
+    }
+    let output = Command::new(cmd).args(args).output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Run a command and return its stdout
+pub fn run_cmd_output(cmd: &str, args: &[&str]) -> BynarResult<String> {
+    debug!("cmd: {} {:?}", cmd, args);
+    let output = Command::new(cmd).args(args).output()?;
+    if !output.status.success() {
+        return Err(BynarError::new(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether member is device or a partition on it.  ie /dev/sdc1 is on /dev/sdc.
+/// Disks whose name ends in a digit put a p before the partition number, so
+/// /dev/nvme0n1p2 is on /dev/nvme0n1 but /dev/nvme0n10 isn't
+pub fn is_same_device(member: &Path, device: &Path) -> bool {
+    if member == device {
+        return true;
+    }
+    let member = member.to_string_lossy();
+    let dev = device.to_string_lossy();
+    if !member.starts_with(dev.as_ref()) {
+        return false;
+    }
+    let rest = &member[dev.len()..];
+    let partition = if dev.ends_with(|c: char| c.is_digit(10)) {
+        if !rest.starts_with('p') {
+            return false;
+        }
+        &rest[1..]
+    } else {
+        rest
+    };
+    !partition.is_empty() && partition.chars().all(|c| c.is_digit(10))
+}
+
+/// Load the records kept in a json state file.  Empty if there's no file yet
+pub fn load_state<T: serde::de::DeserializeOwned>(state_file: &Path) -> BynarResult<Vec<T>> {
+    if !state_file.exists() {
+        return Ok(vec![]);
+    }
+    let state: Vec<T> = serde_json::from_str(&read_to_string(state_file)?)?;
+    Ok(state)
+}
+
+pub fn save_state<T: serde::Serialize>(state_file: &Path, state: &[T]) -> BynarResult<()> {
+    if let Some(parent) = state_file.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut f = File::create(state_file)?;
+    f.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn test_is_same_device() {
+    assert!(is_same_device(Path::new("/dev/sdc"), Path::new("/dev/sdc")));
+    assert!(is_same_device(
+        Path::new("/dev/sdc1"),
+        Path::new("/dev/sdc")
+    ));
+    assert!(is_same_device(
+        Path::new("/dev/nvme0n1p2"),
+        Path::new("/dev/nvme0n1")
+    ));
+    assert!(is_same_device(
+        Path::new("/dev/loop0p1"),
+        Path::new("/dev/loop0")
+    ));
+    assert!(!is_same_device(
+        Path::new("/dev/nvme0n10"),
+        Path::new("/dev/nvme0n1")
+    ));
+    assert!(!is_same_device(
+        Path::new("/dev/loop10"),
+        Path::new("/dev/loop1")
+    ));
+    assert!(!is_same_device(
+        Path::new("/dev/sdca"),
+        Path::new("/dev/sdc")
+    ));
+    assert!(!is_same_device(
+        Path::new("/dev/sdc1x"),
+        Path::new("/dev/sdc")
+    ));
+}
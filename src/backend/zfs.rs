@@ -0,0 +1,489 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::util::{is_same_device, load_state, run_cmd, run_cmd_output, save_state};
+use crate::backend::Backend;
+use api::service::{Capabilities, OpOutcome, Progress};
+
+use dirs::home_dir;
+use helpers::{error::*, load_config};
+use log::{debug, error, info};
+use serde_derive::*;
+
+// default location to record removed vdev members
+fn default_state_file() -> String {
+    "/var/lib/bynar/zfs_removed.json".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ZfsConfig {
+    /// Only manage these pools.  Defaults to every imported pool
+    pools: Option<Vec<String>>,
+    /// The file used to remember removed vdev members until the disk is replaced
+    #[serde(default = "default_state_file")]
+    state_file: String,
+}
+
+/// A vdev member that was taken out of a pool and is waiting for a replacement disk
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct RemovedMember {
+    /// The device the member lived on
+    device: PathBuf,
+    pool: String,
+    /// The member as zpool status knows it.  ie /dev/sdc1
+    member: String,
+    /// A surviving mirror member to attach the replacement to if the
+    /// member was detached from its mirror
+    attach_to: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum VdevSection {
+    Data,
+    Logs,
+    Cache,
+    Spares,
+}
+
+/// A top level vdev of a pool.  A single disk vdev has no members
+#[derive(Clone, Debug, PartialEq)]
+struct Vdev {
+    name: String,
+    state: String,
+    section: VdevSection,
+    members: Vec<VdevMember>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct VdevMember {
+    path: String,
+    state: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Zpool {
+    name: String,
+    state: String,
+    resilvering: bool,
+    vdevs: Vec<Vdev>,
+}
+
+impl Vdev {
+    // How many members can fail before the vdev loses data
+    fn redundancy(&self) -> usize {
+        if self.name.starts_with("mirror") {
+            self.members.len().saturating_sub(1)
+        } else if self.name.starts_with("raidz3") {
+            3
+        } else if self.name.starts_with("raidz2") {
+            2
+        } else if self.name.starts_with("raidz") {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn contains(&self, device: &Path) -> bool {
+        if self.members.is_empty() {
+            return is_same_device(Path::new(&self.name), device);
+        }
+        self.members
+            .iter()
+            .any(|m| is_same_device(Path::new(&m.path), device))
+    }
+}
+
+impl Zpool {
+    fn find_vdev(&self, device: &Path) -> Option<&Vdev> {
+        self.vdevs.iter().find(|v| v.contains(device))
+    }
+}
+
+/// ZFS pools
+pub struct ZfsBackend {
+    config: ZfsConfig,
+}
+
+impl ZfsBackend {
+    pub fn new(config_dir: Option<&Path>) -> BynarResult<ZfsBackend> {
+        let config: ZfsConfig = match config_dir {
+            Some(config_dir) => load_config(config_dir, "zfs.json")?,
+            None => {
+                let home = home_dir().expect("HOME env variable not defined");
+                load_config(&home.join(".config"), "zfs.json")?
+            }
+        };
+        debug!("Loaded zfs config: {:?}", config);
+        Ok(ZfsBackend { config })
+    }
+
+    fn pools(&self) -> BynarResult<Vec<Zpool>> {
+        let output = run_cmd_output("zpool", &["status", "-P", "-L"])?;
+        let pools = parse_zpool_status(&output);
+        Ok(match self.config.pools {
+            Some(ref names) => pools
+                .into_iter()
+                .filter(|p| names.contains(&p.name))
+                .collect(),
+            None => pools,
+        })
+    }
+
+    fn find_device(&self, device: &Path) -> BynarResult<Option<(Zpool, Vdev)>> {
+        for pool in self.pools()? {
+            if let Some(vdev) = pool.find_vdev(device) {
+                let vdev = vdev.clone();
+                return Ok(Some((pool, vdev)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn load_removed(&self) -> BynarResult<Vec<RemovedMember>> {
+        load_state(Path::new(&self.config.state_file))
+    }
+
+    fn save_removed(&self, removed: &[RemovedMember]) -> BynarResult<()> {
+        save_state(Path::new(&self.config.state_file), removed)
+    }
+}
+
+impl Backend for ZfsBackend {
+    fn add_disk(&self, device: &Path, _id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut removed = self.load_removed()?;
+        let pos = match removed.iter().position(|r| r.device == device) {
+            Some(pos) => pos,
+            None => {
+                if self.find_device(device)?.is_some() {
+                    debug!(
+                        "Device {} is already in a pool.  Skipping",
+                        device.display()
+                    );
+                    return Ok(OpOutcome::SkipRepeat);
+                }
+                debug!(
+                    "No removed vdev member recorded for {}.  Skipping",
+                    device.display()
+                );
+                return Ok(OpOutcome::Skipped);
+            }
+        };
+        let record = removed[pos].clone();
+        let dev = device.to_string_lossy();
+        match record.attach_to {
+            // The old member was detached from its mirror so there's nothing left to replace
+            Some(ref sibling) => {
+                info!("Attaching {} to {} in {}", dev, sibling, record.pool);
+                run_cmd("zpool", &["attach", &record.pool, sibling, &dev], simulate)?;
+            }
+            None => {
+                info!(
+                    "Replacing {} with {} in {}",
+                    record.member, dev, record.pool
+                );
+                run_cmd(
+                    "zpool",
+                    &["replace", &record.pool, &record.member, &dev],
+                    simulate,
+                )?;
+            }
+        };
+        if !simulate {
+            removed.remove(pos);
+            self.save_removed(&removed)?;
+        }
+        Ok(OpOutcome::Success)
+    }
+
+    fn remove_disk(&self, device: &Path, simulate: bool) -> BynarResult<OpOutcome> {
+        let mut removed = self.load_removed()?;
+        if removed.iter().any(|r| r.device == device) {
+            debug!(
+                "Vdev member on {} was already removed.  Skipping",
+                device.display()
+            );
+            return Ok(OpOutcome::SkipRepeat);
+        }
+        let (pool, vdev) = match self.find_device(device)? {
+            Some(found) => found,
+            None => {
+                debug!("Device {} is not in a pool.  Skipping", device.display());
+                return Ok(OpOutcome::Skipped);
+            }
+        };
+        let member = match vdev
+            .members
+            .iter()
+            .find(|m| is_same_device(Path::new(&m.path), device))
+        {
+            Some(m) => m.path.clone(),
+            None => vdev.name.clone(),
+        };
+        match vdev.section {
+            // Cache and spare devices hold no unique data and can simply be removed
+            VdevSection::Cache | VdevSection::Spares => {
+                run_cmd("zpool", &["remove", &pool.name, &member], simulate)?;
+                if !simulate {
+                    removed.push(RemovedMember {
+                        device: device.to_path_buf(),
+                        pool: pool.name.clone(),
+                        member,
+                        attach_to: None,
+                    });
+                    self.save_removed(&removed)?;
+                }
+                return Ok(OpOutcome::Success);
+            }
+            VdevSection::Data | VdevSection::Logs => {}
+        };
+        run_cmd("zpool", &["offline", &pool.name, &member], simulate)?;
+        // Only mirror members can be detached.  raidz members stay offline until replaced
+        let attach_to = if vdev.name.starts_with("mirror") {
+            let sibling = vdev
+                .members
+                .iter()
+                .find(|m| m.path != member && m.state == "ONLINE")
+                .map(|m| m.path.clone());
+            if sibling.is_some() {
+                run_cmd("zpool", &["detach", &pool.name, &member], simulate)?;
+            }
+            sibling
+        } else {
+            None
+        };
+        if !simulate {
+            removed.push(RemovedMember {
+                device: device.to_path_buf(),
+                pool: pool.name.clone(),
+                member,
+                attach_to,
+            });
+            self.save_removed(&removed)?;
+        }
+        Ok(OpOutcome::Success)
+    }
+
+    fn safe_to_remove(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let (pool, vdev) = match self.find_device(device)? {
+            Some(found) => found,
+            None => {
+                debug!("Device {} is not in a pool.  Skipping", device.display());
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        Ok((OpOutcome::Success, vdev_can_lose(&pool, &vdev, device)))
+    }
+
+    fn safe_to_add(&self, device: &Path, simulate: bool) -> BynarResult<(OpOutcome, bool)> {
+        let removed = self.load_removed()?;
+        let record = match removed.iter().find(|r| r.device == device) {
+            Some(r) => r.clone(),
+            None => {
+                if self.find_device(device)?.is_some() {
+                    debug!(
+                        "Device {} is already in a pool.  Skipping",
+                        device.display()
+                    );
+                    return Ok((OpOutcome::SkipRepeat, false));
+                }
+                return Ok((OpOutcome::Skipped, false));
+            }
+        };
+        if simulate {
+            return Ok((OpOutcome::Success, true));
+        }
+        // Wait for a running resilver to finish before starting another one
+        match self.pools()?.iter().find(|p| p.name == record.pool) {
+            Some(pool) if pool.resilvering => {
+                error!(
+                    "Pool {} is resilvering.  Not safe to add {}",
+                    pool.name,
+                    device.display()
+                );
+                Ok((OpOutcome::Success, false))
+            }
+            Some(_) => Ok((OpOutcome::Success, true)),
+            None => Err(BynarError::new(format!(
+                "Pool {} is not imported",
+                record.pool
+            ))),
+        }
+    }
+
+    fn get_progress(&self, _device: &Path) -> BynarResult<Option<Progress>> {
+        Ok(None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.set_add_with_id(false);
+        capabilities.set_partition_create(false);
+        capabilities.set_simulate(true);
+        capabilities.set_gradual_weighting(false);
+        capabilities.set_progress(false);
+        capabilities
+    }
+
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+
+// Check that the vdev keeps its data if the device is taken out of it
+fn vdev_can_lose(pool: &Zpool, vdev: &Vdev, device: &Path) -> bool {
+    match vdev.section {
+        VdevSection::Cache | VdevSection::Spares => return true,
+        VdevSection::Data | VdevSection::Logs => {}
+    };
+    if pool.resilvering {
+        error!("Pool {} is resilvering", pool.name);
+        return false;
+    }
+    if vdev.redundancy() == 0 {
+        error!("Vdev {} in pool {} has no redundancy", vdev.name, pool.name);
+        return false;
+    }
+    let degraded: Vec<&VdevMember> = vdev
+        .members
+        .iter()
+        .filter(|m| !is_same_device(Path::new(&m.path), device) && m.state != "ONLINE")
+        .collect();
+    if !degraded.is_empty() {
+        error!(
+            "Vdev {} in pool {} is already degraded: {:?}",
+            vdev.name, pool.name, degraded
+        );
+        return false;
+    }
+    true
+}
+
+// Parse the output of zpool status -P -L.  The config section is indented by
+// vdev depth: pool, then top level vdevs, then their members
+fn parse_zpool_status(output: &str) -> Vec<Zpool> {
+    let mut pools: Vec<Zpool> = Vec::new();
+    let mut in_config = false;
+    let mut pool_indent = 0;
+    let mut section = VdevSection::Data;
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("pool:") {
+            pools.push(Zpool {
+                name: trimmed.trim_start_matches("pool:").trim().to_string(),
+                state: String::new(),
+                resilvering: false,
+                vdevs: Vec::new(),
+            });
+            in_config = false;
+            continue;
+        }
+        let pool = match pools.last_mut() {
+            Some(p) => p,
+            None => continue,
+        };
+        if trimmed.starts_with("state:") {
+            pool.state = trimmed.trim_start_matches("state:").trim().to_string();
+        } else if trimmed.starts_with("scan:") && trimmed.contains("resilver in progress") {
+            pool.resilvering = true;
+        } else if trimmed.starts_with("NAME") && trimmed.contains("STATE") {
+            in_config = true;
+            section = VdevSection::Data;
+            pool_indent = line.len() - line.trim_start().len();
+        } else if trimmed.starts_with("errors:") {
+            in_config = false;
+        } else if in_config && !trimmed.is_empty() {
+            let indent = line.len() - line.trim_start().len();
+            let mut parts = trimmed.split_whitespace();
+            let name = parts.next().unwrap_or("").to_string();
+            let state = parts.next().unwrap_or("").to_string();
+            if indent == pool_indent {
+                match name.as_str() {
+                    "logs" => section = VdevSection::Logs,
+                    "cache" => section = VdevSection::Cache,
+                    "spares" => section = VdevSection::Spares,
+                    // The pool itself
+                    _ => section = VdevSection::Data,
+                }
+            } else if indent <= pool_indent + 2 {
+                pool.vdevs.push(Vdev {
+                    name,
+                    state,
+                    section: section.clone(),
+                    members: Vec::new(),
+                });
+            } else if let Some(vdev) = pool.vdevs.last_mut() {
+                // replacing-N and spare-N vdevs nest one level deeper.  Flatten them
+                if name.starts_with('/') {
+                    vdev.members.push(VdevMember { path: name, state });
+                }
+            }
+        }
+    }
+    pools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STATUS: &str = r#"  pool: tank
+ state: DEGRADED
+status: One or more devices are faulted in response to persistent errors.
+  scan: scrub repaired 0B in 0 days 01:02:03 with 0 errors on Sun Jan 12 01:26:04 2020
+config:
+
+	NAME           STATE     READ WRITE CKSUM
+	tank           DEGRADED     0     0     0
+	  mirror-0     DEGRADED     0     0     0
+	    /dev/sdb1  ONLINE       0     0     0
+	    /dev/sdc1  FAULTED      0     0     0  too many errors
+	  raidz2-1     ONLINE       0     0     0
+	    /dev/sdd1  ONLINE       0     0     0
+	    /dev/sde1  ONLINE       0     0     0
+	    /dev/sdf1  ONLINE       0     0     0
+	    /dev/sdg1  ONLINE       0     0     0
+	cache
+	  /dev/sdh1    ONLINE       0     0     0
+
+errors: No known data errors
+"#;
+
+    #[test]
+    fn test_parse_zpool_status() {
+        let pools = parse_zpool_status(STATUS);
+        assert_eq!(pools.len(), 1);
+        let pool = &pools[0];
+        assert_eq!(pool.name, "tank");
+        assert_eq!(pool.state, "DEGRADED");
+        assert!(!pool.resilvering);
+        assert_eq!(pool.vdevs.len(), 3);
+        assert_eq!(pool.vdevs[0].name, "mirror-0");
+        assert_eq!(pool.vdevs[0].redundancy(), 1);
+        assert_eq!(pool.vdevs[1].members.len(), 4);
+        assert_eq!(pool.vdevs[1].redundancy(), 2);
+        assert_eq!(pool.vdevs[2].section, VdevSection::Cache);
+        assert_eq!(
+            pool.find_vdev(Path::new("/dev/sdc"))
+                .map(|v| v.name.clone()),
+            Some("mirror-0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_vdev_can_lose() {
+        let pools = parse_zpool_status(STATUS);
+        let pool = &pools[0];
+        let check = |dev: &str| {
+            let vdev = pool.find_vdev(Path::new(dev)).unwrap();
+            vdev_can_lose(pool, vdev, Path::new(dev))
+        };
+        // The failed member itself can go, its partner can't
+        assert!(check("/dev/sdc"));
+        assert!(!check("/dev/sdb"));
+        assert!(check("/dev/sdd"));
+        assert!(check("/dev/sdh"));
+    }
+}
@@ -5,7 +5,7 @@ use std::path::Path;
 use std::str::FromStr;
 
 //use disk_manager::disk_manager;
-use api::service::{Disk, OpOutcome};
+use api::service::{BackendInfo, Disk, Op, OpOutcome, Progress};
 use clap::{crate_authors, crate_version, App, Arg, ArgMatches, SubCommand};
 use helpers::error::BynarResult;
 use hostname::get_hostname;
@@ -16,8 +16,14 @@ use zmq::Socket;
     CLI client to call functions over RPC
 */
 
-fn add_disk(s: &Socket, path: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
-    let outcome = helpers::add_disk_request(s, path, id, simulate)?;
+fn add_disk(
+    s: &Socket,
+    path: &Path,
+    id: Option<u64>,
+    backend: Option<&str>,
+    simulate: bool,
+) -> BynarResult<(OpOutcome, Option<String>)> {
+    let outcome = helpers::add_disk_request(s, path, id, backend, simulate)?;
     Ok(outcome)
 }
 
@@ -28,11 +34,26 @@ fn list_disks(s: &Socket) -> BynarResult<Vec<Disk>> {
     Ok(disks)
 }
 
-fn remove_disk(s: &Socket, path: &Path, id: Option<u64>, simulate: bool) -> BynarResult<OpOutcome> {
+fn remove_disk(
+    s: &Socket,
+    path: &Path,
+    id: Option<u64>,
+    simulate: bool,
+) -> BynarResult<(OpOutcome, Option<u64>, Option<String>)> {
     let outcome = helpers::remove_disk_request(s, path, id, simulate)?;
     Ok(outcome)
 }
 
+fn get_progress(s: &Socket, path: &Path) -> BynarResult<Option<Progress>> {
+    let progress = helpers::get_progress_request(s, path)?;
+    Ok(progress)
+}
+
+fn get_backend_info(s: &Socket, path: Option<&Path>) -> BynarResult<BackendInfo> {
+    let info = helpers::get_backend_info_request(s, path)?;
+    Ok(info)
+}
+
 fn handle_add_disk(s: &Socket, matches: &ArgMatches<'_>) {
     let p = Path::new(matches.value_of("path").unwrap());
     info!("Adding disk: {}", p.display());
@@ -44,12 +65,38 @@ fn handle_add_disk(s: &Socket, matches: &ArgMatches<'_>) {
         Some(s) => bool::from_str(&s).unwrap(),
         None => false,
     };
-    match add_disk(s, &p, id, simulate) {
-        Ok(outcome) => match outcome {
-            OpOutcome::Success => println!("Adding disk successful"),
-            OpOutcome::Skipped => println!("Disk cannot be added, Skipping"),
-            OpOutcome::SkipRepeat => println!("Disk already added, Skipping"),
-        },
+    let backend = matches.value_of("backend");
+    if id.is_some() {
+        // Don't let the backend silently ignore the id
+        match get_backend_info(s, Some(p)) {
+            Ok(ref info) if !info.get_capabilities().get_add_with_id() => {
+                println!(
+                    "The {} backend can't add a disk with a specific id",
+                    info.get_backend_type()
+                );
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("Getting backend info failed: {}", e);
+                return;
+            }
+        };
+    }
+    match add_disk(s, &p, id, backend, simulate) {
+        Ok((outcome, job_id)) => {
+            match outcome {
+                OpOutcome::Success => println!("Adding disk successful"),
+                OpOutcome::Skipped => println!("Disk cannot be added, Skipping"),
+                OpOutcome::SkipRepeat => println!("Disk already added, Skipping"),
+                OpOutcome::InProgress => {
+                    println!("Adding disk started.  Check on it with progress")
+                }
+            }
+            if let Some(job_id) = job_id {
+                println!("Job id: {}", job_id);
+            }
+        }
         Err(e) => {
             println!("Adding disk failed: {}", e);
         }
@@ -68,6 +115,79 @@ fn handle_list_disks(s: &Socket) {
     };
 }
 
+fn handle_get_progress(s: &Socket, matches: &ArgMatches<'_>) {
+    let p = Path::new(matches.value_of("path").unwrap());
+    info!("Getting progress for disk: {}", p.display());
+    match get_progress(s, &p) {
+        Ok(Some(progress)) => {
+            print!(
+                "{} {:.1}% done",
+                progress.get_operation(),
+                progress.get_percent_done()
+            );
+            if progress.has_remaining() {
+                print!(", {} left", progress.get_remaining());
+            }
+            if progress.has_eta_seconds() {
+                print!(", {} seconds remaining", progress.get_eta_seconds());
+            }
+            println!();
+        }
+        Ok(None) => println!("Nothing running on {}", p.display()),
+        Err(e) => {
+            println!("Getting progress failed: {}", e);
+        }
+    };
+}
+
+fn handle_backend_info(s: &Socket, matches: &ArgMatches<'_>) {
+    let p = matches.value_of("path").map(Path::new);
+    info!("Getting backend info");
+    match get_backend_info(s, p) {
+        Ok(info) => {
+            let capabilities = info.get_capabilities();
+            println!("Backend: {}", info.get_backend_type());
+            if info.has_version() {
+                println!("Version: {}", info.get_version());
+            }
+            println!("Add with id: {}", capabilities.get_add_with_id());
+            println!("Partition create: {}", capabilities.get_partition_create());
+            println!("Simulate: {}", capabilities.get_simulate());
+            println!(
+                "Gradual weighting: {}",
+                capabilities.get_gradual_weighting()
+            );
+            println!("Progress: {}", capabilities.get_progress());
+        }
+        Err(e) => {
+            println!("Getting backend info failed: {}", e);
+        }
+    };
+}
+
+fn handle_hygiene(s: &Socket, matches: &ArgMatches<'_>) {
+    let p = matches.value_of("path").map(Path::new);
+    let fix = matches.is_present("fix");
+    info!("Checking the host for leftover artifacts");
+    match helpers::host_hygiene_request(s, p, fix) {
+        Ok(orphans) => {
+            if orphans.is_empty() {
+                println!("Nothing left over");
+            }
+            for orphan in orphans {
+                if fix {
+                    println!("Removed {}", orphan);
+                } else {
+                    println!("{}", orphan);
+                }
+            }
+        }
+        Err(e) => {
+            println!("Host hygiene failed: {}", e);
+        }
+    };
+}
+
 fn handle_jira_tickets(s: &Socket) -> BynarResult<()> {
     trace!("handle_jira_tickets called");
     helpers::get_jira_tickets(s)?;
@@ -87,17 +207,40 @@ fn handle_remove_disk(s: &Socket, matches: &ArgMatches<'_>) {
         None => false,
     };
     match remove_disk(s, &p, id, simulate) {
-        Ok(outcome) => match outcome {
-            OpOutcome::Success => println!("Removing disk successful"),
-            OpOutcome::Skipped => println!("Disk cannot be removed.  Skipping"),
-            OpOutcome::SkipRepeat => println!("Disk already removed.  Skipping"),
-        },
+        Ok((outcome, disk_id, job_id)) => {
+            match outcome {
+                OpOutcome::Success => println!("Removing disk successful"),
+                OpOutcome::Skipped => println!("Disk cannot be removed.  Skipping"),
+                OpOutcome::SkipRepeat => println!("Disk already removed.  Skipping"),
+                OpOutcome::InProgress => {
+                    println!("Removing disk started.  Check on it with progress")
+                }
+            }
+            if let Some(job_id) = job_id {
+                println!("Job id: {}", job_id);
+            }
+            if let Some(disk_id) = disk_id {
+                println!(
+                    "Add the replacement disk with --id {} to reuse its id",
+                    disk_id
+                );
+            }
+        }
         Err(e) => {
             println!("Removing disk failed: {}", e);
         }
     }
 }
 
+fn handle_job(s: &Socket, op: Op, matches: &ArgMatches<'_>) {
+    let job_id = matches.value_of("job_id").unwrap();
+    info!("{:?} {}", op, job_id);
+    match helpers::job_request(s, op, job_id) {
+        Ok(_) => println!("{:?} {} successful", op, job_id),
+        Err(e) => println!("{:?} {} failed: {}", op, job_id, e),
+    };
+}
+
 fn get_cli_args(default_server_key: &str) -> ArgMatches<'_> {
     App::new("Ceph Disk Manager Client")
         .version(crate_version!())
@@ -147,6 +290,16 @@ fn get_cli_args(default_server_key: &str) -> ArgMatches<'_> {
                             Err(_) => Err("id must be a valid u64".to_string()),
                         }),
                 )
+                .arg(
+                    Arg::with_name("backend")
+                        .help(
+                            "The backend to add the disk with on hosts with more than one.  \
+                             Ex: ceph",
+                        )
+                        .long("backend")
+                        .required(false)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("simulate")
                         .default_value("false")
@@ -157,8 +310,74 @@ fn get_cli_args(default_server_key: &str) -> ArgMatches<'_> {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("cancel")
+                .about("Stop an add or remove that is still running where it is")
+                .arg(
+                    Arg::with_name("job_id")
+                        .help("The job id add or remove printed")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hygiene")
+                .about("List udev rules, fstab entries and the like left over from removed disks")
+                .arg(
+                    Arg::with_name("path")
+                        .help("A disk of the backend to check: Ex: /dev/sda")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("fix")
+                        .help("Remove what's left over")
+                        .long("fix")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show the disk-manager backend and what it supports")
+                .arg(
+                    Arg::with_name("path")
+                        .help("The disk to show the backend for: Ex: /dev/sda")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
         .subcommand(SubCommand::with_name("list").about("List all disks on a server"))
         .subcommand(SubCommand::with_name("get_jira_tickets").about("get all tickets created"))
+        .subcommand(
+            SubCommand::with_name("pause")
+                .about("Pause an add or remove that is still running")
+                .arg(
+                    Arg::with_name("job_id")
+                        .help("The job id add or remove printed")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("progress")
+                .about("Show the progress of a long running operation on a disk")
+                .arg(
+                    Arg::with_name("path")
+                        .help("The disk path to check: Ex: /dev/sda")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Resume a paused add or remove")
+                .arg(
+                    Arg::with_name("job_id")
+                        .help("The job id add or remove printed")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("remove")
                 .about("Remove a disk from the cluster")
@@ -232,12 +451,30 @@ fn main() {
     if let Some(ref matches) = matches.subcommand_matches("add") {
         handle_add_disk(&s, matches);
     }
+    if let Some(ref matches) = matches.subcommand_matches("hygiene") {
+        handle_hygiene(&s, matches);
+    }
+    if let Some(ref matches) = matches.subcommand_matches("info") {
+        handle_backend_info(&s, matches);
+    }
     if matches.subcommand_matches("list").is_some() {
         handle_list_disks(&s);
     }
     if let Some(ref matches) = matches.subcommand_matches("remove") {
         handle_remove_disk(&s, matches);
     }
+    if let Some(ref matches) = matches.subcommand_matches("progress") {
+        handle_get_progress(&s, matches);
+    }
+    if let Some(ref matches) = matches.subcommand_matches("pause") {
+        handle_job(&s, Op::PauseJob, matches);
+    }
+    if let Some(ref matches) = matches.subcommand_matches("resume") {
+        handle_job(&s, Op::ResumeJob, matches);
+    }
+    if let Some(ref matches) = matches.subcommand_matches("cancel") {
+        handle_job(&s, Op::CancelJob, matches);
+    }
     if let Some(ref _matches) = matches.subcommand_matches("get_jira_tickets") {
         match handle_jira_tickets(&s) {
             Ok(()) => {}
@@ -1,11 +1,15 @@
 use super::ConfigSettings;
+#[cfg(feature = "jira")]
 use goji::issues::*;
+#[cfg(feature = "jira")]
 use goji::{Credentials, Jira};
 use helpers::error::*;
 use log::debug;
+#[cfg(feature = "jira")]
 use serde_json::value::Value;
 
 /// Create a new JIRA support ticket and return the ticket ID associated with it
+#[cfg(feature = "jira")]
 pub fn create_support_ticket(
     settings: &ConfigSettings,
     title: &str,
@@ -59,6 +63,7 @@ pub fn create_support_ticket(
 }
 
 /// Check to see if a JIRA support ticket is marked as resolved
+#[cfg(feature = "jira")]
 pub fn ticket_resolved(settings: &ConfigSettings, issue_id: &str) -> BynarResult<bool> {
     let jira: Jira = match settings.proxy {
         Some(ref url) => {
@@ -87,3 +92,27 @@ pub fn ticket_resolved(settings: &ConfigSettings, issue_id: &str) -> BynarResult
         None => Ok(false),
     }
 }
+
+/// Without JIRA no ticket can be filed.  The failure is still logged by the caller
+#[cfg(not(feature = "jira"))]
+pub fn create_support_ticket(
+    _settings: &ConfigSettings,
+    title: &str,
+    description: &str,
+) -> BynarResult<String> {
+    debug!("Unable to file ticket {}: {}", title, description);
+    Err(BynarError::new(format!(
+        "Bynar was built without the jira feature.  Unable to file ticket: {}",
+        title
+    )))
+}
+
+/// Without JIRA there's no way to tell if a ticket is resolved.  Replaced
+/// disks have to be added back with bynar-client
+#[cfg(not(feature = "jira"))]
+pub fn ticket_resolved(_settings: &ConfigSettings, issue_id: &str) -> BynarResult<bool> {
+    Err(BynarError::new(format!(
+        "Bynar was built without the jira feature.  Unable to check ticket {}",
+        issue_id
+    )))
+}
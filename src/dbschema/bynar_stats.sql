@@ -60,7 +60,7 @@ $BODY$
 
 DECLARE
     new_row INTEGER; 
-    new_rev INTEGER := 4;
+    new_rev INTEGER := 6;
     current_revision INTEGER;
 BEGIN
     
@@ -223,8 +223,22 @@ BEGIN
     END IF;
 
 
+    IF (current_revision < 5)
+    THEN
+        -- The id a disk had in the storage cluster, ie the ceph osd id.  The
+        -- replacement disk is added back with the same id
+        ALTER TABLE operations ADD COLUMN osd_id BIGINT;
+    END IF;
+
+    IF (current_revision < 6)
+    THEN
+        -- The disk-manager backend that removed the disk.  The replacement
+        -- disk is added through the same backend
+        ALTER TABLE operations ADD COLUMN backend VARCHAR(32);
+    END IF;
+
     -- Add next revision here
-    -- IF (current_revision < 5)
+    -- IF (current_revision < 7)
     -- THEN
     --      SQL statements
     -- END IF;
@@ -7,24 +7,33 @@ use std::path::Path;
 use std::process;
 use std::process::Command;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "postgres")]
+use api::service::JiraInfo;
 use api::service::{
-    Disk, DiskType, Disks, JiraInfo, Op, OpJiraTicketsResult, OpOutcome, OpOutcomeResult, OpResult,
+    BackendInfo, Disk, DiskType, Disks, Op, OpBackendInfoResult, OpHygieneResult,
+    OpJiraTicketsResult, OpOutcome, OpOutcomeResult, OpProgressResult, OpResult, OpSuspectResult,
     Operation, Partition, PartitionInfo, ResultType,
 };
 mod backend;
+#[cfg(feature = "postgres")]
 mod in_progress;
+#[cfg(feature = "postgres")]
 mod test_disk;
 
-use crate::backend::BackendType;
+use crate::backend::selector::{route_device, BackendRoute};
+use crate::backend::{Backend, BackendType};
 use block_utils::{Device, MediaType};
 use clap::{crate_authors, crate_version, App, Arg};
 use daemonize::Daemonize;
 use gpt::{disk, header::read_header, partition::read_partitions};
 use hashicorp_vault::client::VaultClient;
-use helpers::{error::*, host_information::Host, ConfigSettings};
+#[cfg(feature = "postgres")]
+use helpers::ConfigSettings;
+use helpers::{error::*, host_information::Host};
 use hostname::get_hostname;
 use libc::c_int;
 use log::{debug, error, info, trace, warn};
@@ -73,10 +82,44 @@ fn default_err() -> String {
 fn default_pid() -> String {
     "disk_manager_daemon.pid".to_string()
 }
+//default file to keep track of adds and removals that are still running
+fn default_job_file() -> String {
+    "/var/lib/bynar/jobs.json".to_string()
+}
+
+// How often adds and removals that are still running get their next step
+const JOB_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+enum JobOp {
+    Add,
+    Remove,
+}
+
+/// An add or remove that returned InProgress.  disk-manager keeps calling
+/// the backend in the background until it's done, paused or cancelled
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct Job {
+    /// Handed to the client to pause, resume or cancel the job with
+    id: String,
+    op: JobOp,
+    device: String,
+    backend: BackendType,
+    /// The osd id an add was asked for.  Every step of the job asks for it again
+    #[serde(default)]
+    osd_id: Option<u64>,
+    #[serde(default)]
+    paused: bool,
+}
 
 #[derive(Clone, Debug, Deserialize)]
 struct DiskManagerConfig {
     backend: BackendType,
+    /// Backends for hosts that run more than one storage service.  Each
+    /// request goes to the first backend with a selector matching its disk.
+    /// If empty every request goes to the --backend backend
+    #[serde(default)]
+    backends: Vec<BackendRoute>,
     vault_token: Option<String>,
     vault_endpoint: Option<String>,
     /// Name of the Daemon Output file
@@ -88,6 +131,10 @@ struct DiskManagerConfig {
     /// Name of the Daemon pid file
     #[serde(default = "default_pid")]
     pub daemon_pid: String,
+    /// Where adds and removals that are still running are recorded so they
+    /// get finished even if disk-manager restarts
+    #[serde(default = "default_job_file")]
+    job_file: String,
     /// Optional Slack webhook (does not have to be the same as main client webhook)
     slack_webhook: Option<String>,
     slack_channel: Option<String>,
@@ -161,6 +208,12 @@ fn op_no_disk(responder: &Socket, op: &Operation) -> bool {
             Op::SafeToRemove => {
                 error!("Safe to remove operation must include disk field.  Ignoring request")
             }
+            Op::SafeToAdd => {
+                error!("Safe to add operation must include disk field.  Ignoring request")
+            }
+            Op::GetProgress => {
+                error!("Get progress operation must include disk field.  Ignoring request")
+            }
             _ => return false,
         }
         // We still have to respond with an error message
@@ -174,12 +227,105 @@ fn op_no_disk(responder: &Socket, op: &Operation) -> bool {
     false
 }
 
+// Find the backend that owns the disk of an operation.  Requests for a disk
+// that no backend owns are answered here and None is returned
+fn route_operation(
+    responder: &Socket,
+    default_backend: &BackendType,
+    routes: &[BackendRoute],
+    op: &Operation,
+) -> Option<BackendType> {
+    if !op.has_disk() || routes.is_empty() {
+        return Some(default_backend.clone());
+    }
+    // ie the backend that removed the disk being replaced
+    let routed = if op.has_backend() {
+        BackendType::from_str(op.get_backend()).map(Some)
+    } else {
+        route_device(routes, Path::new(op.get_disk()))
+    };
+    let error_msg = match routed {
+        Ok(Some(backend_type)) => {
+            debug!(
+                "Routing {} to the {:?} backend",
+                op.get_disk(),
+                backend_type
+            );
+            return Some(backend_type);
+        }
+        Ok(None) => {
+            info!("No backend manages {}.  Skipping", op.get_disk());
+            None
+        }
+        Err(e) => {
+            error!("Unable to route {} to a backend: {:?}", op.get_disk(), e);
+            Some(e.to_string())
+        }
+    };
+    match op.get_Op_type() {
+        Op::GetProgress => {
+            let mut result = OpProgressResult::new();
+            match error_msg {
+                Some(msg) => {
+                    result.set_result(ResultType::ERR);
+                    result.set_error_msg(msg);
+                }
+                // Nothing can be running on a disk no backend manages
+                None => result.set_result(ResultType::OK),
+            };
+            let _ = respond_to_client(&result, responder);
+        }
+        Op::GetBackendInfo => {
+            let mut result = OpBackendInfoResult::new();
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(
+                error_msg.unwrap_or_else(|| format!("No backend manages {}", op.get_disk())),
+            );
+            let _ = respond_to_client(&result, responder);
+        }
+        Op::HostHygiene => {
+            let mut result = OpHygieneResult::new();
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(
+                error_msg.unwrap_or_else(|| format!("No backend manages {}", op.get_disk())),
+            );
+            let _ = respond_to_client(&result, responder);
+        }
+        Op::SuspectDisks => {
+            let mut result = OpSuspectResult::new();
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(
+                error_msg.unwrap_or_else(|| format!("No backend manages {}", op.get_disk())),
+            );
+            let _ = respond_to_client(&result, responder);
+        }
+        _ => {
+            let mut result = OpOutcomeResult::new();
+            match error_msg {
+                Some(msg) => {
+                    result.set_result(ResultType::ERR);
+                    result.set_error_msg(msg);
+                }
+                None => {
+                    result.set_result(ResultType::OK);
+                    result.set_outcome(OpOutcome::Skipped);
+                    result.set_value(false);
+                }
+            };
+            let _ = respond_to_client(&result, responder);
+        }
+    };
+    None
+}
+
 /*
 Server that manages disks
 */
 fn listen(
-    backend_type: &backend::BackendType,
+    default_backend: &backend::BackendType,
+    routes: &[BackendRoute],
     config_dir: &Path,
+    job_file: &Path,
     listen_address: &str,
     signals: &Signals,
     daemon: bool,
@@ -196,6 +342,25 @@ fn listen(
         .bind(&format!("tcp://{}:5555", listen_address))
         .is_ok());
 
+    // Jobs run on their own thread, starting with the ones that were still
+    // running when disk-manager stopped.  Requests that touch the jobs or
+    // the disks wait for the job step that's running to finish
+    let job_lock = Arc::new(Mutex::new(()));
+    {
+        let job_lock = Arc::clone(&job_lock);
+        let job_file = job_file.to_path_buf();
+        let config_dir = config_dir.to_path_buf();
+        thread::spawn(move || {
+            let mut backends = Vec::new();
+            loop {
+                if let Err(e) = run_jobs(&job_file, &config_dir, &job_lock, &mut backends) {
+                    error!("Running jobs failed: {:?}", e);
+                }
+                thread::sleep(JOB_INTERVAL);
+            }
+        });
+    }
+
     'outer: loop {
         let now = Instant::now();
         let events = responder.get_events()? as zmq::PollEvents;
@@ -216,8 +381,14 @@ fn listen(
             if op_no_disk(&responder, &operation) {
                 continue;
             }
+            let backend_type =
+                match route_operation(&responder, default_backend, routes, &operation) {
+                    Some(backend_type) => backend_type,
+                    None => continue,
+                };
             match operation.get_Op_type() {
                 Op::Add => {
+                    let _jobs = lock_jobs(&job_lock);
                     let id = if operation.has_osd_id() {
                         Some(operation.get_osd_id())
                     } else {
@@ -229,6 +400,7 @@ fn listen(
                         &backend_type,
                         id,
                         config_dir,
+                        job_file,
                     ) {
                         Ok(_) => {
                             info!("Add disk finished");
@@ -252,18 +424,25 @@ fn listen(
                     };
                 }
                 Op::Remove => {
+                    let _jobs = lock_jobs(&job_lock);
                     let mut result = OpOutcomeResult::new();
                     match safe_to_remove(
                         &Path::new(operation.get_disk()),
                         &backend_type,
                         config_dir,
-                    ) {
-                        Ok((OpOutcome::Success, true)) => {
+                    )
+                    .map(|safe| (safe.get_outcome(), safe.get_value(), safe))
+                    {
+                        Ok((OpOutcome::Success, true, _))
+                        // Not safe until a drain moves the data off.  remove_disk
+                        // starts the drain and destroys the disk once it finishes
+                        | Ok((OpOutcome::InProgress, _, _)) => {
                             match remove_disk(
                                 &responder,
                                 operation.get_disk(),
                                 &backend_type,
                                 config_dir,
+                                job_file,
                             ) {
                                 Ok(_) => {
                                     info!("Remove disk finished");
@@ -273,27 +452,54 @@ fn listen(
                                 }
                             };
                         }
-                        Ok((OpOutcome::Skipped, val)) => {
+                        Ok((OpOutcome::Skipped, val, _)) => {
                             debug!("Disk skipped");
                             result.set_outcome(OpOutcome::Skipped);
                             result.set_value(val);
                             result.set_result(ResultType::OK);
                             let _ = respond_to_client(&result, &responder);
                         }
-                        Ok((OpOutcome::SkipRepeat, val)) => {
+                        Ok((OpOutcome::SkipRepeat, val, _)) => {
                             debug!("Disk skipped, safe to remove already ran");
                             result.set_outcome(OpOutcome::SkipRepeat);
                             result.set_value(val);
                             result.set_result(ResultType::OK);
                             let _ = respond_to_client(&result, &responder);
                         }
-                        Ok((_, false)) => {
+                        Ok((_, false, mut safe)) => {
                             debug!("Disk is not safe to remove");
                             //Response to client
                             result.set_value(false);
                             result.set_outcome(OpOutcome::Success);
                             result.set_result(ResultType::ERR);
-                            result.set_error_msg("Not safe to remove disk".to_string());
+                            let blocking_pgs = safe.take_blocking_pgs().into_vec();
+                            let reason = if safe.has_reason() {
+                                Some(safe.take_reason())
+                            } else {
+                                None
+                            };
+                            match reason {
+                                Some(reason) => {
+                                    result.set_error_msg(format!(
+                                        "Not safe to remove disk: {}",
+                                        reason
+                                    ));
+                                    result.set_reason(reason);
+                                }
+                                None if !blocking_pgs.is_empty() => {
+                                    result.set_error_msg(format!(
+                                        "Not safe to remove disk: {} pgs would go below min_size: {}",
+                                        blocking_pgs.len(),
+                                        blocking_pgs
+                                            .iter()
+                                            .map(|pg| pg.get_pgid())
+                                            .collect::<Vec<&str>>()
+                                            .join(", ")
+                                    ));
+                                }
+                                None => result.set_error_msg("Not safe to remove disk".to_string()),
+                            };
+                            result.set_blocking_pgs(RepeatedField::from_vec(blocking_pgs));
                             let _ = respond_to_client(&result, &responder);
                         }
                         Err(e) => {
@@ -321,6 +527,72 @@ fn listen(
                         }
                     };
                 }
+                Op::SafeToAdd => {
+                    match safe_to_add_disk(
+                        &responder,
+                        operation.get_disk(),
+                        &backend_type,
+                        config_dir,
+                    ) {
+                        Ok(_) => {
+                            info!("Safe to add disk finished");
+                        }
+                        Err(e) => {
+                            error!("Safe to add error: {:?}", e);
+                        }
+                    };
+                }
+                Op::GetProgress => {
+                    match get_progress(&responder, operation.get_disk(), &backend_type, config_dir)
+                    {
+                        Ok(_) => {
+                            info!("Get progress finished");
+                        }
+                        Err(e) => {
+                            error!("Get progress error: {:?}", e);
+                        }
+                    };
+                }
+                Op::GetBackendInfo => {
+                    match get_backend_info(&responder, &operation, &backend_type, config_dir) {
+                        Ok(_) => {
+                            info!("Get backend info finished");
+                        }
+                        Err(e) => {
+                            error!("Get backend info error: {:?}", e);
+                        }
+                    };
+                }
+                Op::HostHygiene => {
+                    // Without a disk every backend cleans up after itself
+                    let backends = if operation.has_disk() {
+                        vec![backend_type]
+                    } else {
+                        all_backends(default_backend, routes)
+                    };
+                    match host_hygiene(&responder, &operation, &backends, config_dir) {
+                        Ok(_) => {
+                            info!("Host hygiene finished");
+                        }
+                        Err(e) => {
+                            error!("Host hygiene error: {:?}", e);
+                        }
+                    };
+                }
+                Op::SuspectDisks => {
+                    match suspect_disks(
+                        &responder,
+                        &all_backends(default_backend, routes),
+                        config_dir,
+                    ) {
+                        Ok(_) => {
+                            info!("Suspect disks finished");
+                        }
+                        Err(e) => {
+                            error!("Suspect disks error: {:?}", e);
+                        }
+                    };
+                }
                 Op::GetCreatedTickets => {
                     match get_jira_tickets(&responder, config_dir) {
                         Ok(_) => {
@@ -331,6 +603,17 @@ fn listen(
                         }
                     };
                 }
+                Op::PauseJob | Op::ResumeJob | Op::CancelJob => {
+                    let _jobs = lock_jobs(&job_lock);
+                    match update_job(&responder, &operation, config_dir, job_file) {
+                        Ok(_) => {
+                            info!("{:?} finished", operation.get_Op_type());
+                        }
+                        Err(e) => {
+                            error!("{:?} error: {:?}", operation.get_Op_type(), e);
+                        }
+                    };
+                }
             };
         }
         if daemon {
@@ -369,6 +652,9 @@ fn listen(
             }
         }
     }
+    // Wait for the job step that's running and keep the job thread from
+    // starting another while disk-manager exits
+    std::mem::forget(lock_jobs(&job_lock));
     Ok(())
 }
 
@@ -385,9 +671,11 @@ fn add_disk(
     backend: &BackendType,
     id: Option<u64>,
     config_dir: &Path,
+    job_file: &Path,
 ) -> BynarResult<()> {
     let mut result = OpOutcomeResult::new();
-    let backend = match backend::load_backend(backend, Some(config_dir)) {
+    let backend_type = backend;
+    let backend = match backend::load_backend(backend_type, Some(config_dir)) {
         Ok(backend) => backend,
         Err(e) => {
             result.set_result(ResultType::ERR);
@@ -402,6 +690,13 @@ fn add_disk(
     //Send back OpOutcomeResult
     match backend.add_disk(&Path::new(d), id, false) {
         Ok(outcome) => {
+            if outcome == OpOutcome::InProgress {
+                // Finish it in the background
+                match add_job(job_file, JobOp::Add, d, backend_type, id) {
+                    Ok(job_id) => result.set_job_id(job_id),
+                    Err(e) => error!("Unable to record the add of {}: {:?}", d, e),
+                }
+            }
             result.set_outcome(outcome);
             result.set_result(ResultType::OK);
         }
@@ -482,10 +777,16 @@ fn list_disks(s: &Socket) -> BynarResult<()> {
     Ok(())
 }
 
-fn remove_disk(s: &Socket, d: &str, backend: &BackendType, config_dir: &Path) -> BynarResult<()> {
+fn remove_disk(
+    s: &Socket,
+    d: &str,
+    backend_type: &BackendType,
+    config_dir: &Path,
+    job_file: &Path,
+) -> BynarResult<()> {
     //Returns OpOutcomeResult
     let mut result = OpOutcomeResult::new();
-    let backend = match backend::load_backend(backend, Some(config_dir)) {
+    let backend = match backend::load_backend(backend_type, Some(config_dir)) {
         Ok(b) => b,
         Err(e) => {
             result.set_result(ResultType::ERR);
@@ -496,8 +797,28 @@ fn remove_disk(s: &Socket, d: &str, backend: &BackendType, config_dir: &Path) ->
             return Ok(());
         }
     };
+    // Look the id up while the backend still knows about the disk
+    let id = match backend.disk_id(&Path::new(d)) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Unable to find the id of {}: {:?}", d, e);
+            None
+        }
+    };
     match backend.remove_disk(&Path::new(d), false) {
         Ok(outcome) => {
+            if outcome == OpOutcome::InProgress {
+                // Finish it in the background
+                match add_job(job_file, JobOp::Remove, d, backend_type, None) {
+                    Ok(job_id) => result.set_job_id(job_id),
+                    Err(e) => error!("Unable to record the removal of {}: {:?}", d, e),
+                }
+            }
+            if let Some(id) = id {
+                if outcome == OpOutcome::Success || outcome == OpOutcome::InProgress {
+                    result.set_osd_id(id);
+                }
+            }
             result.set_outcome(outcome);
             result.set_result(ResultType::OK);
         }
@@ -510,15 +831,219 @@ fn remove_disk(s: &Socket, d: &str, backend: &BackendType, config_dir: &Path) ->
     Ok(())
 }
 
+fn load_jobs(job_file: &Path) -> BynarResult<Vec<Job>> {
+    if !job_file.exists() {
+        return Ok(vec![]);
+    }
+    let jobs: Vec<Job> = serde_json::from_str(&read_to_string(job_file)?)?;
+    Ok(jobs)
+}
+
+fn save_jobs(job_file: &Path, jobs: &[Job]) -> BynarResult<()> {
+    if let Some(parent) = job_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut f = File::create(job_file)?;
+    f.write_all(serde_json::to_string_pretty(jobs)?.as_bytes())?;
+    Ok(())
+}
+
+// Record an operation that's still running and return its job id.  Asking
+// again for a disk that already has a job hands back the same id
+fn add_job(
+    job_file: &Path,
+    op: JobOp,
+    d: &str,
+    backend_type: &BackendType,
+    osd_id: Option<u64>,
+) -> BynarResult<String> {
+    let mut jobs = load_jobs(job_file)?;
+    if let Some(job) = jobs.iter().find(|j| j.op == op && j.device == d) {
+        return Ok(job.id.clone());
+    }
+    let id = uuid::Uuid::new_v4().to_hyphenated().to_string();
+    debug!("Starting job {} to {:?} {}", id, op, d);
+    jobs.push(Job {
+        id: id.clone(),
+        op,
+        device: d.to_string(),
+        backend: backend_type.clone(),
+        osd_id,
+        paused: false,
+    });
+    save_jobs(job_file, &jobs)?;
+    Ok(id)
+}
+
+// The job file and the disks are only touched by one thread at a time.  A
+// thread that panicked while holding the lock left nothing half written
+fn lock_jobs(job_lock: &Mutex<()>) -> MutexGuard<'_, ()> {
+    job_lock.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// The job thread's handle to a backend.  It connects once and again only
+// after a job failed on it
+fn job_backend<'a>(
+    backends: &'a mut Vec<(BackendType, Box<dyn Backend>)>,
+    backend_type: &BackendType,
+    config_dir: &Path,
+) -> BynarResult<&'a dyn Backend> {
+    let pos = match backends.iter().position(|(t, _)| t == backend_type) {
+        Some(pos) => pos,
+        None => {
+            let backend = backend::load_backend(backend_type, Some(config_dir))?;
+            backends.push((backend_type.clone(), backend));
+            backends.len() - 1
+        }
+    };
+    Ok(backends[pos].1.as_ref())
+}
+
+// Call add_disk or remove_disk again for every job that isn't paused.
+// The ones that finish are dropped from the list.  Each step holds the job
+// lock so a job can't be paused or cancelled in the middle of one
+fn run_jobs(
+    job_file: &Path,
+    config_dir: &Path,
+    job_lock: &Mutex<()>,
+    backends: &mut Vec<(BackendType, Box<dyn Backend>)>,
+) -> BynarResult<()> {
+    let job_ids: Vec<String> = {
+        let _jobs = lock_jobs(job_lock);
+        load_jobs(job_file)?
+            .into_iter()
+            .filter(|j| !j.paused)
+            .map(|j| j.id)
+            .collect()
+    };
+    for job_id in job_ids {
+        let _jobs = lock_jobs(job_lock);
+        // Paused or cancelled since
+        let mut jobs = load_jobs(job_file)?;
+        let pos = match jobs.iter().position(|j| j.id == job_id && !j.paused) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let job = jobs[pos].clone();
+        debug!("Checking on job {} to {:?} {}", job.id, job.op, job.device);
+        let device = Path::new(&job.device);
+        let outcome = job_backend(backends, &job.backend, config_dir).and_then(|b| match job.op {
+            JobOp::Add => b.add_disk(device, job.osd_id, false),
+            JobOp::Remove => b.remove_disk(device, false),
+        });
+        match outcome {
+            Ok(OpOutcome::InProgress) => {}
+            Ok(outcome) => {
+                info!(
+                    "Job {} to {:?} {} finished: {:?}",
+                    job.id, job.op, job.device, outcome
+                );
+                jobs.remove(pos);
+                save_jobs(job_file, &jobs)?;
+            }
+            Err(e) => {
+                error!(
+                    "Job {} to {:?} {} failed: {:?}.  Trying again later",
+                    job.id, job.op, job.device, e
+                );
+                backends.retain(|(t, _)| t != &job.backend);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Pause, resume or cancel a job.  A cancelled job is left wherever it got to.
+// Pausing only stops disk-manager from taking the next step.  Whatever the
+// backend started keeps going, ie a ceph osd at weight 0 keeps draining
+fn change_job(op: Op, job_id: &str, config_dir: &Path, job_file: &Path) -> BynarResult<()> {
+    let mut jobs = load_jobs(job_file)?;
+    let pos = jobs
+        .iter()
+        .position(|j| j.id == job_id)
+        .ok_or_else(|| BynarError::new(format!("No job with id {}", job_id)))?;
+    match op {
+        Op::PauseJob => {
+            info!("Pausing job {}", job_id);
+            jobs[pos].paused = true;
+        }
+        Op::ResumeJob => {
+            info!("Resuming job {}", job_id);
+            jobs[pos].paused = false;
+        }
+        Op::CancelJob => {
+            info!("Cancelling job {}", job_id);
+            let job = jobs.remove(pos);
+            let backend = backend::load_backend(&job.backend, Some(config_dir))?;
+            backend.cancel_operation(Path::new(&job.device))?;
+        }
+        _ => return Err(BynarError::new(format!("{:?} is not a job operation", op))),
+    }
+    save_jobs(job_file, &jobs)
+}
+
+fn update_job(s: &Socket, op: &Operation, config_dir: &Path, job_file: &Path) -> BynarResult<()> {
+    let mut result = OpResult::new();
+    if !op.has_job_id() {
+        error!("{:?} operation must include job_id field", op.get_Op_type());
+        result.set_result(ResultType::ERR);
+        result.set_error_msg("missing job_id field in protocol. Ignoring request".to_string());
+        return respond_to_client(&result, s);
+    }
+    match change_job(op.get_Op_type(), op.get_job_id(), config_dir, job_file) {
+        Ok(_) => result.set_result(ResultType::OK),
+        Err(e) => {
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(e.to_string());
+        }
+    };
+    respond_to_client(&result, s)
+}
+
+// Returns an OK result with the outcome, whether it's safe, the reason the
+// removal policy refused, the disks that would be removed along with d and
+// the pgs blocking the removal
 fn safe_to_remove(
     d: &Path,
     backend: &BackendType,
     config_dir: &Path,
-) -> BynarResult<(OpOutcome, bool)> {
+) -> BynarResult<OpOutcomeResult> {
     let backend = backend::load_backend(backend, Some(config_dir))?;
-    let safe = backend.safe_to_remove(d, false)?;
+    let mut result = OpOutcomeResult::new();
+    result.set_result(ResultType::OK);
+    result.set_dependents(RepeatedField::from_vec(
+        backend
+            .dependent_disks(d)?
+            .iter()
+            .map(|d| d.to_string_lossy().into_owned())
+            .collect(),
+    ));
+    if let Some(reason) = backend.check_removal_policy(d)? {
+        info!("Removal policy refused {}: {}", d.display(), reason);
+        result.set_outcome(OpOutcome::Success);
+        result.set_value(false);
+        result.set_reason(reason);
+        return Ok(result);
+    }
+    let (outcome, safe) = backend.safe_to_remove(d, false)?;
+    result.set_outcome(outcome);
+    result.set_value(safe);
+    if outcome != OpOutcome::Success || safe {
+        return Ok(result);
+    }
+    // The answer is already no.  Not being able to say why doesn't change it
+    match backend.blocking_pgs(d) {
+        Ok(pgs) => result.set_blocking_pgs(RepeatedField::from_vec(pgs)),
+        Err(e) => {
+            error!(
+                "Finding the pgs blocking removal of {} failed: {}",
+                d.display(),
+                e
+            );
+        }
+    };
 
-    Ok(safe)
+    Ok(result)
 }
 
 fn safe_to_remove_disk(
@@ -528,15 +1053,13 @@ fn safe_to_remove_disk(
     config_dir: &Path,
 ) -> BynarResult<()> {
     debug!("Checking if {} is safe to remove", d);
-    let mut result = OpOutcomeResult::new();
-    match safe_to_remove(&Path::new(d), &backend, &config_dir) {
-        Ok((outcome, val)) => {
-            debug!("Safe to remove: {}", val);
-            result.set_result(ResultType::OK);
-            result.set_value(val);
-            result.set_outcome(outcome);
+    let result = match safe_to_remove(&Path::new(d), &backend, &config_dir) {
+        Ok(result) => {
+            debug!("Safe to remove: {}", result.get_value());
+            result
         }
         Err(e) => {
+            let mut result = OpOutcomeResult::new();
             debug!("Safe to remove err: {}", e);
             result.set_result(ResultType::ERR);
             result.set_error_msg(e.to_string());
@@ -552,6 +1075,202 @@ fn safe_to_remove_disk(
     Ok(())
 }
 
+fn safe_to_add(
+    d: &Path,
+    backend: &BackendType,
+    config_dir: &Path,
+) -> BynarResult<(OpOutcome, bool)> {
+    let backend = backend::load_backend(backend, Some(config_dir))?;
+    let safe = backend.safe_to_add(d, false)?;
+
+    Ok(safe)
+}
+
+fn safe_to_add_disk(
+    s: &Socket,
+    d: &str,
+    backend: &BackendType,
+    config_dir: &Path,
+) -> BynarResult<()> {
+    debug!("Checking if {} is safe to add", d);
+    let mut result = OpOutcomeResult::new();
+    match safe_to_add(&Path::new(d), &backend, &config_dir) {
+        Ok((outcome, val)) => {
+            debug!("Safe to add: {}", val);
+            result.set_result(ResultType::OK);
+            result.set_value(val);
+            result.set_outcome(outcome);
+        }
+        Err(e) => {
+            debug!("Safe to add err: {}", e);
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(e.to_string());
+            let _ = respond_to_client(&result, s);
+            return Err(BynarError::new(format!("safe to add error: {}", e)));
+        }
+    };
+    respond_to_client(&result, s)
+}
+
+fn get_progress(s: &Socket, d: &str, backend: &BackendType, config_dir: &Path) -> BynarResult<()> {
+    let mut result = OpProgressResult::new();
+    let backend = match backend::load_backend(backend, Some(config_dir)) {
+        Ok(b) => b,
+        Err(e) => {
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(e.to_string());
+
+            // Bail early.  We can't load the backend
+            let _ = respond_to_client(&result, s);
+            return Ok(());
+        }
+    };
+    match backend.get_progress(&Path::new(d)) {
+        Ok(progress) => {
+            if let Some(progress) = progress {
+                result.set_progress(progress);
+            }
+            result.set_result(ResultType::OK);
+        }
+        Err(e) => {
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(e.to_string());
+        }
+    };
+    let _ = respond_to_client(&result, s);
+    Ok(())
+}
+
+fn get_backend_info(
+    s: &Socket,
+    op: &Operation,
+    backend_type: &BackendType,
+    config_dir: &Path,
+) -> BynarResult<()> {
+    let mut result = OpBackendInfoResult::new();
+    let backend = match backend::load_backend(backend_type, Some(config_dir)) {
+        Ok(b) => b,
+        Err(e) => {
+            result.set_result(ResultType::ERR);
+            result.set_error_msg(e.to_string());
+
+            // Bail early.  We can't load the backend
+            let _ = respond_to_client(&result, s);
+            return Ok(());
+        }
+    };
+    let mut info = BackendInfo::new();
+    info.set_backend_type(format!("{:?}", backend_type).to_lowercase());
+    if let Some(version) = backend.version() {
+        info.set_version(version);
+    }
+    let capabilities = if op.has_disk() {
+        match backend.device_capabilities(Path::new(op.get_disk())) {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                result.set_result(ResultType::ERR);
+                result.set_error_msg(e.to_string());
+                let _ = respond_to_client(&result, s);
+                return Ok(());
+            }
+        }
+    } else {
+        backend.capabilities()
+    };
+    info.set_capabilities(capabilities);
+    result.set_info(info);
+    result.set_result(ResultType::OK);
+    let _ = respond_to_client(&result, s);
+    Ok(())
+}
+
+// Every backend disk-manager serves, the --backend one first
+fn all_backends(default_backend: &BackendType, routes: &[BackendRoute]) -> Vec<BackendType> {
+    let mut backends = vec![default_backend.clone()];
+    for route in routes {
+        if !backends.contains(&route.backend) {
+            backends.push(route.backend.clone());
+        }
+    }
+    backends
+}
+
+fn host_hygiene(
+    s: &Socket,
+    op: &Operation,
+    backends: &[BackendType],
+    config_dir: &Path,
+) -> BynarResult<()> {
+    let mut result = OpHygieneResult::new();
+    let mut orphans: Vec<String> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for backend_type in backends {
+        let found = backend::load_backend(backend_type, Some(config_dir))
+            .and_then(|backend| backend.host_hygiene(op.get_fix()));
+        match found {
+            Ok(found) => {
+                for orphan in &found {
+                    if op.get_fix() {
+                        info!("Removed orphaned {}", orphan);
+                    } else {
+                        info!("Found orphaned {}", orphan);
+                    }
+                }
+                orphans.extend(found);
+            }
+            Err(e) => {
+                error!(
+                    "Host hygiene of the {:?} backend failed: {:?}",
+                    backend_type, e
+                );
+                errors.push(format!("{:?}: {}", backend_type, e));
+            }
+        }
+    }
+    // The other backends still report what they found
+    if errors.is_empty() {
+        result.set_result(ResultType::OK);
+    } else {
+        result.set_result(ResultType::ERR);
+        result.set_error_msg(errors.join(", "));
+    }
+    result.set_orphans(RepeatedField::from_vec(orphans));
+    let _ = respond_to_client(&result, s);
+    Ok(())
+}
+
+// Suspects are a hint.  A backend that can't say is logged and the rest
+// are still answered
+fn suspect_disks(s: &Socket, backends: &[BackendType], config_dir: &Path) -> BynarResult<()> {
+    let mut result = OpSuspectResult::new();
+    let mut suspects = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for backend_type in backends {
+        let found = backend::load_backend(backend_type, Some(config_dir))
+            .and_then(|backend| backend.suspect_disks());
+        match found {
+            Ok(found) => suspects.extend(found),
+            Err(e) => {
+                error!(
+                    "Suspect disks of the {:?} backend failed: {:?}",
+                    backend_type, e
+                );
+                errors.push(format!("{:?}: {}", backend_type, e));
+            }
+        }
+    }
+    if errors.len() == backends.len() {
+        result.set_result(ResultType::ERR);
+        result.set_error_msg(errors.join(", "));
+    } else {
+        result.set_result(ResultType::OK);
+        result.set_suspects(RepeatedField::from_vec(suspects));
+    }
+    let _ = respond_to_client(&result, s);
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
 pub fn get_jira_tickets(s: &Socket, config_dir: &Path) -> BynarResult<()> {
     let mut result = OpJiraTicketsResult::new();
     let config: ConfigSettings = match helpers::load_config(&config_dir, "bynar.json") {
@@ -599,6 +1318,16 @@ pub fn get_jira_tickets(s: &Socket, config_dir: &Path) -> BynarResult<()> {
     Ok(())
 }
 
+// The tickets are tracked in the bynar database
+#[cfg(not(feature = "postgres"))]
+pub fn get_jira_tickets(s: &Socket, _config_dir: &Path) -> BynarResult<()> {
+    let mut result = OpJiraTicketsResult::new();
+    result.set_result(ResultType::ERR);
+    result.set_error_msg("disk-manager was built without the postgres feature".to_string());
+    let _ = respond_to_client(&result, s);
+    Ok(())
+}
+
 fn main() {
     let matches = App::new("Disk Manager")
         .version(crate_version!())
@@ -609,7 +1338,7 @@ fn main() {
                 .default_value("ceph")
                 .help("Backend cluster type to manage disks for")
                 .long("backend")
-                .possible_values(&["ceph"])
+                .possible_values(&["ceph", "exec", "gluster", "lvm", "mdraid", "scaleio", "zfs"])
                 .takes_value(true)
                 .required(false),
         )
@@ -790,7 +1519,9 @@ fn main() {
     let host_info = h_info.expect("Failed to gather host information");
     match listen(
         &backend,
+        &config.backends,
         config_dir,
+        Path::new(&config.job_file),
         matches.value_of("listen").unwrap(),
         &signals,
         daemon,
@@ -69,6 +69,7 @@ mod tests {
             storage_detail_id: result.storage_detail_id,
             operation_id: None,
             smart_passed: false,
+            suspect: None,
         };
 
         println!("Adding disk {:#?}", d);
@@ -191,6 +192,10 @@ pub struct DiskRepairTicket {
     pub ticket_id: String,
     pub device_name: String,
     pub device_path: String,
+    /// The id the failed disk had in the cluster, if the backend uses one
+    pub osd_id: Option<u64>,
+    /// The disk-manager backend that removed the failed disk
+    pub backend: Option<String>,
 }
 
 #[derive(Debug)]
@@ -1057,6 +1062,8 @@ fn row_to_ticket(row: &Row<'_>) -> DiskRepairTicket {
         ticket_id: row.get(0),
         device_name: row.get(1),
         device_path: row.get(2),
+        osd_id: row.get::<_, Option<i64>>(3).map(|id| id as u64),
+        backend: row.get(4),
     }
 }
 
@@ -1069,7 +1076,7 @@ pub fn get_outstanding_repair_tickets(
     let conn = get_connection_from_pool(pool)?;
 
     // Get all tickets of myself with device.state=WaitingForReplacement and operation_detail.status = pending or in_progress
-    let stmt = "SELECT tracking_id, device_name, device_path FROM operation_details JOIN operations USING (operation_id)
+    let stmt = "SELECT tracking_id, device_name, device_path, osd_id, backend FROM operation_details JOIN operations USING (operation_id)
      JOIN hardware USING (device_id) WHERE 
      (status=$1 OR status=$2) AND 
      type_id = (SELECT type_id FROM operation_types WHERE op_name= $3) AND 
@@ -1110,6 +1117,47 @@ pub fn get_outstanding_repair_tickets(
     }
 }
 
+/// Record the id a disk had in the cluster before it was removed so its
+/// replacement can be added back with the same id
+pub fn save_osd_id(
+    pool: &Pool<ConnectionManager>,
+    operation_id: u32,
+    osd_id: u64,
+) -> BynarResult<()> {
+    let conn = get_connection_from_pool(pool)?;
+    let op_id = operation_id as i32;
+    let id = osd_id as i64;
+    let stmt_query = conn.execute(
+        "UPDATE operations SET osd_id = $1 WHERE operation_id = $2",
+        &[&id, &op_id],
+    )?;
+    info!(
+        "Updated {} rows in database. Operation {} has osd id {}",
+        stmt_query, operation_id, osd_id
+    );
+    Ok(())
+}
+
+/// Record the disk-manager backend that removed a disk so its replacement
+/// is added through the same one
+pub fn save_backend(
+    pool: &Pool<ConnectionManager>,
+    operation_id: u32,
+    backend: &str,
+) -> BynarResult<()> {
+    let conn = get_connection_from_pool(pool)?;
+    let op_id = operation_id as i32;
+    let stmt_query = conn.execute(
+        "UPDATE operations SET backend = $1 WHERE operation_id = $2",
+        &[&backend, &op_id],
+    )?;
+    info!(
+        "Updated {} rows in database. Operation {} has backend {}",
+        stmt_query, operation_id, backend
+    );
+    Ok(())
+}
+
 /// Sets status=Complete for the record that has the given ticket_id.
 /// Equivalent to calling add_or_update_operation_detail() with appropriate fields set
 pub fn resolve_ticket_in_db(pool: &Pool<ConnectionManager>, ticket_id: &str) -> BynarResult<()> {
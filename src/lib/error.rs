@@ -1,11 +1,14 @@
 use blkid::BlkidError;
 use block_utils::BlockUtilsError;
+#[cfg(feature = "ceph")]
 use ceph::error::RadosError;
 use derive_error as de;
+#[cfg(feature = "jira")]
 use goji::Error as GojiError;
 use hashicorp_vault::client::error::Error as VaultError;
 use lvm::LvmError;
 use nix::Error as NixError;
+#[cfg(feature = "postgres")]
 use postgres::Error as PostgresError;
 use protobuf::ProtobufError;
 use pwd::PwdError;
@@ -68,6 +71,7 @@ pub enum BynarError {
     BlockUtilsError(BlockUtilsError),
     #[error(msg_embedded, non_std, no_from)]
     Error(String),
+    #[cfg(feature = "jira")]
     GojiError(GojiError),
     #[error(msg, non_std, no_from)]
     HardwareError(HardwareError),
@@ -75,11 +79,13 @@ pub enum BynarError {
     LvmError(LvmError),
     NixError(NixError),
     ParseIntError(ParseIntError),
+    #[cfg(feature = "postgres")]
     PostgresError(PostgresError),
     ProtobufError(ProtobufError),
     #[error(msg, non_std, no_from)]
     PwdError(PwdBError),
     R2d2Error(R2d2Error),
+    #[cfg(feature = "ceph")]
     #[error(msg, non_std)]
     RadosError(RadosError),
     ReqwestError(ReqwestError),
@@ -6,7 +6,9 @@ use std::path::Path;
 
 use crate::error::{BynarError, BynarResult};
 use api::service::{
-    Disk, JiraInfo, Op, OpJiraTicketsResult, OpOutcome, OpOutcomeResult, Operation, ResultType,
+    BackendInfo, BlockingPg, Disk, JiraInfo, Op, OpBackendInfoResult, OpHygieneResult,
+    OpJiraTicketsResult, OpOutcome, OpOutcomeResult, OpProgressResult, OpResult, OpSuspectResult,
+    Operation, Progress, ResultType, SuspectDisk,
 };
 use hashicorp_vault::client::VaultClient;
 use log::{debug, error};
@@ -55,12 +57,17 @@ pub fn get_vault_token(endpoint: &str, token: &str, hostname: &str) -> BynarResu
     Ok(res)
 }
 
+/// Returns the outcome and, if the add is still running, the id of the job
+/// disk-manager finishes it with
+/// backend picks the disk-manager backend instead of the disk's selectors,
+/// ie the one that removed the disk being replaced
 pub fn add_disk_request(
     s: &Socket,
     path: &Path,
     id: Option<u64>,
+    backend: Option<&str>,
     simulate: bool,
-) -> BynarResult<OpOutcome> {
+) -> BynarResult<(OpOutcome, Option<String>)> {
     let mut o = Operation::new();
     debug!("Creating add disk operation request");
     o.set_Op_type(Op::Add);
@@ -69,6 +76,9 @@ pub fn add_disk_request(
     if let Some(id) = id {
         o.set_osd_id(id);
     }
+    if let Some(backend) = backend {
+        o.set_backend(backend.to_string());
+    }
 
     let encoded = o.write_to_bytes().unwrap();
     debug!("Sending message");
@@ -79,7 +89,7 @@ pub fn add_disk_request(
     debug!("Decoding msg len: {}", add_response.len());
     let op_result = parse_from_bytes::<api::service::OpOutcomeResult>(&add_response)?;
     match op_result.get_result() {
-        ResultType::OK => Ok(op_result.get_outcome()),
+        ResultType::OK => Ok((op_result.get_outcome(), job_id(&op_result))),
         ResultType::ERR => {
             if op_result.has_error_msg() {
                 let msg = op_result.get_error_msg();
@@ -139,7 +149,10 @@ pub fn list_disks_request(s: &Socket) -> BynarResult<Vec<Disk>> {
     Ok(d)
 }
 
-pub fn safe_to_remove_request(s: &Socket, path: &Path) -> BynarResult<(OpOutcome, bool)> {
+/// Returns the outcome, whether the disk is safe to remove, the reason
+/// if the backend's removal policy refused, the other disks a removal
+/// would take out with it and the pgs blocking the removal
+pub fn safe_to_remove_request(s: &Socket, path: &Path) -> BynarResult<OpOutcomeResult> {
     let mut o = Operation::new();
     debug!("Creating safe to remove operation request");
     o.set_Op_type(Op::SafeToRemove);
@@ -148,6 +161,54 @@ pub fn safe_to_remove_request(s: &Socket, path: &Path) -> BynarResult<(OpOutcome
     debug!("Sending message");
     s.send(&encoded, 0)?;
 
+    debug!("Waiting for response");
+    let safe_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", safe_response.len());
+    let op_result = parse_from_bytes::<OpOutcomeResult>(&safe_response)?;
+    match op_result.get_result() {
+        ResultType::OK => Ok(op_result),
+        ResultType::ERR => Err(BynarError::from(op_result.get_error_msg())),
+    }
+}
+
+/// One line about a pg that blocks a removal, for tickets and notifications
+pub fn describe_blocking_pg(pg: &BlockingPg) -> String {
+    let osds: Vec<String> = pg
+        .get_osds()
+        .iter()
+        .map(|id| format!("osd.{}", id))
+        .collect();
+    format!(
+        "pg {} in pool {} ({}) would be left on {} of min_size {}: {}",
+        pg.get_pgid(),
+        pg.get_pool(),
+        pg.get_state(),
+        osds.len(),
+        pg.get_min_size(),
+        if osds.is_empty() {
+            "none".to_string()
+        } else {
+            osds.join(", ")
+        }
+    )
+}
+
+pub fn safe_to_add_request(
+    s: &Socket,
+    path: &Path,
+    backend: Option<&str>,
+) -> BynarResult<(OpOutcome, bool)> {
+    let mut o = Operation::new();
+    debug!("Creating safe to add operation request");
+    o.set_Op_type(Op::SafeToAdd);
+    o.set_disk(format!("{}", path.display()));
+    if let Some(backend) = backend {
+        o.set_backend(backend.to_string());
+    }
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
     debug!("Waiting for response");
     let safe_response = s.recv_bytes(0)?;
     debug!("Decoding msg len: {}", safe_response.len());
@@ -158,12 +219,123 @@ pub fn safe_to_remove_request(s: &Socket, path: &Path) -> BynarResult<(OpOutcome
     }
 }
 
+pub fn get_progress_request(s: &Socket, path: &Path) -> BynarResult<Option<Progress>> {
+    let mut o = Operation::new();
+    debug!("Creating get progress operation request");
+    o.set_Op_type(Op::GetProgress);
+    o.set_disk(format!("{}", path.display()));
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
+    debug!("Waiting for response");
+    let progress_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", progress_response.len());
+    let op_result = parse_from_bytes::<OpProgressResult>(&progress_response)?;
+    match op_result.get_result() {
+        ResultType::OK => {
+            if op_result.has_progress() {
+                Ok(Some(op_result.get_progress().clone()))
+            } else {
+                Ok(None)
+            }
+        }
+        ResultType::ERR => {
+            error!("Get progress failed: {}", op_result.get_error_msg());
+            Err(BynarError::from(op_result.get_error_msg()))
+        }
+    }
+}
+
+pub fn get_backend_info_request(s: &Socket, path: Option<&Path>) -> BynarResult<BackendInfo> {
+    let mut o = Operation::new();
+    debug!("Creating get backend info operation request");
+    o.set_Op_type(Op::GetBackendInfo);
+    // disk-manager can run more than one backend.  The disk picks which one
+    if let Some(path) = path {
+        o.set_disk(format!("{}", path.display()));
+    }
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
+    debug!("Waiting for response");
+    let info_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", info_response.len());
+    let op_result = parse_from_bytes::<OpBackendInfoResult>(&info_response)?;
+    match op_result.get_result() {
+        ResultType::OK => Ok(op_result.get_info().clone()),
+        ResultType::ERR => {
+            error!("Get backend info failed: {}", op_result.get_error_msg());
+            Err(BynarError::from(op_result.get_error_msg()))
+        }
+    }
+}
+
+/// Returns what earlier adds left on the host that nothing uses anymore.
+/// With fix set disk-manager removes them too
+pub fn host_hygiene_request(
+    s: &Socket,
+    path: Option<&Path>,
+    fix: bool,
+) -> BynarResult<Vec<String>> {
+    let mut o = Operation::new();
+    debug!("Creating host hygiene operation request");
+    o.set_Op_type(Op::HostHygiene);
+    // disk-manager can run more than one backend.  The disk picks which one
+    if let Some(path) = path {
+        o.set_disk(format!("{}", path.display()));
+    }
+    o.set_fix(fix);
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
+    debug!("Waiting for response");
+    let hygiene_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", hygiene_response.len());
+    let op_result = parse_from_bytes::<OpHygieneResult>(&hygiene_response)?;
+    match op_result.get_result() {
+        ResultType::OK => Ok(op_result.get_orphans().to_vec()),
+        ResultType::ERR => {
+            error!("Host hygiene failed: {}", op_result.get_error_msg());
+            Err(BynarError::from(op_result.get_error_msg()))
+        }
+    }
+}
+
+/// Returns the disks the backend sees failing before SMART does, ie a ceph
+/// osd that keeps going down
+pub fn suspect_disks_request(s: &Socket) -> BynarResult<Vec<SuspectDisk>> {
+    let mut o = Operation::new();
+    debug!("Creating suspect disks operation request");
+    o.set_Op_type(Op::SuspectDisks);
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
+    debug!("Waiting for response");
+    let suspect_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", suspect_response.len());
+    let op_result = parse_from_bytes::<OpSuspectResult>(&suspect_response)?;
+    match op_result.get_result() {
+        ResultType::OK => Ok(op_result.get_suspects().to_vec()),
+        ResultType::ERR => {
+            error!("Suspect disks failed: {}", op_result.get_error_msg());
+            Err(BynarError::from(op_result.get_error_msg()))
+        }
+    }
+}
+
+/// Returns the outcome, the id the disk had in the cluster if the backend
+/// uses one and the job id if the removal is still running.  Pass the disk
+/// id to add_disk_request for the replacement disk
 pub fn remove_disk_request(
     s: &Socket,
     path: &Path,
     id: Option<u64>,
     simulate: bool,
-) -> BynarResult<OpOutcome> {
+) -> BynarResult<(OpOutcome, Option<u64>, Option<String>)> {
     let mut o = Operation::new();
     debug!("Creating remove operation request");
     o.set_Op_type(Op::Remove);
@@ -188,7 +360,14 @@ pub fn remove_disk_request(
         Ok(o) => o,
     };
     match op_result.get_result() {
-        ResultType::OK => Ok(op_result.get_outcome()),
+        ResultType::OK => {
+            let osd_id = if op_result.has_osd_id() {
+                Some(op_result.get_osd_id())
+            } else {
+                None
+            };
+            Ok((op_result.get_outcome(), osd_id, job_id(&op_result)))
+        }
         ResultType::ERR => {
             if op_result.has_error_msg() {
                 let msg = op_result.get_error_msg();
@@ -202,6 +381,38 @@ pub fn remove_disk_request(
     }
 }
 
+fn job_id(op_result: &OpOutcomeResult) -> Option<String> {
+    if op_result.has_job_id() {
+        Some(op_result.get_job_id().to_string())
+    } else {
+        None
+    }
+}
+
+/// Pause, resume or cancel an add or remove that's still running.
+/// op is one of PauseJob, ResumeJob or CancelJob
+pub fn job_request(s: &Socket, op: Op, job_id: &str) -> BynarResult<()> {
+    let mut o = Operation::new();
+    debug!("Creating {:?} operation request", op);
+    o.set_Op_type(op);
+    o.set_job_id(job_id.to_string());
+    let encoded = o.write_to_bytes()?;
+    debug!("Sending message");
+    s.send(&encoded, 0)?;
+
+    debug!("Waiting for response");
+    let job_response = s.recv_bytes(0)?;
+    debug!("Decoding msg len: {}", job_response.len());
+    let op_result = parse_from_bytes::<OpResult>(&job_response)?;
+    match op_result.get_result() {
+        ResultType::OK => Ok(()),
+        ResultType::ERR => {
+            error!("{:?} failed: {}", op, op_result.get_error_msg());
+            Err(BynarError::from(op_result.get_error_msg()))
+        }
+    }
+}
+
 // default filename for daemon_output
 fn default_out() -> String {
     "bynar_daemon.out".to_string()
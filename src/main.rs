@@ -39,6 +39,7 @@ use signal_hook::iterator::Signals;
 use signal_hook::*;
 use simplelog::{CombinedLogger, Config, SharedLogger, TermLogger, WriteLogger};
 use slack_hook::{PayloadBuilder, Slack};
+use std::collections::BTreeMap;
 use std::fs::{create_dir, read_to_string, File, OpenOptions};
 use std::path::{Path, PathBuf};
 use std::process;
@@ -177,8 +178,27 @@ fn check_for_failed_disks(
         host_info.kernel,
     ));
 
+    // Disks the storage backend sees failing before SMART does, ie a flapping
+    // ceph osd.  The checks go ahead without them if disk-manager can't say
+    let suspects: BTreeMap<PathBuf, String> = match helpers::connect(
+        &config.manager_host,
+        &config.manager_port.to_string(),
+        &public_key,
+    )
+    .and_then(|socket| helpers::suspect_disks_request(&socket))
+    {
+        Ok(suspects) => suspects
+            .into_iter()
+            .map(|s| (PathBuf::from(s.get_disk_path()), s.get_reason().to_string()))
+            .collect(),
+        Err(e) => {
+            warn!("Unable to get the suspect disks from disk-manager: {:?}", e);
+            BTreeMap::new()
+        }
+    };
+
     info!("Checking all drives");
-    for result in test_disk::check_all_disks(&host_info, pool, host_mapping)? {
+    for result in test_disk::check_all_disks(&host_info, pool, host_mapping, &suspects)? {
         match result {
             Ok(state_machine) => {
                 info!(
@@ -191,6 +211,9 @@ fn check_for_failed_disks(
 
                 if state_machine.block_device.state == State::WaitingForReplacement {
                     add_disk_to_description(&mut description, &dev_path, &state_machine);
+                    if let Some(ref reason) = state_machine.block_device.suspect {
+                        description.push_str(&format!("\nSuspected because: {}", reason));
+                    }
                     trace!("Description: {}", description);
                     info!("Connecting to database to check if disk is in progress");
                     let in_progress = in_progress::is_hardware_waiting_repair(
@@ -211,12 +234,38 @@ fn check_for_failed_disks(
                                 &config.manager_port.to_string(),
                                 &public_key,
                             )?;
+                            let safe_to_remove =
+                                helpers::safe_to_remove_request(&socket, &dev_path);
+                            if let Ok(ref safe) = safe_to_remove {
+                                let dependents = safe.get_dependents();
+                                let blocking_pgs = safe.get_blocking_pgs();
+                                // ie the osds on a journal device.  They're
+                                // removed with it and replaced under this ticket
+                                if !dependents.is_empty() {
+                                    description.push_str(&format!(
+                                        "\nDisks removed along with it: {}",
+                                        dependents.join(", ")
+                                    ));
+                                }
+                                if !blocking_pgs.is_empty() {
+                                    description
+                                        .push_str("\nPlacement groups blocking the removal:");
+                                    for pg in blocking_pgs {
+                                        description.push_str(&format!(
+                                            "\n{}",
+                                            helpers::describe_blocking_pg(pg)
+                                        ));
+                                    }
+                                }
+                            }
                             match (
-                                helpers::safe_to_remove_request(&socket, &dev_path),
+                                safe_to_remove
+                                    .map(|safe| (safe.get_outcome(), safe.get_value(), safe)),
                                 config.slack_webhook.is_some(),
                             ) {
-                                (Ok((OpOutcome::Success, true)), true) => {
-                                    debug!("safe to remove: true");
+                                (Ok((OpOutcome::Success, true, _)), true)
+                                | (Ok((OpOutcome::InProgress, _, _)), true) => {
+                                    debug!("safe to remove: true or after a drain");
                                     //Ok to remove the disk
                                     let _ = notify_slack(
                                         config,
@@ -230,29 +279,88 @@ fn check_for_failed_disks(
                                     match helpers::remove_disk_request(
                                         &socket, &dev_path, None, false,
                                     ) {
-                                        Ok(outcome) => match outcome {
-                                            OpOutcome::Success => debug!("Disk removal successful"),
-                                            OpOutcome::Skipped => {
-                                                debug!("Disk skipped, disk is not removable")
+                                        Ok((outcome, disk_id, _)) => {
+                                            match outcome {
+                                                OpOutcome::Success => {
+                                                    debug!("Disk removal successful")
+                                                }
+                                                OpOutcome::Skipped => {
+                                                    debug!("Disk skipped, disk is not removable")
+                                                }
+                                                OpOutcome::SkipRepeat => {
+                                                    debug!("Disk already removed, skipping.")
+                                                }
+                                                OpOutcome::InProgress => debug!(
+                                                    "Disk removal started.  disk-manager will finish it"
+                                                ),
                                             }
-                                            OpOutcome::SkipRepeat => {
-                                                debug!("Disk already removed, skipping.")
+                                            // Remember the id so the replacement disk gets it back
+                                            if let (Some(id), Some(op_id)) =
+                                                (disk_id, state_machine.block_device.operation_id)
+                                            {
+                                                if let Err(e) =
+                                                    in_progress::save_osd_id(pool, op_id, id)
+                                                {
+                                                    error!(
+                                                        "Failed to record osd id {}: {:?}",
+                                                        id, e
+                                                    );
+                                                }
                                             }
-                                        },
+                                            // The replacement disk won't match a serial or
+                                            // WWN selector.  Add it through the same backend
+                                            let removed = outcome == OpOutcome::Success
+                                                || outcome == OpOutcome::InProgress;
+                                            if let (true, Some(op_id)) =
+                                                (removed, state_machine.block_device.operation_id)
+                                            {
+                                                match helpers::get_backend_info_request(
+                                                    &socket,
+                                                    Some(&dev_path),
+                                                )
+                                                .and_then(|info| {
+                                                    in_progress::save_backend(
+                                                        pool,
+                                                        op_id,
+                                                        info.get_backend_type(),
+                                                    )
+                                                }) {
+                                                    Ok(_) => {}
+                                                    Err(e) => error!(
+                                                        "Failed to record the backend of {}: {:?}",
+                                                        dev_path.display(),
+                                                        e
+                                                    ),
+                                                };
+                                            }
+                                        }
                                         Err(e) => {
                                             error!("Disk removal failed: {}", e);
                                         }
                                     };
                                 }
-                                (Ok((_, false)), true) => {
+                                (Ok((_, false, safe)), true) => {
                                     debug!("safe to remove: false");
+                                    let reason = if safe.has_reason() {
+                                        Some(safe.get_reason().to_string())
+                                    } else if !safe.get_blocking_pgs().is_empty() {
+                                        Some(format!(
+                                            "{} pgs would go below min_size",
+                                            safe.get_blocking_pgs().len()
+                                        ))
+                                    } else {
+                                        None
+                                    };
                                     let _ = notify_slack(
                                         config,
                                         &format!(
                                             "Need to remove disk {} but it's not safe \
-                                             on host: {}. I need a human.  Filing a ticket",
+                                             on host: {}{}. I need a human.  Filing a ticket",
                                             dev_path.display(),
                                             host_info.hostname,
+                                            reason
+                                                .map(|r| format!(" because {}", r))
+                                                .unwrap_or_default(),
                                         ),
                                     );
                                 }
@@ -432,23 +540,75 @@ fn add_repaired_disks(
                     &public_key,
                 )?;
 
+                // The ticket stays outstanding so the add is retried next time around
+                let backend = ticket.backend.as_ref().map(|b| b.as_str());
+                match helpers::safe_to_add_request(
+                    &socket,
+                    &Path::new(&ticket.device_path),
+                    backend,
+                ) {
+                    Ok((OpOutcome::Success, false)) => {
+                        info!("Not safe to add {} yet.  Deferring", ticket.device_path);
+                        continue;
+                    }
+                    Ok(_) => debug!("safe to add check passed"),
+                    Err(e) => {
+                        error!(
+                            "Unable to tell if it's safe to add {}.  Deferring. {:?}",
+                            ticket.device_path, e
+                        );
+                        continue;
+                    }
+                };
+                if simulate {
+                    // A backend that can't simulate would really add the disk
+                    let device_path = Path::new(&ticket.device_path);
+                    match helpers::get_backend_info_request(&socket, Some(device_path)) {
+                        Ok(ref info) if !info.get_capabilities().get_simulate() => {
+                            info!(
+                                "The {} backend can't simulate adding {}.  Skipping",
+                                info.get_backend_type(),
+                                ticket.device_path
+                            );
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            error!("Unable to get backend info.  Skipping. {:?}", e);
+                            continue;
+                        }
+                    };
+                }
+
+                // Reuse the id the failed disk had so data only moves once
                 match helpers::add_disk_request(
                     &socket,
                     &Path::new(&ticket.device_path),
-                    None,
+                    ticket.osd_id,
+                    backend,
                     simulate,
                 ) {
-                    Ok(outcome) => {
+                    Ok((outcome, _)) => {
                         match outcome {
                             OpOutcome::Success => {
                                 debug!("Disk added successfully. Updating database record")
                             }
-                            // Disk was either boot or something that shouldn't be added via backend
-                            OpOutcome::Skipped => debug!("Disk Skipped.  Updating database record"),
+                            // Disk was either boot or something no backend manages.  Nothing
+                            // was added so the ticket stays open for a human
+                            OpOutcome::Skipped => {
+                                warn!(
+                                    "No backend added {}.  Leaving ticket {} open",
+                                    ticket.device_path, ticket.ticket_id
+                                );
+                                continue;
+                            }
                             // Disk is already in the cluster
                             OpOutcome::SkipRepeat => {
                                 debug!("Disk already added.  Skipping.  Updating database record")
                             }
+                            OpOutcome::InProgress => {
+                                debug!("Disk add started.  Updating database record")
+                            }
                         }
                         match in_progress::resolve_ticket_in_db(pool, &ticket.ticket_id) {
                             Ok(_) => debug!("Database updated"),
@@ -57,6 +57,10 @@ pub struct BlockDevice {
     pub storage_detail_id: u32,
     pub operation_id: Option<u32>,
     pub smart_passed: bool,
+    // Why the storage backend thinks the disk is failing, ie its ceph osd
+    // keeps going down.  A suspect disk gets the full evaluation even if
+    // SMART passes
+    pub suspect: Option<String>,
 }
 
 impl BlockDevice {
@@ -176,6 +180,7 @@ mod tests {
             storage_detail_id: 1,
             operation_id: None,
             smart_passed: false,
+            suspect: None,
         };
         let mut s = super::StateMachine::new(d, None, true);
         s.setup_state_machine();
@@ -232,6 +237,7 @@ mod tests {
             storage_detail_id: 1,
             operation_id: None,
             smart_passed: false,
+            suspect: None,
         };
         let mut s = super::StateMachine::new(d, None, true);
         s.setup_state_machine();
@@ -287,6 +293,7 @@ mod tests {
             storage_detail_id: 1,
             operation_id: None,
             smart_passed: false,
+            suspect: None,
         };
         let mut s = super::StateMachine::new(d, None, false);
         s.setup_state_machine();
@@ -333,6 +340,7 @@ mod tests {
             storage_detail_id: 1,
             operation_id: None,
             smart_passed: true,
+            suspect: None,
         };
         // restore state?
         let mut s = super::StateMachine::new(d, None, true);
@@ -777,13 +785,19 @@ impl Transition for Scan {
                     device.smart_passed = stat;
                     // If the device is a Disk, then end the state machine here.
                     if device.device.device_type == DeviceType::Disk {
-                        if stat {
-                            debug!("Disk is healthy");
-                            return State::Good;
-                        } else {
+                        if !stat {
                             debug!("Disk Health Scan Failed");
                             return State::Fail;
                         }
+                        match device.suspect {
+                            Some(ref reason) => {
+                                debug!("SMART passed but {}.  Evaluating further", reason);
+                            }
+                            None => {
+                                debug!("Disk is healthy");
+                                return State::Good;
+                            }
+                        }
                     }
                     to_state
                 }
@@ -1250,6 +1264,7 @@ fn filter_disks(devices: &[PathBuf], storage_detail_id: u32) -> BynarResult<Vec<
                 storage_detail_id,
                 operation_id: None,
                 smart_passed: false,
+                suspect: None,
             }
         })
         .collect();
@@ -1359,6 +1374,7 @@ fn add_previous_devices(
                     storage_detail_id: host_mapping.storage_detail_id,
                     operation_id: None,
                     smart_passed: false,
+                    suspect: None,
                 };
                 save_state(pool, &b, State::WaitingForReplacement)?;
                 devices.push(b);
@@ -1372,10 +1388,13 @@ fn add_previous_devices(
 /// Retrives a list of disks, and sets up a state machine on each of them.
 /// Retrives previous state and runs through the state machine and preserves
 /// the final state in the database before returning a vector of StateMachine
+/// suspects are disks the storage backend thinks are failing and why.  They're
+/// evaluated again from the start whatever state they were left in
 pub fn check_all_disks(
     host_info: &Host,
     pool: &Pool<ConnectionManager>,
     host_mapping: &HostDetailsMapping,
+    suspects: &BTreeMap<PathBuf, String>,
 ) -> BynarResult<Vec<BynarResult<StateMachine>>> {
     // Udev will only show the disks that are currently attached to the tree
     // It will fail to show disks that have died and disconnected but are still
@@ -1446,10 +1465,22 @@ pub fn check_all_disks(
         if let Some((i, opt)) = scsi_info.clone() {
             device.scsi_info = i;
         }
+        device.suspect = suspects.get(&device.dev_path).cloned();
         debug!("thread {} device: {:?}", process::id(), device);
         let mut s = StateMachine::new(device, scsi_info, false);
         s.setup_state_machine();
         s.block_device.state = get_state(pool, &s.block_device)?;
+        // A disk already waiting for replacement stays there
+        if let Some(ref reason) = s.block_device.suspect {
+            if s.block_device.state != State::WaitingForReplacement {
+                warn!(
+                    "Evaluating {} again: {}",
+                    s.block_device.dev_path.display(),
+                    reason
+                );
+                s.block_device.state = State::Unscanned;
+            }
+        }
         s.run();
         // Save the state to database after state machine finishes its run
         save_state(pool, &s.block_device, s.block_device.state)?;
@@ -1,10 +1,14 @@
 use crate::ConfigSettings;
-use helpers::{error::BynarError, error::BynarResult, error::HardwareError};
+use helpers::error::BynarResult;
+#[cfg(feature = "redfish")]
+use helpers::error::{BynarError, HardwareError};
+#[cfg(feature = "redfish")]
 use libredfish::{
     common::Status, manager::Manager, power::Power, storage::ArrayController, storage::DiskDrive,
     storage::Hardware, storage::StorageEnclosure, thermal::Thermal, *,
 };
 use log::debug;
+#[cfg(feature = "redfish")]
 use reqwest::Client;
 
 /// Summary of all the hardware status information
@@ -21,6 +25,7 @@ pub struct HardwareHealthSummary {
     pub thermals: Vec<BynarResult<()>>,
 }
 
+#[cfg(feature = "redfish")]
 fn collect_redfish_info(config: &ConfigSettings) -> BynarResult<HardwareHealthSummary> {
     let client = Client::builder()
         .danger_accept_invalid_certs(true)
@@ -91,10 +96,26 @@ fn collect_redfish_info(config: &ConfigSettings) -> BynarResult<HardwareHealthSu
     })
 }
 
+#[cfg(feature = "redfish")]
 pub fn check_hardware(config: &ConfigSettings) -> BynarResult<HardwareHealthSummary> {
     collect_redfish_info(&config)
 }
 
+/// Without redfish there's nothing to check so everything comes back empty
+#[cfg(not(feature = "redfish"))]
+pub fn check_hardware(_config: &ConfigSettings) -> BynarResult<HardwareHealthSummary> {
+    debug!("Bynar was built without redfish support.  Skipping hardware checks");
+    Ok(HardwareHealthSummary {
+        array_controllers: vec![],
+        disk_drives: vec![],
+        manager: vec![],
+        power: vec![],
+        storage_enclosures: vec![],
+        thermals: vec![],
+    })
+}
+
+#[cfg(feature = "redfish")]
 fn evaluate_storage<T>(hardware: T) -> BynarResult<()>
 where
     T: Hardware + Status,
@@ -111,6 +132,7 @@ where
     Ok(())
 }
 
+#[cfg(feature = "redfish")]
 fn evaluate_manager(manager: &Manager) -> Vec<BynarResult<()>> {
     // Look through all the self test results
     // Check if this is an HP machine first?
@@ -127,6 +149,7 @@ fn evaluate_manager(manager: &Manager) -> Vec<BynarResult<()>> {
     results
 }
 
+#[cfg(feature = "redfish")]
 fn evaluate_power(power: &Power) -> Vec<BynarResult<()>> {
     let mut results: Vec<BynarResult<()>> = Vec::new();
 
@@ -141,6 +164,7 @@ fn evaluate_power(power: &Power) -> Vec<BynarResult<()>> {
     results
 }
 
+#[cfg(feature = "redfish")]
 fn evaluate_thermals(thermal: &Thermal) -> Vec<BynarResult<()>> {
     let mut results: Vec<BynarResult<()>> = Vec::new();
     for fan in &thermal.fans {